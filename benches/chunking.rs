@@ -0,0 +1,65 @@
+//
+// Copyright (c) 2023 Florian Gäbler
+//
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use fastcdc_alt::FastCDC;
+
+fn pseudo_random_bytes(seed: u64, length: usize) -> Vec<u8> {
+    let mut state = seed;
+    (0..length).map(|_| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state >> 32) as u8
+    }).collect()
+}
+
+/// Chunk the whole buffer at once, which uses the contiguous hot loop.
+fn chunk_contiguous(chunker: &mut FastCDC, contents: &[u8]) -> usize {
+    chunker.as_iterator(contents).count()
+}
+
+/// Chunk the buffer in small pieces, so the chunks always span several calls to `cut()`.
+fn chunk_streamed(chunker: &mut FastCDC, contents: &[u8], piece: usize) -> usize {
+    chunker.set_content_length(contents.len());
+    let mut count = 0;
+    let mut cursor = 0;
+    while cursor < contents.len() {
+        let end = (cursor + piece).min(contents.len());
+        match chunker.cut(&contents[cursor..end]) {
+            Some(chunk) => {
+                cursor += chunk.cutpoint;
+                count += 1;
+            }
+            None => cursor = end,
+        }
+    }
+    count
+}
+
+fn bench_cut(c: &mut Criterion) {
+    let contents = pseudo_random_bytes(0x2545f4914f6cdd1d, 16 * 1024 * 1024);
+    let mut group = c.benchmark_group("cut");
+    group.throughput(Throughput::Bytes(contents.len() as u64));
+
+    for (min_size, avg_size, max_size) in [(2048, 8192, 65536), (16384, 65536, 262144)] {
+        let mut chunker = FastCDC::new(min_size, avg_size, max_size).unwrap();
+        group.bench_with_input(BenchmarkId::new("contiguous", avg_size), &contents, |b, contents| {
+            b.iter(|| chunk_contiguous(&mut chunker, contents))
+        });
+        // Baseline for the contiguous hot loop: the buffers end just before the maximum chunk
+        // size, so nearly all bytes are hashed by the loop keeping its state in the context.
+        group.bench_with_input(BenchmarkId::new("baseline", avg_size), &contents, |b, contents| {
+            b.iter(|| chunk_streamed(&mut chunker, contents, max_size as usize - 1))
+        });
+        group.bench_with_input(BenchmarkId::new("streamed", avg_size), &contents, |b, contents| {
+            b.iter(|| chunk_streamed(&mut chunker, contents, 4096))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_cut);
+criterion_main!(benches);
@@ -0,0 +1,25 @@
+//
+// Copyright (c) 2023 Florian Gäbler
+//
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // Generate the C header for the bindings of the capi feature. The build writes only
+    // to OUT_DIR, CI checks that the committed header in include/ is up to date.
+    #[cfg(feature = "capi")]
+    {
+        let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        let out_dir = std::env::var("OUT_DIR").unwrap();
+        println!("cargo:rerun-if-changed=src/capi.rs");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+
+        let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir)).unwrap();
+        cbindgen::Builder::new()
+            .with_src(format!("{}/src/capi.rs", crate_dir))
+            .with_config(config)
+            .generate()
+            .expect("unable to generate the C header")
+            .write_to_file(format!("{}/fastcdc_alt.h", out_dir));
+    }
+}
@@ -0,0 +1,159 @@
+#ifndef FASTCDC_ALT_H
+#define FASTCDC_ALT_H
+
+/* Generated by cbindgen from src/capi.rs with the capi feature, do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Opaque chunker created by fastcdc_new() and released by fastcdc_free().
+ */
+typedef struct FastCDC FastCDC;
+
+/**
+ *
+ * Result of validating a chunker configuration.
+ *
+ */
+typedef enum FastCDCStatus {
+  /**
+   * The configuration is valid.
+   */
+  FAST_CDC_STATUS_OK = 0,
+  /**
+   * The minimum size is below `MINIMUM_MIN`.
+   */
+  FAST_CDC_STATUS_MINIMUM_TOO_LOW,
+  /**
+   * The minimum size is above `MINIMUM_MAX`.
+   */
+  FAST_CDC_STATUS_MINIMUM_TOO_HIGH,
+  /**
+   * The average size is below `AVERAGE_MIN`.
+   */
+  FAST_CDC_STATUS_AVERAGE_TOO_LOW,
+  /**
+   * The average size is above `AVERAGE_MAX`.
+   */
+  FAST_CDC_STATUS_AVERAGE_TOO_HIGH,
+  /**
+   * The maximum size is below `MAXIMUM_MIN`.
+   */
+  FAST_CDC_STATUS_MAXIMUM_TOO_LOW,
+  /**
+   * The maximum size is above `MAXIMUM_MAX`.
+   */
+  FAST_CDC_STATUS_MAXIMUM_TOO_HIGH,
+  /**
+   * The normalization level is not between 0 and 3.
+   */
+  FAST_CDC_STATUS_INVALID_LEVEL,
+  /**
+   * A required pointer is null.
+   */
+  FAST_CDC_STATUS_NULL_POINTER,
+} FastCDCStatus;
+
+/**
+ *
+ * A chunk identified by `fastcdc_cut()`, with the fields of [Chunk].
+ *
+ */
+typedef struct FastCDCChunk {
+  /**
+   * The gear hash value as of the end of the chunk.
+   */
+  uint64_t hash;
+  /**
+   * The offset of the chunk start relative to the passed buffer, zero or negative.
+   */
+  intptr_t offset;
+  /**
+   * The cut point relative to the passed buffer, i.e. the chunk ends before this byte.
+   */
+  uintptr_t cutpoint;
+  /**
+   * Whether the chunk consists only of zero bytes.
+   */
+  bool zero;
+} FastCDCChunk;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ *
+ * Check the chunk sizes and normalization level against the limits of [FastCDC::new_advanced].
+ *
+ */
+enum FastCDCStatus fastcdc_validate(uint32_t min_size,
+                                    uint32_t avg_size,
+                                    uint32_t max_size,
+                                    uint32_t level);
+
+/**
+ *
+ * Create a chunker with the given chunk sizes and normalization level and store it
+ * in `chunker`, which is left untouched if the configuration is invalid.\
+ * The content length is zero until set with `fastcdc_set_content_length()`.
+ *
+ * # Safety
+ *
+ * `chunker` must be null or valid for writes.
+ *
+ */
+enum FastCDCStatus fastcdc_new(uint32_t min_size,
+                               uint32_t avg_size,
+                               uint32_t max_size,
+                               uint32_t level,
+                               FastCDC **chunker);
+
+/**
+ *
+ * Release a chunker created by `fastcdc_new()`. Passing null does nothing.
+ *
+ * # Safety
+ *
+ * `chunker` must be null or a chunker created by `fastcdc_new()` that was not released yet.
+ *
+ */
+void fastcdc_free(FastCDC *chunker);
+
+/**
+ *
+ * Set the length of the content to chunk and reset the chunker, see [FastCDC::set_content_length].
+ *
+ * # Safety
+ *
+ * `chunker` must be null or a valid chunker created by `fastcdc_new()`.
+ *
+ */
+enum FastCDCStatus fastcdc_set_content_length(FastCDC *chunker, uintptr_t length);
+
+/**
+ *
+ * Try to identify the next cut point in the buffer, see [FastCDC::cut].\
+ * Returns `true` and stores the chunk in `chunk` if a chunk has been identified,
+ * and `false` if more data or a new content length is needed.
+ *
+ * # Safety
+ *
+ * `chunker` must be a valid chunker created by `fastcdc_new()`, `data` must be
+ * valid for reads of `length` bytes, or may be null if `length` is zero, and
+ * `chunk` must be valid for writes.
+ *
+ */
+bool fastcdc_cut(FastCDC *chunker,
+                 const uint8_t *data,
+                 uintptr_t length,
+                 struct FastCDCChunk *chunk);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* FASTCDC_ALT_H */
@@ -0,0 +1,497 @@
+//
+// Copyright (c) 2023 Florian Gäbler
+//
+
+//! The `bench` subcommand, measuring the chunking throughput of `FastCDC`,
+//! `StreamCDC` and `AsyncStreamCDC` on generated inputs.
+
+use crate::chunk::{csv_field, json_string, Format};
+use crate::{parse_params, parse_size, CliResult, Config};
+use clap::builder::PossibleValuesParser;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use fastcdc_alt::FastCDC;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+///
+/// Global allocator counting the allocations and allocated bytes of the process,
+/// reallocations included.
+///
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count(layout.size());
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count(new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+fn count(size: usize) {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    ALLOCATED_BYTES.fetch_add(size as u64, Ordering::Relaxed);
+}
+
+fn allocations() -> (u64, u64) {
+    (ALLOCATIONS.load(Ordering::Relaxed), ALLOCATED_BYTES.load(Ordering::Relaxed))
+}
+
+///
+/// The chunker API to measure.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    /// [FastCDC::cut] over the whole input in memory.
+    Cut,
+    /// `StreamCDC` reading the input from a slice.
+    Stream,
+    /// `AsyncStreamCDC` reading the input from a cursor, requires the `futures` feature.
+    #[cfg(all(feature = "futures", not(feature = "tokio")))]
+    Async,
+}
+
+impl Engine {
+    #[cfg(all(feature = "futures", not(feature = "tokio")))]
+    pub const NAMES: &'static [&'static str] = &["cut", "stream", "async"];
+    #[cfg(not(all(feature = "futures", not(feature = "tokio"))))]
+    pub const NAMES: &'static [&'static str] = &["cut", "stream"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "cut" => Some(Engine::Cut),
+            "stream" => Some(Engine::Stream),
+            #[cfg(all(feature = "futures", not(feature = "tokio")))]
+            "async" => Some(Engine::Async),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        Engine::NAMES[self as usize]
+    }
+
+    ///
+    /// Chunk the data once, returning the number of chunks.
+    ///
+    pub fn run(self, config: &Config, data: &[u8]) -> Result<u64, Box<dyn std::error::Error>> {
+        match self {
+            Engine::Cut => {
+                let mut chunker = FastCDC::new_advanced(config.min_size, config.avg_size, config.max_size, config.level, None)?;
+                chunker.set_gear_seed(config.seed);
+                Ok(chunker.as_iterator(data).count() as u64)
+            }
+            Engine::Stream => {
+                let mut chunks = 0;
+                for result in config.stream(data)? {
+                    result?;
+                    chunks += 1;
+                }
+                Ok(chunks)
+            }
+            #[cfg(all(feature = "futures", not(feature = "tokio")))]
+            Engine::Async => run_async(config, data),
+        }
+    }
+}
+
+#[cfg(all(feature = "futures", not(feature = "tokio")))]
+fn run_async(config: &Config, data: &[u8]) -> Result<u64, Box<dyn std::error::Error>> {
+    use fastcdc_alt::AsyncStreamCDC;
+    use futures::StreamExt;
+
+    let source = futures::io::Cursor::new(data);
+    let mut chunker = AsyncStreamCDC::new_advanced(source, config.min_size, config.avg_size, config.max_size, config.level)?;
+    chunker.set_gear_seed(config.seed);
+    futures::executor::block_on(async {
+        let stream = chunker.as_stream();
+        futures::pin_mut!(stream);
+        let mut chunks = 0;
+        while let Some(result) = stream.next().await {
+            result?;
+            chunks += 1;
+        }
+        Ok(chunks)
+    })
+}
+
+///
+/// The kind of data to chunk.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    /// Pseudo-random bytes from a fixed seed.
+    Random,
+    /// Only zero bytes.
+    Zeros,
+    /// Pseudo-random lines of English words.
+    Text,
+    /// The test fixture JPEG, repeated.
+    Jpeg,
+}
+
+impl Input {
+    pub const NAMES: [&'static str; 4] = ["random", "zeros", "text", "jpeg"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "random" => Some(Input::Random),
+            "zeros" => Some(Input::Zeros),
+            "text" => Some(Input::Text),
+            "jpeg" => Some(Input::Jpeg),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        Input::NAMES[self as usize]
+    }
+
+    ///
+    /// Generate `size` bytes of the input, the same on every call.
+    ///
+    pub fn generate(self, size: usize, fixture: &[u8]) -> Vec<u8> {
+        const WORDS: [&str; 16] = [
+            "the", "chunk", "of", "data", "is", "cut", "where", "gear", "hash", "matches", "a", "mask", "and", "content",
+            "defined", "boundaries",
+        ];
+
+        let mut random = Random(0x5eed);
+        match self {
+            Input::Random => (0..size.div_ceil(8)).flat_map(|_| random.next().to_le_bytes()).take(size).collect(),
+            Input::Zeros => vec![0; size],
+            Input::Text => {
+                let mut text = Vec::with_capacity(size + 16);
+                while text.len() < size {
+                    let value = random.next();
+                    text.extend_from_slice(WORDS[(value % 16) as usize].as_bytes());
+                    text.push(if value >> 60 == 0 { b'\n' } else { b' ' });
+                }
+                text.truncate(size);
+                text
+            }
+            Input::Jpeg => fixture.iter().copied().cycle().take(size).collect(),
+        }
+    }
+}
+
+/// SplitMix64, for inputs that are reproducible across runs and platforms.
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
+///
+/// The timed runs of one engine on one input with one set of parameters.
+///
+#[derive(Debug, Clone)]
+pub struct Measurement {
+    pub engine: Engine,
+    pub input: Input,
+    pub config: Config,
+    pub bytes: u64,
+    pub chunks: u64,
+    pub runs: Vec<Duration>,
+    /// Allocations per run.
+    pub allocations: u64,
+    /// Allocated bytes per run.
+    pub allocated_bytes: u64,
+}
+
+impl Measurement {
+    ///
+    /// Run the engine `warmup` times untimed, then `repeat` times timed.
+    ///
+    pub fn take(engine: Engine, input: Input, config: &Config, data: &[u8], warmup: u32, repeat: u32) -> Result<Self, Box<dyn std::error::Error>> {
+        for _ in 0..warmup {
+            engine.run(config, data)?;
+        }
+
+        let mut runs = Vec::with_capacity(repeat as usize);
+        let mut chunks = 0;
+        let before = allocations();
+        for _ in 0..repeat.max(1) {
+            let start = Instant::now();
+            chunks = engine.run(config, data)?;
+            runs.push(start.elapsed());
+        }
+        let after = allocations();
+
+        let count = runs.len() as u64;
+        Ok(Self {
+            engine,
+            input,
+            config: *config,
+            bytes: data.len() as u64,
+            chunks,
+            runs,
+            allocations: (after.0 - before.0) / count,
+            allocated_bytes: (after.1 - before.1) / count,
+        })
+    }
+
+    pub fn median(&self) -> Duration {
+        let mut runs = self.runs.clone();
+        runs.sort();
+        runs[runs.len() / 2]
+    }
+
+    pub fn fastest(&self) -> Duration {
+        self.runs.iter().copied().min().unwrap_or_default()
+    }
+
+    ///
+    /// Throughput of the median run in megabytes (10^6 bytes) per second.
+    ///
+    pub fn megabytes_per_second(&self) -> f64 {
+        self.bytes as f64 / 1e6 / self.median().as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+
+    ///
+    /// Chunks of the median run per second.
+    ///
+    pub fn chunks_per_second(&self) -> f64 {
+        self.chunks as f64 / self.median().as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+}
+
+pub fn write_header(output: &mut dyn Write, format: Format) -> io::Result<()> {
+    match format {
+        Format::Text => writeln!(
+            output,
+            "{:<7} {:<7} {:>10} {:>12} {:>10} {:>12} {:>10}  parameters",
+            "engine", "input", "MB/s", "chunks/s", "chunks", "allocations", "alloc MB"
+        ),
+        Format::Csv => writeln!(
+            output,
+            "version,engine,input,parameters,bytes,chunks,runs,median_ns,fastest_ns,mb_per_s,chunks_per_s,allocations,allocated_bytes"
+        ),
+        Format::JsonLines => Ok(()),
+    }
+}
+
+pub fn write_measurement(output: &mut dyn Write, format: Format, measurement: &Measurement) -> io::Result<()> {
+    let m = measurement;
+    let version = env!("CARGO_PKG_VERSION");
+    match format {
+        Format::Text => writeln!(
+            output,
+            "{:<7} {:<7} {:>10.1} {:>12.0} {:>10} {:>12} {:>10.2}  {}",
+            m.engine.name(), m.input.name(), m.megabytes_per_second(), m.chunks_per_second(), m.chunks,
+            m.allocations, m.allocated_bytes as f64 / 1e6, m.config
+        ),
+        Format::Csv => writeln!(
+            output,
+            "{},{},{},{},{},{},{},{},{},{:.3},{:.3},{},{}",
+            version, m.engine.name(), m.input.name(), csv_field(&m.config.to_string()), m.bytes, m.chunks, m.runs.len(),
+            m.median().as_nanos(), m.fastest().as_nanos(), m.megabytes_per_second(), m.chunks_per_second(),
+            m.allocations, m.allocated_bytes
+        ),
+        Format::JsonLines => writeln!(
+            output,
+            "{{\"version\":{},\"engine\":\"{}\",\"input\":\"{}\",\"parameters\":{},\"bytes\":{},\"chunks\":{},\"runs\":{},\
+             \"median_ns\":{},\"fastest_ns\":{},\"mb_per_s\":{:.3},\"chunks_per_s\":{:.3},\"allocations\":{},\"allocated_bytes\":{}}}",
+            json_string(version), m.engine.name(), m.input.name(), json_string(&m.config.to_string()), m.bytes, m.chunks,
+            m.runs.len(), m.median().as_nanos(), m.fastest().as_nanos(), m.megabytes_per_second(), m.chunks_per_second(),
+            m.allocations, m.allocated_bytes
+        ),
+    }
+}
+
+pub fn command() -> Command {
+    Command::new("bench")
+        .about("Measures the chunking throughput on generated inputs.")
+        .args(Config::args())
+        .arg(
+            Arg::new("params")
+                .short('p')
+                .long("params")
+                .value_name("PARAMS")
+                .help("Parameters to measure, e.g. avg=64K,level=2, instead of the options above")
+                .action(ArgAction::Append)
+                .value_parser(parse_params)
+                .conflicts_with_all(["min", "avg", "max", "level", "seed"]),
+        )
+        .arg(
+            Arg::new("engine")
+                .short('e')
+                .long("engine")
+                .help("Chunker APIs to measure, async is available when built with the futures feature")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .default_values(["cut", "stream"])
+                .value_parser(PossibleValuesParser::new(Engine::NAMES.iter().copied())),
+        )
+        .arg(
+            Arg::new("input")
+                .short('i')
+                .long("input")
+                .help("Inputs to chunk")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .default_values(Input::NAMES)
+                .value_parser(Input::NAMES),
+        )
+        .arg(
+            Arg::new("size")
+                .long("size")
+                .value_name("SIZE")
+                .help("Size of each input")
+                .default_value("16M")
+                .value_parser(parse_size),
+        )
+        .arg(
+            Arg::new("fixture")
+                .long("fixture")
+                .value_name("FILE")
+                .help("File repeated for the jpeg input")
+                .default_value("test/fixtures/SekienAkashita.jpg"),
+        )
+        .arg(
+            Arg::new("warmup")
+                .long("warmup")
+                .value_name("RUNS")
+                .help("Untimed runs before measuring")
+                .default_value("1")
+                .value_parser(clap::value_parser!(u32)),
+        )
+        .arg(
+            Arg::new("repeat")
+                .short('r')
+                .long("repeat")
+                .value_name("RUNS")
+                .help("Timed runs, the median is reported")
+                .default_value("5")
+                .value_parser(clap::value_parser!(u32).range(1..)),
+        )
+        .arg(
+            Arg::new("format")
+                .short('f')
+                .long("format")
+                .help("Output format")
+                .default_value("text")
+                .value_parser(Format::NAMES),
+        )
+}
+
+pub fn run(matches: &ArgMatches) -> CliResult {
+    let configs = match matches.get_many::<Config>("params") {
+        Some(configs) => configs.copied().collect(),
+        None => vec![Config::from_matches(matches)],
+    };
+    let engines = matches.get_many::<String>("engine").unwrap().filter_map(|name| Engine::from_name(name)).collect::<Vec<_>>();
+    let inputs = matches.get_many::<String>("input").unwrap().filter_map(|name| Input::from_name(name)).collect::<Vec<_>>();
+    let size = *matches.get_one::<u32>("size").unwrap() as usize;
+    let warmup = *matches.get_one::<u32>("warmup").unwrap();
+    let repeat = *matches.get_one::<u32>("repeat").unwrap();
+    let format = Format::from_name(matches.get_one::<String>("format").unwrap()).unwrap();
+    let fixture = if inputs.contains(&Input::Jpeg) {
+        let path = matches.get_one::<String>("fixture").unwrap();
+        fs::read(path).map_err(|error| format!("{}: {}", path, error))?
+    } else {
+        Vec::new()
+    };
+
+    let mut output = BufWriter::new(io::stdout().lock());
+    write_header(&mut output, format)?;
+    for input in inputs {
+        let data = input.generate(size, &fixture);
+        for config in &configs {
+            for &engine in &engines {
+                let measurement = Measurement::take(engine, input, config, &data, warmup, repeat)?;
+                write_measurement(&mut output, format, &measurement)?;
+                // Show progress as the measurements complete.
+                output.flush()?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fastcdc_alt::Normalization;
+
+    const CONFIG: Config = Config {
+        min_size: 4096,
+        avg_size: 16384,
+        max_size: 65535,
+        level: Normalization::Level1,
+        seed: 0,
+    };
+
+    #[test]
+    fn test_inputs() {
+        let fixture = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        for input in Input::NAMES.iter().filter_map(|name| Input::from_name(name)) {
+            let data = input.generate(300001, &fixture);
+            assert_eq!(data.len(), 300001);
+            assert!(data == input.generate(300001, &fixture));
+        }
+        assert!(Input::Zeros.generate(100, &fixture).iter().all(|byte| *byte == 0));
+        assert!(Input::Text.generate(1000, &fixture).is_ascii());
+        assert_eq!(&Input::Jpeg.generate(200000, &fixture)[109466..109566], &fixture[..100]);
+    }
+
+    #[test]
+    fn test_engines_agree() {
+        let fixture = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        assert_eq!(Engine::Cut.run(&CONFIG, &fixture).unwrap(), 5);
+        for input in [Input::Random, Input::Zeros, Input::Text] {
+            let data = input.generate(1 << 20, &fixture);
+            let chunks = Engine::Cut.run(&CONFIG, &data).unwrap();
+            assert!(chunks > 0);
+            assert_eq!(Engine::Stream.run(&CONFIG, &data).unwrap(), chunks);
+            #[cfg(all(feature = "futures", not(feature = "tokio")))]
+            assert_eq!(Engine::Async.run(&CONFIG, &data).unwrap(), chunks);
+        }
+    }
+
+    #[test]
+    fn test_measurement() {
+        let data = Input::Random.generate(1 << 20, &[]);
+        let measurement = Measurement::take(Engine::Stream, Input::Random, &CONFIG, &data, 1, 3).unwrap();
+        assert_eq!(measurement.runs.len(), 3);
+        assert_eq!(measurement.bytes, 1 << 20);
+        // StreamCDC allocates its buffer and a vector per chunk.
+        assert!(measurement.allocations > measurement.chunks);
+        assert!(measurement.allocated_bytes >= 1 << 20);
+        assert!(measurement.fastest() <= measurement.median());
+        assert!(measurement.megabytes_per_second() > 0.0);
+
+        let mut output = Vec::new();
+        write_header(&mut output, Format::Csv).unwrap();
+        write_measurement(&mut output, Format::Csv, &measurement).unwrap();
+        write_measurement(&mut output, Format::JsonLines, &measurement).unwrap();
+        let text = String::from_utf8(output).unwrap();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0].split(',').count(), lines[1].split(',').count());
+        assert!(lines[2].starts_with("{\"version\":") && lines[2].ends_with('}'));
+    }
+}
@@ -0,0 +1,298 @@
+//
+// Copyright (c) 2023 Florian Gäbler
+//
+
+//! The `chunk` subcommand, listing the chunks of files or stdin.
+
+use crate::digest::{to_hex, Digest};
+use crate::{open_input, CliResult, Config};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use fastcdc_alt::ManifestWriter;
+use std::io::{self, BufWriter, Read, Write};
+
+///
+/// Output format of the chunk listing.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `key=value` pairs like the examples print.
+    Text,
+    /// Comma-separated values with a header line.
+    Csv,
+    /// One JSON object per line.
+    JsonLines,
+}
+
+impl Format {
+    /// The names accepted by [Format::from_name].
+    pub const NAMES: [&'static str; 3] = ["text", "csv", "jsonl"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(Format::Text),
+            "csv" => Some(Format::Csv),
+            "jsonl" => Some(Format::JsonLines),
+            _ => None,
+        }
+    }
+}
+
+///
+/// A chunk of an input as written in the listing.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record<'a> {
+    pub file: &'a str,
+    pub offset: u64,
+    pub length: usize,
+    pub hash: u64,
+    pub digest: Option<String>,
+}
+
+pub fn command() -> Command {
+    Command::new("chunk")
+        .about("Lists the content-defined chunks of files or stdin.")
+        .args(Config::args())
+        .arg(
+            Arg::new("format")
+                .short('f')
+                .long("format")
+                .help("Output format")
+                .default_value("text")
+                .value_parser(["text", "csv", "jsonl", "manifest"]),
+        )
+        .arg(
+            Arg::new("digest")
+                .short('d')
+                .long("digest")
+                .help("Strong digest to compute for each chunk")
+                .value_parser(Digest::NAMES),
+        )
+        .arg(
+            Arg::new("FILE")
+                .help("Files to chunk, stdin if none or -")
+                .action(ArgAction::Append),
+        )
+}
+
+pub fn run(matches: &ArgMatches) -> CliResult {
+    let config = Config::from_matches(matches);
+    let digest = matches.get_one::<String>("digest").and_then(|name| Digest::from_name(name));
+    let files = match matches.get_many::<String>("FILE") {
+        Some(files) => files.map(String::as_str).collect(),
+        None => vec!["-"],
+    };
+
+    let format = match matches.get_one::<String>("format").unwrap().as_str() {
+        "manifest" => {
+            let [file] = files[..] else {
+                return Err("a manifest describes a single file".into());
+            };
+            let source = open_input(file).map_err(|error| format!("{}: {}", file, error))?;
+            return write_manifest(&config, source, digest, BufWriter::new(io::stdout().lock()));
+        }
+        name => Format::from_name(name).unwrap(),
+    };
+
+    let mut output = BufWriter::new(io::stdout().lock());
+    if format == Format::Csv {
+        write_csv_header(&mut output, digest.is_some())?;
+    }
+    for file in &files {
+        let source = open_input(file).map_err(|error| format!("{}: {}", file, error))?;
+        chunk_input(&config, file, source, format, digest, files.len() > 1, &mut output)?;
+    }
+    output.flush()?;
+    Ok(())
+}
+
+///
+/// Write a record for each chunk of the source, naming the file in text output
+/// only if `show_file` is set.
+///
+pub fn chunk_input<R: Read>(
+    config: &Config,
+    file: &str,
+    source: R,
+    format: Format,
+    digest: Option<Digest>,
+    show_file: bool,
+    output: &mut dyn Write
+) -> CliResult {
+    for result in config.stream(source)? {
+        let (data, chunk) = result?;
+        let record = Record {
+            file,
+            offset: chunk.offset as u64,
+            length: chunk.get_length(),
+            hash: chunk.hash,
+            digest: digest.map(|digest| to_hex(&digest.compute(&data))),
+        };
+        write_record(output, format, &record, show_file)?;
+    }
+    Ok(())
+}
+
+///
+/// Write the chunks of the source in the binary manifest format of [ManifestWriter].
+///
+pub fn write_manifest<R: Read, W: Write>(config: &Config, source: R, digest: Option<Digest>, output: W) -> CliResult {
+    let mut manifest = ManifestWriter::new(output, config.manifest_header(digest))?;
+    for result in config.stream(source)? {
+        let (data, chunk) = result?;
+        match digest {
+            Some(digest) => manifest.write_chunk(&chunk, &digest.compute(&data))?,
+            None => manifest.write_chunk(&chunk, &[])?,
+        }
+    }
+    manifest.finish()?;
+    Ok(())
+}
+
+pub fn write_csv_header(output: &mut dyn Write, digest: bool) -> io::Result<()> {
+    if digest {
+        writeln!(output, "file,offset,length,hash,digest")
+    } else {
+        writeln!(output, "file,offset,length,hash")
+    }
+}
+
+pub fn write_record(output: &mut dyn Write, format: Format, record: &Record, show_file: bool) -> io::Result<()> {
+    match format {
+        Format::Text => {
+            if show_file {
+                write!(output, "file={} ", record.file)?;
+            }
+            write!(output, "hash={} offset={} size={}", record.hash, record.offset, record.length)?;
+            if let Some(digest) = &record.digest {
+                write!(output, " digest={}", digest)?;
+            }
+            writeln!(output)
+        }
+        Format::Csv => {
+            write!(output, "{},{},{},{}", csv_field(record.file), record.offset, record.length, record.hash)?;
+            if let Some(digest) = &record.digest {
+                write!(output, ",{}", digest)?;
+            }
+            writeln!(output)
+        }
+        Format::JsonLines => {
+            write!(
+                output,
+                "{{\"file\":{},\"offset\":{},\"length\":{},\"hash\":{}",
+                json_string(record.file), record.offset, record.length, record.hash
+            )?;
+            if let Some(digest) = &record.digest {
+                write!(output, ",\"digest\":\"{}\"", digest)?;
+            }
+            writeln!(output, "}}")
+        }
+    }
+}
+
+/// Quote a CSV field if it contains a separator, quote or line break.
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Format a JSON string literal with the necessary escapes.
+pub fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fastcdc_alt::Normalization;
+    use std::fs::File;
+
+    const CONFIG: Config = Config {
+        min_size: 4096,
+        avg_size: 16384,
+        max_size: 65535,
+        level: Normalization::Level1,
+        seed: 0,
+    };
+
+    #[test]
+    fn test_chunk_sekien_16k() {
+        let path = "test/fixtures/SekienAkashita.jpg";
+        let mut output = Vec::new();
+        chunk_input(&CONFIG, path, File::open(path).unwrap(), Format::Text, None, false, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "hash=17968276318003433923 offset=0 size=21325\n\
+             hash=8197189939299398838 offset=21325 size=17140\n\
+             hash=13019990849178155730 offset=38465 size=28084\n\
+             hash=4509236223063678303 offset=66549 size=18217\n\
+             hash=2504464741100432583 offset=84766 size=24700\n"
+        );
+    }
+
+    #[test]
+    fn test_chunk_seeded_differs() {
+        let path = "test/fixtures/SekienAkashita.jpg";
+        let mut default = Vec::new();
+        chunk_input(&CONFIG, path, File::open(path).unwrap(), Format::Csv, None, false, &mut default).unwrap();
+        let mut seeded = Vec::new();
+        let config = Config { seed: 42, ..CONFIG };
+        chunk_input(&config, path, File::open(path).unwrap(), Format::Csv, None, false, &mut seeded).unwrap();
+        assert_ne!(default, seeded);
+    }
+
+    #[test]
+    fn test_write_manifest() {
+        let path = "test/fixtures/SekienAkashita.jpg";
+        let mut output = Vec::new();
+        write_manifest(&CONFIG, File::open(path).unwrap(), Some(Digest::Sha256), &mut output).unwrap();
+
+        let reader = fastcdc_alt::ManifestReader::new(&output[..]).unwrap();
+        assert_eq!(Digest::from_manifest_header(reader.header()), Some(Digest::Sha256));
+        let entries = reader.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(entries.iter().map(|entry| entry.length).collect::<Vec<_>>(), [21325, 17140, 28084, 18217, 24700]);
+        let contents = std::fs::read(path).unwrap();
+        assert_eq!(entries[0].digest, Digest::Sha256.compute(&contents[..21325]));
+    }
+
+    #[test]
+    fn test_write_record_formats() {
+        let record = Record {
+            file: "a \"b\",c",
+            offset: 10,
+            length: 20,
+            hash: 30,
+            digest: Some("ab".into()),
+        };
+        let format = |format, show_file| {
+            let mut output = Vec::new();
+            write_record(&mut output, format, &record, show_file).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        assert_eq!(format(Format::Text, false), "hash=30 offset=10 size=20 digest=ab\n");
+        assert_eq!(format(Format::Text, true), "file=a \"b\",c hash=30 offset=10 size=20 digest=ab\n");
+        assert_eq!(format(Format::Csv, false), "\"a \"\"b\"\",c\",10,20,30,ab\n");
+        assert_eq!(
+            format(Format::JsonLines, false),
+            "{\"file\":\"a \\\"b\\\",c\",\"offset\":10,\"length\":20,\"hash\":30,\"digest\":\"ab\"}\n"
+        );
+        assert_eq!(json_string("\u{1}\t"), "\"\\u0001\\t\"");
+    }
+}
@@ -0,0 +1,383 @@
+//
+// Copyright (c) 2023 Florian Gäbler
+//
+
+//! The `dedup` subcommand, reporting how well the files of directory trees
+//! deduplicate with one or more sets of chunker parameters.
+
+use crate::digest::{to_hex, Digest};
+use crate::{parse_params, CliResult, Config};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+///
+/// A distinct chunk and how often it occurs.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkStats {
+    pub length: usize,
+    pub count: u64,
+}
+
+///
+/// A file and the bytes of its chunks that were not seen in earlier files, i.e.
+/// what it adds to the deduplicated total.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileStats {
+    pub path: PathBuf,
+    pub bytes: u64,
+    pub new_bytes: u64,
+}
+
+///
+/// Deduplication statistics of the files chunked with one set of parameters.
+///
+pub struct Report {
+    pub config: Config,
+    pub files: Vec<FileStats>,
+    pub chunks: HashMap<[u8; 32], ChunkStats>,
+    pub total_bytes: u64,
+    pub total_chunks: u64,
+}
+
+impl Report {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            files: Vec::new(),
+            chunks: HashMap::new(),
+            total_bytes: 0,
+            total_chunks: 0,
+        }
+    }
+
+    ///
+    /// Chunk the contents of a file and add its chunks to the statistics.
+    ///
+    pub fn add<R: Read>(&mut self, path: &Path, source: R, digest: Digest) -> CliResult {
+        let mut file = FileStats { path: path.to_path_buf(), bytes: 0, new_bytes: 0 };
+        for result in self.config.stream(source)? {
+            let (data, _chunk) = result?;
+            let stats = self.chunks.entry(digest.compute(&data)).or_insert(ChunkStats { length: data.len(), count: 0 });
+            if stats.count == 0 {
+                file.new_bytes += data.len() as u64;
+            }
+            stats.count += 1;
+            file.bytes += data.len() as u64;
+            self.total_chunks += 1;
+        }
+        self.total_bytes += file.bytes;
+        self.files.push(file);
+        Ok(())
+    }
+
+    pub fn unique_bytes(&self) -> u64 {
+        self.chunks.values().map(|stats| stats.length as u64).sum()
+    }
+
+    ///
+    /// Total bytes divided by unique bytes, 1.0 if nothing deduplicates.
+    ///
+    pub fn ratio(&self) -> f64 {
+        match self.unique_bytes() {
+            0 => 1.0,
+            unique => self.total_bytes as f64 / unique as f64,
+        }
+    }
+
+    ///
+    /// Number and bytes of all chunks by power-of-two size class, as
+    /// `(lower bound, chunks, bytes)` in ascending order.
+    ///
+    pub fn histogram(&self) -> Vec<(usize, u64, u64)> {
+        let mut classes = Vec::<(usize, u64, u64)>::new();
+        for stats in self.chunks.values() {
+            let lower = 1 << (usize::BITS - 1 - stats.length.max(1).leading_zeros());
+            let index = match classes.binary_search_by_key(&lower, |class| class.0) {
+                Ok(index) => index,
+                Err(index) => {
+                    classes.insert(index, (lower, 0, 0));
+                    index
+                }
+            };
+            classes[index].1 += stats.count;
+            classes[index].2 += stats.count * stats.length as u64;
+        }
+        classes
+    }
+
+    ///
+    /// The chunks occurring more than once, the most bytes saved first.
+    ///
+    pub fn top_duplicates(&self, count: usize) -> Vec<([u8; 32], ChunkStats)> {
+        let mut duplicates = self.chunks.iter().filter(|(_, stats)| stats.count > 1).map(|(digest, stats)| (*digest, *stats)).collect::<Vec<_>>();
+        duplicates.sort_by(|a, b| saved(&b.1).cmp(&saved(&a.1)).then(a.0.cmp(&b.0)));
+        duplicates.truncate(count);
+        duplicates
+    }
+
+    ///
+    /// The files adding the most bytes to the deduplicated total first.
+    ///
+    pub fn top_files(&self, count: usize) -> Vec<&FileStats> {
+        let mut files = self.files.iter().collect::<Vec<_>>();
+        files.sort_by(|a, b| b.new_bytes.cmp(&a.new_bytes).then(a.path.cmp(&b.path)));
+        files.truncate(count);
+        files
+    }
+
+    pub fn write(&self, output: &mut dyn Write, top: usize) -> io::Result<()> {
+        let unique_bytes = self.unique_bytes();
+        writeln!(output, "parameters: {}", self.config)?;
+        writeln!(output, "files: {}", self.files.len())?;
+        writeln!(output, "chunks: {} total, {} unique", self.total_chunks, self.chunks.len())?;
+        writeln!(output, "bytes: {} total, {} unique", self.total_bytes, unique_bytes)?;
+        writeln!(output, "dedup ratio: {:.3} ({:.1}% saved)", self.ratio(), percent(self.total_bytes - unique_bytes, self.total_bytes))?;
+
+        writeln!(output, "\nchunk sizes:")?;
+        let histogram = self.histogram();
+        let most = histogram.iter().map(|class| class.1).max().unwrap_or(0);
+        for (lower, chunks, bytes) in histogram {
+            let bar = "#".repeat((chunks * 40).div_ceil(most.max(1)) as usize);
+            writeln!(output, "  {:>10} - {:<10} {:>10} chunks {:>14} bytes  {}", lower, lower * 2 - 1, chunks, bytes, bar)?;
+        }
+
+        writeln!(output, "\ntop duplicate chunks:")?;
+        let duplicates = self.top_duplicates(top);
+        if duplicates.is_empty() {
+            writeln!(output, "  none")?;
+        }
+        for (digest, stats) in duplicates {
+            writeln!(output, "  {} {:>10} bytes x{:<8} {:>14} bytes saved", to_hex(&digest), stats.length, stats.count, saved(&stats))?;
+        }
+
+        writeln!(output, "\ntop files by contribution to unique bytes:")?;
+        for file in self.top_files(top) {
+            writeln!(
+                output,
+                "  {:>14} of {:>14} bytes new {:>6.1}%  {}",
+                file.new_bytes, file.bytes, percent(file.new_bytes, unique_bytes), file.path.display()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub fn command() -> Command {
+    Command::new("dedup")
+        .about("Reports how well the files of directory trees deduplicate.")
+        .args(Config::args())
+        .arg(
+            Arg::new("params")
+                .short('p')
+                .long("params")
+                .value_name("PARAMS")
+                .help("Parameters to compare, e.g. avg=64K,level=2, instead of the options above")
+                .action(ArgAction::Append)
+                .value_parser(parse_params)
+                .conflicts_with_all(["min", "avg", "max", "level", "seed"]),
+        )
+        .arg(
+            Arg::new("digest")
+                .short('d')
+                .long("digest")
+                .help("Digest identifying duplicate chunks")
+                .default_value("blake3")
+                .value_parser(Digest::NAMES),
+        )
+        .arg(
+            Arg::new("top")
+                .long("top")
+                .value_name("COUNT")
+                .help("Number of duplicate chunks and files to list")
+                .default_value("10")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("PATH")
+                .help("Files and directories to scan")
+                .required(true)
+                .action(ArgAction::Append),
+        )
+}
+
+pub fn run(matches: &ArgMatches) -> CliResult {
+    let configs = match matches.get_many::<Config>("params") {
+        Some(configs) => configs.copied().collect(),
+        None => vec![Config::from_matches(matches)],
+    };
+    let digest = Digest::from_name(matches.get_one::<String>("digest").unwrap()).unwrap();
+    let top = *matches.get_one::<usize>("top").unwrap();
+
+    let mut files = Vec::new();
+    for path in matches.get_many::<String>("PATH").unwrap() {
+        walk(Path::new(path), &mut files).map_err(|error| format!("{}: {}", path, error))?;
+    }
+
+    let mut output = BufWriter::new(io::stdout().lock());
+    let mut reports = Vec::new();
+    for config in configs {
+        let mut report = Report::new(config);
+        for path in &files {
+            let file = File::open(path).map_err(|error| format!("{}: {}", path.display(), error))?;
+            report.add(path, file, digest)?;
+        }
+        if !reports.is_empty() {
+            writeln!(output)?;
+        }
+        report.write(&mut output, top)?;
+        reports.push(report);
+    }
+    if reports.len() > 1 {
+        writeln!(output)?;
+        write_comparison(&mut output, &reports)?;
+    }
+    output.flush()?;
+    Ok(())
+}
+
+///
+/// Write one line per report, to compare the parameter sets.
+///
+pub fn write_comparison(output: &mut dyn Write, reports: &[Report]) -> io::Result<()> {
+    writeln!(output, "comparison:")?;
+    writeln!(output, "  {:>10} {:>10} {:>14} {:>8}  parameters", "chunks", "unique", "unique bytes", "ratio")?;
+    for report in reports {
+        writeln!(
+            output,
+            "  {:>10} {:>10} {:>14} {:>8.3}  {}",
+            report.total_chunks, report.chunks.len(), report.unique_bytes(), report.ratio(), report.config
+        )?;
+    }
+    Ok(())
+}
+
+///
+/// Collect the regular files at the path, recursing into directories in name
+/// order. A symbolic link given as the path is followed, while symbolic links
+/// found in the directories are skipped, so every file is counted once.
+///
+pub fn walk(path: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    visit(path, fs::metadata(path)?, files)
+}
+
+fn visit(path: &Path, metadata: fs::Metadata, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    if metadata.is_dir() {
+        let mut entries = fs::read_dir(path)?.map(|entry| entry.map(|entry| entry.path())).collect::<io::Result<Vec<_>>>()?;
+        entries.sort();
+        for entry in entries {
+            let metadata = fs::symlink_metadata(&entry)?;
+            visit(&entry, metadata, files)?;
+        }
+    } else if metadata.is_file() {
+        files.push(path.to_path_buf());
+    }
+    Ok(())
+}
+
+fn saved(stats: &ChunkStats) -> u64 {
+    (stats.count - 1) * stats.length as u64
+}
+
+fn percent(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / whole as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fastcdc_alt::Normalization;
+
+    const CONFIG: Config = Config {
+        min_size: 4096,
+        avg_size: 16384,
+        max_size: 65535,
+        level: Normalization::Level1,
+        seed: 0,
+    };
+
+    #[test]
+    fn test_report_duplicate_files() {
+        let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        let mut report = Report::new(CONFIG);
+        report.add(Path::new("a"), &contents[..], Digest::Blake3).unwrap();
+        report.add(Path::new("b"), &contents[..], Digest::Blake3).unwrap();
+        report.add(Path::new("c"), &contents[..21325], Digest::Blake3).unwrap();
+
+        assert_eq!(report.total_chunks, 11);
+        assert_eq!(report.chunks.len(), 5);
+        assert_eq!(report.unique_bytes(), 109466);
+        assert_eq!(report.total_bytes, 2 * 109466 + 21325);
+        assert!((report.ratio() - 240257.0 / 109466.0).abs() < 1e-9);
+
+        let new_bytes = report.files.iter().map(|file| file.new_bytes).collect::<Vec<_>>();
+        assert_eq!(new_bytes, [109466, 0, 0]);
+        assert_eq!(report.top_files(1)[0].path, Path::new("a"));
+
+        // The first chunk occurs three times and saves the most.
+        let top = report.top_duplicates(10);
+        assert_eq!(top.len(), 5);
+        assert_eq!(top[0].1, ChunkStats { length: 21325, count: 3 });
+
+        let histogram = report.histogram();
+        assert_eq!(histogram, [(16384, 11, 240257)]);
+        assert_eq!(histogram.iter().map(|class| class.1).sum::<u64>(), report.total_chunks);
+
+        let mut output = Vec::new();
+        report.write(&mut output, 3).unwrap();
+        let text = String::from_utf8(output).unwrap();
+        assert!(text.contains("dedup ratio: 2.195 (54.4% saved)"), "{}", text);
+    }
+
+    #[test]
+    fn test_report_empty() {
+        let report = Report::new(CONFIG);
+        assert_eq!(report.ratio(), 1.0);
+        assert!(report.histogram().is_empty());
+        report.write(&mut Vec::new(), 10).unwrap();
+    }
+
+    #[test]
+    fn test_walk() {
+        let root = std::env::temp_dir().join(format!("fastcdc-walk-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("b/c")).unwrap();
+        fs::write(root.join("b/c/d"), b"d").unwrap();
+        fs::write(root.join("a"), b"a").unwrap();
+        fs::write(root.join("b/e"), b"e").unwrap();
+
+        let mut files = Vec::new();
+        walk(&root, &mut files).unwrap();
+        assert_eq!(files, [root.join("a"), root.join("b/c/d"), root.join("b/e")]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_walk_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let root = std::env::temp_dir().join(format!("fastcdc-walk-links-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("data")).unwrap();
+        fs::write(root.join("data/a"), b"a").unwrap();
+        symlink(root.join("data/a"), root.join("data/link")).unwrap();
+        symlink(root.join("data"), root.join("dir")).unwrap();
+        symlink(root.join("data/a"), root.join("file")).unwrap();
+
+        // Links given as the path are followed, links within directories are not.
+        let mut files = Vec::new();
+        walk(&root.join("dir"), &mut files).unwrap();
+        walk(&root.join("file"), &mut files).unwrap();
+        assert_eq!(files, [root.join("dir/a"), root.join("file")]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
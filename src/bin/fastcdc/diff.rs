@@ -0,0 +1,251 @@
+//
+// Copyright (c) 2023 Florian Gäbler
+//
+
+//! The `diff` subcommand, showing which regions of a new file version reuse
+//! chunks of the old version and what a chunk-based sync would transfer.
+
+use crate::digest::Digest;
+use crate::{open_input, CliResult, Config};
+use clap::{Arg, ArgMatches, Command};
+use std::collections::HashSet;
+use std::io::{self, BufWriter, Read, Write};
+
+///
+/// Whether a range of the new file has to be transferred, or consists of chunks
+/// the receiver already has, from the old file or from earlier in the new file.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Reused,
+    New,
+}
+
+///
+/// A maximal run of consecutive chunks of the new file of the same kind.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Range {
+    pub kind: Kind,
+    pub offset: u64,
+    pub length: u64,
+    pub chunks: u64,
+}
+
+///
+/// Comparison of the chunks of two file versions.\
+/// A chunk of the new file has to be transferred if the old file lacks it, once
+/// even if it occurs repeatedly.
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Diff {
+    pub ranges: Vec<Range>,
+    pub old_bytes: u64,
+    pub old_chunks: u64,
+    pub new_bytes: u64,
+    pub new_chunks: u64,
+    pub transfer_bytes: u64,
+    pub transfer_chunks: u64,
+}
+
+impl Diff {
+    pub fn reused_bytes(&self) -> u64 {
+        self.ranges.iter().filter(|range| range.kind == Kind::Reused).map(|range| range.length).sum()
+    }
+
+    pub fn write(&self, output: &mut dyn Write) -> io::Result<()> {
+        for range in &self.ranges {
+            let kind = match range.kind {
+                Kind::Reused => "reused",
+                Kind::New => "new",
+            };
+            writeln!(
+                output,
+                "{:<6} {:>12}..{:<12} {:>12} bytes {:>8} chunks",
+                kind, range.offset, range.offset + range.length, range.length, range.chunks
+            )?;
+        }
+        writeln!(output, "old: {} bytes in {} chunks", self.old_bytes, self.old_chunks)?;
+        writeln!(output, "new: {} bytes in {} chunks, {} bytes reused", self.new_bytes, self.new_chunks, self.reused_bytes())?;
+        let saved = self.new_bytes - self.transfer_bytes;
+        let percent = if self.new_bytes == 0 { 0.0 } else { saved as f64 * 100.0 / self.new_bytes as f64 };
+        writeln!(
+            output,
+            "transfer: {} bytes in {} chunks, {} bytes ({:.1}%) saved",
+            self.transfer_bytes, self.transfer_chunks, saved, percent
+        )
+    }
+
+    ///
+    /// Write a map of the new file, one character per `width`th of it: `=` where
+    /// it is reused, `+` where it is new and `~` where it is mixed.
+    ///
+    pub fn write_map(&self, output: &mut dyn Write, width: usize) -> io::Result<()> {
+        let mut map = String::with_capacity(width);
+        let mut ranges = self.ranges.iter().peekable();
+        for cell in 0..width as u64 {
+            let start = self.new_bytes * cell / width as u64;
+            let end = (self.new_bytes * (cell + 1) / width as u64).max(start + 1);
+            let (mut reused, mut new) = (false, false);
+            while let Some(range) = ranges.peek() {
+                if range.offset < end && range.offset + range.length > start {
+                    match range.kind {
+                        Kind::Reused => reused = true,
+                        Kind::New => new = true,
+                    }
+                }
+                if range.offset + range.length > end {
+                    break;
+                }
+                ranges.next();
+            }
+            map.push(match (reused, new) {
+                (true, true) => '~',
+                (true, false) => '=',
+                (false, true) => '+',
+                (false, false) => ' ',
+            });
+        }
+        writeln!(output, "[{}]", map)
+    }
+}
+
+pub fn command() -> Command {
+    Command::new("diff")
+        .about("Shows which regions of a new file version reuse chunks of the old version.")
+        .args(Config::args())
+        .arg(
+            Arg::new("digest")
+                .short('d')
+                .long("digest")
+                .help("Digest identifying equal chunks")
+                .default_value("blake3")
+                .value_parser(Digest::NAMES),
+        )
+        .arg(
+            Arg::new("map")
+                .long("map")
+                .value_name("WIDTH")
+                .help("Print a map of the new file with the given width")
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("64")
+                .value_parser(clap::value_parser!(u16).range(1..)),
+        )
+        .arg(Arg::new("OLD").help("Old version of the file, - for stdin").required(true))
+        .arg(Arg::new("NEW").help("New version of the file, - for stdin").required(true))
+}
+
+pub fn run(matches: &ArgMatches) -> CliResult {
+    let config = Config::from_matches(matches);
+    let digest = Digest::from_name(matches.get_one::<String>("digest").unwrap()).unwrap();
+    let old = matches.get_one::<String>("OLD").unwrap();
+    let new = matches.get_one::<String>("NEW").unwrap();
+    if old == "-" && new == "-" {
+        return Err("only one of the files can be read from stdin".into());
+    }
+    let old_source = open_input(old).map_err(|error| format!("{}: {}", old, error))?;
+    let new_source = open_input(new).map_err(|error| format!("{}: {}", new, error))?;
+
+    let diff = diff(&config, digest, old_source, new_source)?;
+    let mut output = BufWriter::new(io::stdout().lock());
+    diff.write(&mut output)?;
+    if let Some(width) = matches.get_one::<u16>("map") {
+        diff.write_map(&mut output, *width as usize)?;
+    }
+    output.flush()?;
+    Ok(())
+}
+
+///
+/// Chunk both versions with the same parameters and compare their chunks.
+///
+pub fn diff<A: Read, B: Read>(config: &Config, digest: Digest, old: A, new: B) -> Result<Diff, Box<dyn std::error::Error>> {
+    let mut diff = Diff::default();
+    let mut known = HashSet::new();
+    for result in config.stream(old)? {
+        let (data, _chunk) = result?;
+        known.insert(digest.compute(&data));
+        diff.old_bytes += data.len() as u64;
+        diff.old_chunks += 1;
+    }
+
+    for result in config.stream(new)? {
+        let (data, chunk) = result?;
+        let length = data.len() as u64;
+        let kind = if known.insert(digest.compute(&data)) {
+            diff.transfer_bytes += length;
+            diff.transfer_chunks += 1;
+            Kind::New
+        } else {
+            Kind::Reused
+        };
+        match diff.ranges.last_mut() {
+            Some(range) if range.kind == kind => {
+                range.length += length;
+                range.chunks += 1;
+            }
+            _ => diff.ranges.push(Range { kind, offset: chunk.offset as u64, length, chunks: 1 }),
+        }
+        diff.new_bytes += length;
+        diff.new_chunks += 1;
+    }
+    Ok(diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fastcdc_alt::Normalization;
+    use std::fs;
+
+    const CONFIG: Config = Config {
+        min_size: 4096,
+        avg_size: 16384,
+        max_size: 65535,
+        level: Normalization::Level1,
+        seed: 0,
+    };
+
+    #[test]
+    fn test_diff_changed_start() {
+        let old = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        let mut new = old.clone();
+        new[100] ^= 1;
+
+        let diff = diff(&CONFIG, Digest::Blake3, &old[..], &new[..]).unwrap();
+        assert_eq!(diff.ranges, [
+            Range { kind: Kind::New, offset: 0, length: 21325, chunks: 1 },
+            Range { kind: Kind::Reused, offset: 21325, length: 88141, chunks: 4 },
+        ]);
+        assert_eq!((diff.transfer_bytes, diff.transfer_chunks), (21325, 1));
+        assert_eq!(diff.reused_bytes(), 88141);
+
+        let mut output = Vec::new();
+        diff.write_map(&mut output, 10).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "[+~========]\n");
+    }
+
+    #[test]
+    fn test_diff_repeated_new_chunks() {
+        // Chunks occurring twice in the new file are transferred once, then reused.
+        let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        let new = [&contents[..], &contents[..]].concat();
+
+        let diff = diff(&CONFIG, Digest::Blake3, &[][..], &new[..]).unwrap();
+        assert_eq!(diff.ranges.iter().map(|range| range.kind).collect::<Vec<_>>(), [Kind::New, Kind::Reused, Kind::New]);
+        assert_eq!((diff.transfer_bytes, diff.transfer_chunks), (172631, 7));
+        assert_eq!((diff.new_bytes, diff.new_chunks), (218932, 9));
+        assert_eq!((diff.old_bytes, diff.old_chunks), (0, 0));
+    }
+
+    #[test]
+    fn test_diff_empty() {
+        let diff = diff(&CONFIG, Digest::Blake3, &[][..], &[][..]).unwrap();
+        assert!(diff.ranges.is_empty());
+        let mut output = Vec::new();
+        diff.write(&mut output).unwrap();
+        diff.write_map(&mut output, 4).unwrap();
+        assert!(String::from_utf8(output).unwrap().ends_with("saved\n[    ]\n"));
+    }
+}
@@ -0,0 +1,116 @@
+//
+// Copyright (c) 2023 Florian Gäbler
+//
+
+//! Strong digests of chunk contents.
+
+use fastcdc_alt::ManifestHeader;
+use sha2::{Digest as _, Sha256};
+use std::fmt::Write;
+
+///
+/// A strong digest algorithm for identifying chunks by content.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Digest {
+    Sha256,
+    Blake3,
+}
+
+impl Digest {
+    /// The names accepted by [Digest::from_name].
+    pub const NAMES: [&'static str; 2] = ["sha256", "blake3"];
+
+    ///
+    /// Look up an algorithm by its name, see [Digest::NAMES].
+    ///
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sha256" => Some(Digest::Sha256),
+            "blake3" => Some(Digest::Blake3),
+            _ => None,
+        }
+    }
+
+    ///
+    /// The name of the algorithm, the inverse of [Digest::from_name].
+    ///
+    pub fn name(self) -> &'static str {
+        match self {
+            Digest::Sha256 => "sha256",
+            Digest::Blake3 => "blake3",
+        }
+    }
+
+    ///
+    /// The identifier of the algorithm in the header of a manifest, see
+    /// [ManifestHeader::digest_algorithm](fastcdc_alt::ManifestHeader::digest_algorithm).
+    ///
+    pub fn id(self) -> u8 {
+        match self {
+            Digest::Sha256 => 1,
+            Digest::Blake3 => 2,
+        }
+    }
+
+    ///
+    /// Look up an algorithm by its identifier, the inverse of [Digest::id].
+    ///
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(Digest::Sha256),
+            2 => Some(Digest::Blake3),
+            _ => None,
+        }
+    }
+
+    ///
+    /// The algorithm of the digests of a manifest, if it stores digests of a known
+    /// algorithm.
+    ///
+    pub fn from_manifest_header(header: &ManifestHeader) -> Option<Self> {
+        Self::from_id(header.digest_algorithm).filter(|_| header.digest_length == 32)
+    }
+
+    ///
+    /// Compute the digest of the data.
+    ///
+    pub fn compute(self, data: &[u8]) -> [u8; 32] {
+        match self {
+            Digest::Sha256 => Sha256::digest(data).into(),
+            Digest::Blake3 => blake3::hash(data).into(),
+        }
+    }
+}
+
+///
+/// Format the bytes as lowercase hexadecimal.
+///
+pub fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        write!(hex, "{:02x}", byte).unwrap();
+    }
+    hex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_digest_vectors() {
+        assert_eq!(
+            to_hex(&Digest::Sha256.compute(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            to_hex(&Digest::Blake3.compute(b"")),
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+        );
+        assert_eq!(Digest::NAMES.map(Digest::from_name), [Some(Digest::Sha256), Some(Digest::Blake3)]);
+        assert_eq!(Digest::NAMES, [Digest::Sha256.name(), Digest::Blake3.name()]);
+        assert_eq!([Digest::Sha256.id(), Digest::Blake3.id()].map(Digest::from_id), [Some(Digest::Sha256), Some(Digest::Blake3)]);
+        assert_eq!(Digest::from_id(0), None);
+    }
+}
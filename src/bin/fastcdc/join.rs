@@ -0,0 +1,147 @@
+//
+// Copyright (c) 2023 Florian Gäbler
+//
+
+//! The `join` subcommand, reassembling a file from its manifest and chunk files.
+
+use crate::digest::{to_hex, Digest};
+use crate::store::Store;
+use crate::{open_input, open_output, CliResult};
+use clap::{Arg, ArgMatches, Command};
+use fastcdc_alt::ManifestReader;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+pub fn command() -> Command {
+    Command::new("join")
+        .about("Reassembles a file from a manifest and its chunk files, verifying their digests.")
+        .arg(
+            Arg::new("store")
+                .long("store")
+                .value_name("DIR")
+                .help("Directory of the chunk files")
+                .required(true),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("FILE")
+                .help("File to write, stdout if -")
+                .default_value("-"),
+        )
+        .arg(
+            Arg::new("MANIFEST")
+                .help("Manifest of the file, stdin if none or -")
+                .default_value("-"),
+        )
+}
+
+pub fn run(matches: &ArgMatches) -> CliResult {
+    let store = Store::new(matches.get_one::<String>("store").unwrap());
+    let path = matches.get_one::<String>("MANIFEST").unwrap();
+    let manifest = open_input(path).map_err(|error| format!("{}: {}", path, error))?;
+    let path = matches.get_one::<String>("output").unwrap();
+    let output = open_output(path).map_err(|error| format!("{}: {}", path, error))?;
+
+    join(&store, BufReader::new(manifest), BufWriter::new(output))
+}
+
+///
+/// Write the chunks listed in the manifest to the output, failing on the first
+/// chunk file that is missing or does not match its length and digest.\
+/// The output is incomplete after a failure.
+///
+pub fn join<R: Read, W: Write>(store: &Store, manifest: R, mut output: W) -> CliResult {
+    let manifest = ManifestReader::new(manifest)?;
+    let digest = Digest::from_manifest_header(manifest.header()).ok_or("manifest without chunk digests")?;
+    for entry in manifest {
+        let entry = entry?;
+        let data = store.get(&entry.digest)?;
+        if data.len() != entry.length || digest.compute(&data)[..] != entry.digest[..] {
+            return Err(format!("chunk {} is corrupt", to_hex(&entry.digest)).into());
+        }
+        output.write_all(&data)?;
+    }
+    output.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::write_manifest;
+    use crate::split::{split, SplitStats};
+    use crate::Config;
+    use fastcdc_alt::Normalization;
+    use std::fs;
+    use std::path::PathBuf;
+
+    const CONFIG: Config = Config {
+        min_size: 4096,
+        avg_size: 16384,
+        max_size: 65535,
+        level: Normalization::Level1,
+        seed: 0,
+    };
+
+    fn temporary_store(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("fastcdc-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        root
+    }
+
+    #[test]
+    fn test_split_join_round_trip() {
+        let root = temporary_store("round-trip");
+        let store = Store::new(&root);
+        let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+
+        let mut manifest = Vec::new();
+        let stats = split(&CONFIG, Digest::Sha256, &store, &contents[..], &mut manifest).unwrap();
+        assert_eq!(stats, SplitStats { chunks: 5, bytes: 109466, new_chunks: 5, new_bytes: 109466 });
+
+        // Changing the start only stores the chunks that changed.
+        let mut changed = contents.clone();
+        changed[100] ^= 1;
+        let mut changed_manifest = Vec::new();
+        let stats = split(&CONFIG, Digest::Sha256, &store, &changed[..], &mut changed_manifest).unwrap();
+        assert_eq!(stats, SplitStats { chunks: 5, bytes: 109466, new_chunks: 1, new_bytes: 21325 });
+
+        let mut joined = Vec::new();
+        join(&store, &manifest[..], &mut joined).unwrap();
+        assert!(joined == contents);
+        joined.clear();
+        join(&store, &changed_manifest[..], &mut joined).unwrap();
+        assert!(joined == changed);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_join_corrupt_chunk() {
+        let root = temporary_store("corrupt");
+        let store = Store::new(&root);
+        let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        let mut manifest = Vec::new();
+        split(&CONFIG, Digest::Blake3, &store, &contents[..], &mut manifest).unwrap();
+
+        let digest = Digest::Blake3.compute(&contents[21325..38465]);
+        let mut data = fs::read(store.path(&digest)).unwrap();
+        data[0] ^= 1;
+        fs::write(store.path(&digest), data).unwrap();
+        let error = join(&store, &manifest[..], Vec::new()).unwrap_err();
+        assert_eq!(error.to_string(), format!("chunk {} is corrupt", to_hex(&digest)));
+
+        fs::remove_file(store.path(&digest)).unwrap();
+        assert!(join(&store, &manifest[..], Vec::new()).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_join_manifest_without_digests() {
+        let path = "test/fixtures/SekienAkashita.jpg";
+        let mut manifest = Vec::new();
+        write_manifest(&CONFIG, fs::File::open(path).unwrap(), None, &mut manifest).unwrap();
+        let error = join(&Store::new(temporary_store("no-digests")), &manifest[..], Vec::new()).unwrap_err();
+        assert_eq!(error.to_string(), "manifest without chunk digests");
+    }
+}
@@ -0,0 +1,346 @@
+//
+// Copyright (c) 2023 Florian Gäbler
+//
+
+//! The `fastcdc` command-line tool, enabled by the `cli` feature.
+
+mod bench;
+mod chunk;
+mod dedup;
+mod diff;
+mod digest;
+mod join;
+mod split;
+mod store;
+
+use clap::{command, value_parser, Arg, ArgMatches, Command};
+use digest::Digest;
+use fastcdc_alt::{Error, ManifestHeader, Normalization, StreamCDC};
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::process::ExitCode;
+
+#[global_allocator]
+static ALLOCATOR: bench::CountingAllocator = bench::CountingAllocator;
+
+/// Result type of the subcommands.
+pub type CliResult = Result<(), Box<dyn std::error::Error>>;
+
+///
+/// The chunker parameters shared by the subcommands.
+///
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    pub min_size: u32,
+    pub avg_size: u32,
+    pub max_size: u32,
+    pub level: Normalization,
+    pub seed: u64,
+}
+
+impl Config {
+    ///
+    /// The arguments from which [Config::from_matches] reads the parameters.
+    ///
+    pub fn args() -> [Arg; 5] {
+        [
+            Arg::new("min")
+                .long("min")
+                .value_name("SIZE")
+                .help("Minimum chunk size, a quarter of the average by default")
+                .value_parser(parse_size),
+            Arg::new("avg")
+                .short('s')
+                .long("avg")
+                .value_name("SIZE")
+                .help("Average chunk size, with an optional K, M or G suffix")
+                .default_value("16K")
+                .value_parser(parse_size),
+            Arg::new("max")
+                .long("max")
+                .value_name("SIZE")
+                .help("Maximum chunk size, four times the average by default")
+                .value_parser(parse_size),
+            Arg::new("level")
+                .short('n')
+                .long("level")
+                .value_name("LEVEL")
+                .help("Chunk size normalization level")
+                .default_value("1")
+                .value_parser(value_parser!(u8).range(0..=3)),
+            Arg::new("seed")
+                .long("seed")
+                .value_name("SEED")
+                .help("Seed of the gear table, 0 for the default table")
+                .default_value("0")
+                .value_parser(parse_seed),
+        ]
+    }
+
+    ///
+    /// Read the parameters from matches of a command built with [Config::args].
+    ///
+    pub fn from_matches(matches: &ArgMatches) -> Self {
+        let avg_size = *matches.get_one::<u32>("avg").unwrap();
+        let level = normalization(*matches.get_one::<u8>("level").unwrap()).unwrap();
+        Self {
+            min_size: matches.get_one::<u32>("min").copied().unwrap_or(avg_size / 4),
+            avg_size,
+            max_size: matches.get_one::<u32>("max").copied().unwrap_or(avg_size.saturating_mul(4)),
+            level,
+            seed: *matches.get_one::<u64>("seed").unwrap(),
+        }
+    }
+
+    ///
+    /// The header of a manifest of chunks with these parameters, with digests of the
+    /// given algorithm or none.
+    ///
+    pub fn manifest_header(&self, digest: Option<Digest>) -> ManifestHeader {
+        ManifestHeader {
+            min_size: self.min_size,
+            avg_size: self.avg_size,
+            max_size: self.max_size,
+            level: self.level,
+            gear_seed: self.seed,
+            digest_length: if digest.is_some() { 32 } else { 0 },
+            digest_algorithm: digest.map_or(0, Digest::id),
+        }
+    }
+
+    ///
+    /// The parameters recorded in the header of a manifest.
+    ///
+    pub fn from_manifest_header(header: &ManifestHeader) -> Self {
+        Self {
+            min_size: header.min_size,
+            avg_size: header.avg_size,
+            max_size: header.max_size,
+            level: header.level,
+            seed: header.gear_seed,
+        }
+    }
+
+    ///
+    /// Construct a [StreamCDC] over the source with these parameters.
+    ///
+    pub fn stream<R: Read>(&self, source: R) -> Result<StreamCDC<R>, Error> {
+        let mut chunker = StreamCDC::new_advanced(source, self.min_size, self.avg_size, self.max_size, self.level)?;
+        chunker.set_gear_seed(self.seed);
+        Ok(chunker)
+    }
+}
+
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "min={} avg={} max={} level={} seed={}",
+            self.min_size, self.avg_size, self.max_size, normalization_level(self.level), self.seed
+        )
+    }
+}
+
+///
+/// The normalization of the given level, from 0 to 3.
+///
+pub fn normalization(level: u8) -> Option<Normalization> {
+    match level {
+        0 => Some(Normalization::Level0),
+        1 => Some(Normalization::Level1),
+        2 => Some(Normalization::Level2),
+        3 => Some(Normalization::Level3),
+        _ => None,
+    }
+}
+
+///
+/// The level of the normalization, the inverse of [normalization].
+///
+pub fn normalization_level(level: Normalization) -> u8 {
+    match level {
+        Normalization::Level0 => 0,
+        Normalization::Level1 => 1,
+        Normalization::Level2 => 2,
+        Normalization::Level3 => 3,
+    }
+}
+
+///
+/// Open the file at the path for reading, or stdin if the path is `-`.
+///
+pub fn open_input(path: &str) -> io::Result<Box<dyn Read>> {
+    if path == "-" {
+        Ok(Box::new(io::stdin().lock()))
+    } else {
+        Ok(Box::new(File::open(path)?))
+    }
+}
+
+///
+/// Create the file at the path for writing, or write to stdout if the path is `-`.
+///
+pub fn open_output(path: &str) -> io::Result<Box<dyn Write>> {
+    if path == "-" {
+        Ok(Box::new(io::stdout().lock()))
+    } else {
+        Ok(Box::new(File::create(path)?))
+    }
+}
+
+/// Parse a size in bytes with an optional binary `K`, `M` or `G` suffix.
+fn parse_size(value: &str) -> Result<u32, String> {
+    let (digits, shift) = match value.char_indices().last() {
+        Some((index, 'k' | 'K')) => (&value[..index], 10),
+        Some((index, 'm' | 'M')) => (&value[..index], 20),
+        Some((index, 'g' | 'G')) => (&value[..index], 30),
+        _ => (value, 0),
+    };
+    let size = digits.parse::<u64>().map_err(|error| error.to_string())?;
+    size.checked_mul(1 << shift)
+        .and_then(|size| u32::try_from(size).ok())
+        .ok_or_else(|| format!("size {} is too large", value))
+}
+
+///
+/// Parse chunker parameters as `key=value` pairs separated by commas or spaces,
+/// like [Config] displays them. The keys are `min`, `avg`, `max`, `level` and
+/// `seed`, and missing ones get the defaults of [Config::args].
+///
+pub fn parse_params(spec: &str) -> Result<Config, String> {
+    let (mut min_size, mut avg_size, mut max_size, mut level, mut seed) = (None, 16384, None, 1, 0);
+    for pair in spec.split([',', ' ']).filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').ok_or_else(|| format!("expected key=value, found {}", pair))?;
+        match key {
+            "min" => min_size = Some(parse_size(value)?),
+            "avg" => avg_size = parse_size(value)?,
+            "max" => max_size = Some(parse_size(value)?),
+            "level" => level = value.parse::<u8>().map_err(|error| error.to_string())?,
+            "seed" => seed = parse_seed(value)?,
+            _ => return Err(format!("unknown parameter {}", key)),
+        }
+    }
+    Ok(Config {
+        min_size: min_size.unwrap_or(avg_size / 4),
+        avg_size,
+        max_size: max_size.unwrap_or(avg_size.saturating_mul(4)),
+        level: normalization(level).ok_or_else(|| format!("invalid level {}", level))?,
+        seed,
+    })
+}
+
+/// Parse a gear seed, in decimal or in hexadecimal with a `0x` prefix.
+fn parse_seed(value: &str) -> Result<u64, String> {
+    match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse::<u64>(),
+    }
+    .map_err(|error| error.to_string())
+}
+
+fn command() -> Command {
+    command!()
+        .about("Content-defined chunking with FastCDC.")
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .subcommand(chunk::command())
+        .subcommand(split::command())
+        .subcommand(join::command())
+        .subcommand(dedup::command())
+        .subcommand(diff::command())
+        .subcommand(bench::command())
+}
+
+fn main() -> ExitCode {
+    let matches = command().get_matches();
+    let result = match matches.subcommand() {
+        Some(("chunk", matches)) => chunk::run(matches),
+        Some(("split", matches)) => split::run(matches),
+        Some(("join", matches)) => join::run(matches),
+        Some(("dedup", matches)) => dedup::run(matches),
+        Some(("diff", matches)) => diff::run(matches),
+        Some(("bench", matches)) => bench::run(matches),
+        _ => unreachable!("subcommand is required"),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        // A closed pipe, e.g. when piped into `head`, is not an error.
+        Err(error) if is_broken_pipe(error.as_ref()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("fastcdc: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn is_broken_pipe(error: &(dyn std::error::Error + 'static)) -> bool {
+    match error.downcast_ref::<io::Error>() {
+        Some(error) => error.kind() == io::ErrorKind::BrokenPipe,
+        None => matches!(error.downcast_ref::<Error>(), Some(Error::IoError(error)) if error.kind() == io::ErrorKind::BrokenPipe),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command() {
+        command().debug_assert();
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("16K"), Ok(16384));
+        assert_eq!(parse_size("1m"), Ok(1 << 20));
+        assert_eq!(parse_size("3G"), Ok(3 << 30));
+        assert!(parse_size("4G").is_err());
+        assert!(parse_size("K").is_err());
+        assert!(parse_size("-1").is_err());
+    }
+
+    #[test]
+    fn test_parse_seed() {
+        assert_eq!(parse_seed("42"), Ok(42));
+        assert_eq!(parse_seed("0xff"), Ok(255));
+        assert!(parse_seed("0xg").is_err());
+    }
+
+    #[test]
+    fn test_parse_params() {
+        let config = parse_params("avg=64K,level=2").unwrap();
+        assert_eq!((config.min_size, config.avg_size, config.max_size, config.seed), (16384, 65536, 262144, 0));
+        assert!(matches!(config.level, Normalization::Level2));
+
+        let config = parse_params("min=1K avg=4K max=8K level=0 seed=0x10").unwrap();
+        assert_eq!(parse_params(&config.to_string()).unwrap().to_string(), config.to_string());
+        assert_eq!(config.to_string(), "min=1024 avg=4096 max=8192 level=0 seed=16");
+
+        assert!(parse_params("avg").is_err());
+        assert!(parse_params("size=4K").is_err());
+        assert!(parse_params("level=4").is_err());
+    }
+
+    #[test]
+    fn test_config_defaults() {
+        let matches = Command::new("test").args(Config::args()).get_matches_from(["test", "-s", "64K"]);
+        let config = Config::from_matches(&matches);
+        assert_eq!((config.min_size, config.avg_size, config.max_size), (16384, 65536, 262144));
+        assert!(matches!(config.level, Normalization::Level1));
+        assert_eq!(config.seed, 0);
+    }
+
+    #[test]
+    fn test_config_manifest_header() {
+        let config = parse_params("min=1K avg=4K max=8K level=3 seed=7").unwrap();
+        let header = config.manifest_header(Some(Digest::Blake3));
+        assert_eq!((header.digest_length, Digest::from_manifest_header(&header)), (32, Some(Digest::Blake3)));
+        assert_eq!(Config::from_manifest_header(&header).to_string(), config.to_string());
+        assert_eq!(config.manifest_header(None).digest_length, 0);
+        assert_eq!(Digest::from_manifest_header(&config.manifest_header(None)), None);
+    }
+}
@@ -0,0 +1,115 @@
+//
+// Copyright (c) 2023 Florian Gäbler
+//
+
+//! The `split` subcommand, storing the chunks of a file as content-addressed
+//! chunk files and writing a manifest to join them back.
+
+use crate::digest::Digest;
+use crate::store::Store;
+use crate::{open_input, open_output, CliResult, Config};
+use clap::{Arg, ArgMatches, Command};
+use fastcdc_alt::{ManifestReader, ManifestWriter};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+///
+/// Counts of the chunks of a split, and of those that were not stored yet.
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SplitStats {
+    pub chunks: u64,
+    pub bytes: u64,
+    pub new_chunks: u64,
+    pub new_bytes: u64,
+}
+
+pub fn command() -> Command {
+    Command::new("split")
+        .about("Splits a file into content-addressed chunk files and writes a manifest.")
+        .args(Config::args())
+        .arg(
+            Arg::new("store")
+                .long("store")
+                .value_name("DIR")
+                .help("Directory of the chunk files")
+                .required(true),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("MANIFEST")
+                .help("Manifest file to write, stdout if -")
+                .default_value("-"),
+        )
+        .arg(
+            Arg::new("digest")
+                .short('d')
+                .long("digest")
+                .help("Digest naming the chunk files")
+                .default_value("sha256")
+                .value_parser(Digest::NAMES),
+        )
+        .arg(
+            Arg::new("like")
+                .long("like")
+                .value_name("MANIFEST")
+                .help("Use the parameters and digest of an earlier manifest, so unchanged chunks are reused")
+                .conflicts_with_all(["min", "avg", "max", "level", "seed", "digest"]),
+        )
+        .arg(
+            Arg::new("FILE")
+                .help("File to split, stdin if none or -")
+                .default_value("-"),
+        )
+}
+
+pub fn run(matches: &ArgMatches) -> CliResult {
+    let (config, digest) = match matches.get_one::<String>("like") {
+        Some(path) => {
+            let file = File::open(path).map_err(|error| format!("{}: {}", path, error))?;
+            let manifest = ManifestReader::new(BufReader::new(file)).map_err(|error| format!("{}: {}", path, error))?;
+            let digest = Digest::from_manifest_header(manifest.header()).ok_or_else(|| format!("{}: manifest without chunk digests", path))?;
+            (Config::from_manifest_header(manifest.header()), digest)
+        }
+        None => (
+            Config::from_matches(matches),
+            Digest::from_name(matches.get_one::<String>("digest").unwrap()).unwrap(),
+        ),
+    };
+    let store = Store::new(matches.get_one::<String>("store").unwrap());
+    let file = matches.get_one::<String>("FILE").unwrap();
+    let source = open_input(file).map_err(|error| format!("{}: {}", file, error))?;
+    let path = matches.get_one::<String>("output").unwrap();
+    let output = open_output(path).map_err(|error| format!("{}: {}", path, error))?;
+
+    let stats = split(&config, digest, &store, source, BufWriter::new(output))?;
+    eprintln!(
+        "chunks={} bytes={} new_chunks={} new_bytes={}",
+        stats.chunks, stats.bytes, stats.new_chunks, stats.new_bytes
+    );
+    Ok(())
+}
+
+///
+/// Store the chunks of the source that are not in the store yet and write the
+/// manifest of all chunks to the output.
+///
+pub fn split<R: Read, W: Write>(config: &Config, digest: Digest, store: &Store, source: R, output: W) -> Result<SplitStats, Box<dyn std::error::Error>> {
+    let mut manifest = ManifestWriter::new(output, config.manifest_header(Some(digest)))?;
+    let mut stats = SplitStats::default();
+    for result in config.stream(source)? {
+        let (data, chunk) = result?;
+        let chunk_digest = digest.compute(&data);
+        if store.put(&chunk_digest, &data)? {
+            stats.new_chunks += 1;
+            stats.new_bytes += data.len() as u64;
+        }
+        stats.chunks += 1;
+        stats.bytes += data.len() as u64;
+        manifest.write_chunk(&chunk, &chunk_digest)?;
+    }
+    manifest.finish()?;
+    Ok(stats)
+}
@@ -0,0 +1,57 @@
+//
+// Copyright (c) 2023 Florian Gäbler
+//
+
+//! Directory of content-addressed chunk files.
+
+use crate::digest::to_hex;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+///
+/// A directory holding chunk files named by the hex digest of their contents,
+/// fanned out into subdirectories by the first two digits.
+///
+pub struct Store {
+    root: PathBuf,
+}
+
+impl Store {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self { root: root.as_ref().to_path_buf() }
+    }
+
+    ///
+    /// The path of the chunk file with the given digest.
+    ///
+    pub fn path(&self, digest: &[u8]) -> PathBuf {
+        let hex = to_hex(digest);
+        self.root.join(&hex[..2]).join(hex)
+    }
+
+    ///
+    /// Store the chunk unless a chunk with the digest already exists.\
+    /// Returns whether the chunk file was written. It is written to a temporary file
+    /// first, so an interrupted write never leaves a partial chunk file.
+    ///
+    pub fn put(&self, digest: &[u8], data: &[u8]) -> io::Result<bool> {
+        let path = self.path(digest);
+        if path.exists() {
+            return Ok(false);
+        }
+        fs::create_dir_all(path.parent().unwrap())?;
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, data)?;
+        fs::rename(&temporary, &path)?;
+        Ok(true)
+    }
+
+    ///
+    /// Read the chunk with the given digest.
+    ///
+    pub fn get(&self, digest: &[u8]) -> io::Result<Vec<u8>> {
+        let path = self.path(digest);
+        fs::read(&path).map_err(|error| io::Error::new(error.kind(), format!("{}: {}", path.display(), error)))
+    }
+}
@@ -0,0 +1,249 @@
+//
+// Copyright (c) 2023 Florian Gäbler
+//
+
+//! C ABI over [FastCDC], enabled by the `capi` feature.
+//!
+//! The header `include/fastcdc_alt.h` is generated from this module by cbindgen,
+//! into `OUT_DIR` when building with the feature. A chunker is created with `fastcdc_new()`, fed
+//! with `fastcdc_set_content_length()` and `fastcdc_cut()` exactly like
+//! [FastCDC::set_content_length] and [FastCDC::cut], and released with `fastcdc_free()`.
+//! The cut points are identical to those of the Rust API.
+
+use crate::v2020::*;
+use std::slice;
+
+///
+/// Result of validating a chunker configuration.
+///
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FastCDCStatus {
+    /// The configuration is valid.
+    Ok = 0,
+    /// The minimum size is below `MINIMUM_MIN`.
+    MinimumTooLow,
+    /// The minimum size is above `MINIMUM_MAX`.
+    MinimumTooHigh,
+    /// The average size is below `AVERAGE_MIN`.
+    AverageTooLow,
+    /// The average size is above `AVERAGE_MAX`.
+    AverageTooHigh,
+    /// The maximum size is below `MAXIMUM_MIN`.
+    MaximumTooLow,
+    /// The maximum size is above `MAXIMUM_MAX`.
+    MaximumTooHigh,
+    /// The normalization level is not between 0 and 3.
+    InvalidLevel,
+    /// A required pointer is null.
+    NullPointer,
+}
+
+///
+/// A chunk identified by `fastcdc_cut()`, with the fields of [Chunk].
+///
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FastCDCChunk {
+    /// The gear hash value as of the end of the chunk.
+    pub hash: u64,
+    /// The offset of the chunk start relative to the passed buffer, zero or negative.
+    pub offset: isize,
+    /// The cut point relative to the passed buffer, i.e. the chunk ends before this byte.
+    pub cutpoint: usize,
+    /// Whether the chunk consists only of zero bytes.
+    pub zero: bool,
+}
+
+fn normalization(level: u32) -> Option<Normalization> {
+    match level {
+        0 => Some(Normalization::Level0),
+        1 => Some(Normalization::Level1),
+        2 => Some(Normalization::Level2),
+        3 => Some(Normalization::Level3),
+        _ => None,
+    }
+}
+
+///
+/// Check the chunk sizes and normalization level against the limits of [FastCDC::new_advanced].
+///
+#[no_mangle]
+pub extern "C" fn fastcdc_validate(min_size: u32, avg_size: u32, max_size: u32, level: u32) -> FastCDCStatus {
+    if min_size < MINIMUM_MIN {
+        FastCDCStatus::MinimumTooLow
+    } else if min_size > MINIMUM_MAX {
+        FastCDCStatus::MinimumTooHigh
+    } else if avg_size < AVERAGE_MIN {
+        FastCDCStatus::AverageTooLow
+    } else if avg_size > AVERAGE_MAX {
+        FastCDCStatus::AverageTooHigh
+    } else if max_size < MAXIMUM_MIN {
+        FastCDCStatus::MaximumTooLow
+    } else if max_size > MAXIMUM_MAX {
+        FastCDCStatus::MaximumTooHigh
+    } else if normalization(level).is_none() {
+        FastCDCStatus::InvalidLevel
+    } else {
+        FastCDCStatus::Ok
+    }
+}
+
+///
+/// Create a chunker with the given chunk sizes and normalization level and store it
+/// in `chunker`, which is left untouched if the configuration is invalid.\
+/// The content length is zero until set with `fastcdc_set_content_length()`.
+///
+/// # Safety
+///
+/// `chunker` must be null or valid for writes.
+///
+#[no_mangle]
+pub unsafe extern "C" fn fastcdc_new(
+    min_size: u32,
+    avg_size: u32,
+    max_size: u32,
+    level: u32,
+    chunker: *mut *mut FastCDC
+) -> FastCDCStatus {
+    if chunker.is_null() {
+        return FastCDCStatus::NullPointer;
+    }
+
+    let status = fastcdc_validate(min_size, avg_size, max_size, level);
+    if let (FastCDCStatus::Ok, Some(level)) = (status, normalization(level)) {
+        if let Ok(inner) = FastCDC::new_advanced(min_size, avg_size, max_size, level, None) {
+            *chunker = Box::into_raw(Box::new(inner));
+        }
+    }
+
+    status
+}
+
+///
+/// Release a chunker created by `fastcdc_new()`. Passing null does nothing.
+///
+/// # Safety
+///
+/// `chunker` must be null or a chunker created by `fastcdc_new()` that was not released yet.
+///
+#[no_mangle]
+pub unsafe extern "C" fn fastcdc_free(chunker: *mut FastCDC) {
+    if !chunker.is_null() {
+        drop(Box::from_raw(chunker));
+    }
+}
+
+///
+/// Set the length of the content to chunk and reset the chunker, see [FastCDC::set_content_length].
+///
+/// # Safety
+///
+/// `chunker` must be null or a valid chunker created by `fastcdc_new()`.
+///
+#[no_mangle]
+pub unsafe extern "C" fn fastcdc_set_content_length(chunker: *mut FastCDC, length: usize) -> FastCDCStatus {
+    match chunker.as_mut() {
+        Some(chunker) => {
+            chunker.set_content_length(length);
+            FastCDCStatus::Ok
+        }
+        None => FastCDCStatus::NullPointer,
+    }
+}
+
+///
+/// Try to identify the next cut point in the buffer, see [FastCDC::cut].\
+/// Returns `true` and stores the chunk in `chunk` if a chunk has been identified,
+/// and `false` if more data or a new content length is needed.
+///
+/// # Safety
+///
+/// `chunker` must be a valid chunker created by `fastcdc_new()`, `data` must be
+/// valid for reads of `length` bytes, or may be null if `length` is zero, and
+/// `chunk` must be valid for writes.
+///
+#[no_mangle]
+pub unsafe extern "C" fn fastcdc_cut(
+    chunker: *mut FastCDC,
+    data: *const u8,
+    length: usize,
+    chunk: *mut FastCDCChunk
+) -> bool {
+    let chunker = match chunker.as_mut() {
+        Some(chunker) => chunker,
+        None => return false,
+    };
+    if chunk.is_null() || (data.is_null() && length > 0) {
+        return false;
+    }
+
+    let buffer = if length == 0 { &[] } else { slice::from_raw_parts(data, length) };
+    match chunker.cut(buffer) {
+        Some(found) => {
+            *chunk = FastCDCChunk {
+                hash: found.hash,
+                offset: found.offset,
+                cutpoint: found.cutpoint,
+                zero: found.zero,
+            };
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v2020::vectors::SEKIEN_16K_CHUNKS;
+    use std::fs;
+    use std::ptr;
+
+    #[test]
+    fn test_capi_validate() {
+        assert_eq!(fastcdc_validate(4096, 16384, 65535, 1), FastCDCStatus::Ok);
+        assert_eq!(fastcdc_validate(32, 16384, 65535, 1), FastCDCStatus::MinimumTooLow);
+        assert_eq!(fastcdc_validate(4096, 128, 65535, 1), FastCDCStatus::AverageTooLow);
+        assert_eq!(fastcdc_validate(4096, 16384, MAXIMUM_MAX + 1, 1), FastCDCStatus::MaximumTooHigh);
+        assert_eq!(fastcdc_validate(4096, 16384, 65535, 4), FastCDCStatus::InvalidLevel);
+
+        let mut chunker = ptr::null_mut();
+        unsafe {
+            assert_eq!(fastcdc_new(4096, 16384, 65535, 4, &mut chunker), FastCDCStatus::InvalidLevel);
+            assert_eq!(fastcdc_new(4096, 16384, 65535, 1, ptr::null_mut()), FastCDCStatus::NullPointer);
+        }
+        assert!(chunker.is_null());
+    }
+
+    #[test]
+    fn test_capi_sekien_16k_chunks() {
+        let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        unsafe {
+            let mut chunker = ptr::null_mut();
+            assert_eq!(fastcdc_new(4096, 16384, 65535, 1, &mut chunker), FastCDCStatus::Ok);
+            assert_eq!(fastcdc_set_content_length(chunker, contents.len()), FastCDCStatus::Ok);
+
+            // Feed the content in pieces to cover the incremental cut.
+            let mut chunks = Vec::new();
+            let mut chunk = FastCDCChunk { hash: 0, offset: 0, cutpoint: 0, zero: false };
+            let mut start = 0;
+            let mut cursor = 0;
+            while cursor < contents.len() {
+                let end = (cursor + 10000).min(contents.len());
+                if fastcdc_cut(chunker, contents[cursor..].as_ptr(), end - cursor, &mut chunk) {
+                    let length = (cursor + chunk.cutpoint) - start;
+                    chunks.push((chunk.hash, length));
+                    cursor += chunk.cutpoint;
+                    start = cursor;
+                } else {
+                    cursor = end;
+                }
+            }
+            assert!(!fastcdc_cut(chunker, ptr::null(), 0, &mut chunk));
+            fastcdc_free(chunker);
+
+            assert_eq!(chunks, SEKIEN_16K_CHUNKS);
+        }
+    }
+}
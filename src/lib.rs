@@ -79,12 +79,33 @@
 //! a suitable approach. It allocate a byte vector equal to the maximum
 //! chunk size, draining and resizing the vector as chunks are found. However,
 //! using a crate such as `memmap2` can be significantly faster than the streaming
-//! chunker. See the examples in the `examples` directory for how to use the
+//! chunker. With the `mmap` feature enabled, the `MmapCDC` chunker (see `chunk_file()`)
+//! takes care of mapping the file in windows and returns chunks borrowing the mapping.
+//! See the examples in the `examples` directory for how to use the
 //! streaming versions as-is, versus the non-streaming chunkers which read from a
 //! memory-mapped file.
 //! Also consider directly leveraging the `cut()` method of the FastCDC struct to
 //! manually implement a streaming functionality.
+//!
+//! ## no_std
+//!
+//! The `FastCDC` chunker, `Chunk` and `Normalization` only need `alloc` and can be
+//! used in `no_std` environments by disabling the default `std` feature. The
+//! `StreamCDC` and the other chunkers reading from I/O sources, the parallel
+//! chunkers and the conversions from and to `std::io::Error` require `std`.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+// The tests read their fixtures with `std` even without the `std` feature.
+#[cfg(all(test, not(feature = "std")))]
+extern crate std;
 
 pub mod v2020;
 
+#[cfg(feature = "capi")]
+pub mod capi;
+
+#[cfg(feature = "python")]
+pub mod python;
+
 pub use v2020::*;
\ No newline at end of file
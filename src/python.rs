@@ -0,0 +1,346 @@
+//
+// Copyright (c) 2023 Florian Gäbler
+//
+
+//! Python bindings, enabled by the `python` feature.
+//!
+//! The extension module `fastcdc_alt` is built with maturin (see `pyproject.toml`)
+//! and offers the [FastCDC] chunker, the [StreamCDC] over Python file objects and
+//! statistics over the chunk sizes. The GIL is released while the chunks are found.
+//! Offsets, lengths and hashes are returned as `array.array('Q')` objects, which
+//! `numpy.asarray()` wraps without copying.
+
+// The code generated by pyo3 for functions returning a PyResult triggers this lint.
+#![allow(clippy::useless_conversion)]
+
+use crate::v2020::{self, FastCDC as RustFastCDC, Normalization, StreamCDC as RustStreamCDC};
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyByteArray, PyBytes, PyDict};
+use std::io::{self, Read};
+
+fn to_py_error(error: v2020::Error) -> PyErr {
+    match error {
+        v2020::Error::IoError(error) => PyIOError::new_err(error.to_string()),
+        error => PyValueError::new_err(error.to_string()),
+    }
+}
+
+fn normalization(level: u32) -> PyResult<Normalization> {
+    match level {
+        0 => Ok(Normalization::Level0),
+        1 => Ok(Normalization::Level1),
+        2 => Ok(Normalization::Level2),
+        3 => Ok(Normalization::Level3),
+        _ => Err(PyValueError::new_err(format!("{} is not a normalization level between 0 and 3", level))),
+    }
+}
+
+/// Create an `array.array('Q')` holding the values.
+fn u64_array(py: Python<'_>, values: &[u64]) -> PyResult<PyObject> {
+    let bytes = values.iter().flat_map(|value| value.to_ne_bytes()).collect::<Vec<_>>();
+    let array = py.import_bound("array")?.getattr("array")?;
+    Ok(array.call1(("Q", PyBytes::new_bound(py, &bytes)))?.unbind())
+}
+
+///
+/// A chunk as returned by `FastCDC.cut()` and `StreamCDC`, see [v2020::Chunk].
+///
+#[pyclass(name = "Chunk", module = "fastcdc_alt", frozen, get_all)]
+#[derive(Clone)]
+pub struct PyChunk {
+    hash: u64,
+    offset: isize,
+    cutpoint: usize,
+    zero: bool,
+}
+
+#[pymethods]
+impl PyChunk {
+    /// The length of the chunk.
+    #[getter]
+    fn length(&self) -> isize {
+        self.cutpoint as isize - self.offset
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Chunk(hash={}, offset={}, cutpoint={}, zero={})", self.hash, self.offset, self.cutpoint, if self.zero { "True" } else { "False" })
+    }
+}
+
+impl From<v2020::Chunk> for PyChunk {
+    fn from(chunk: v2020::Chunk) -> Self {
+        Self {
+            hash: chunk.hash,
+            offset: chunk.offset,
+            cutpoint: chunk.cutpoint,
+            zero: chunk.zero,
+        }
+    }
+}
+
+///
+/// The chunks of a buffer as returned by `FastCDC.chunk()`, with the offsets,
+/// lengths and hashes as `array.array('Q')` objects.
+///
+#[pyclass(name = "Chunks", module = "fastcdc_alt", frozen)]
+pub struct PyChunks {
+    offsets: Vec<u64>,
+    lengths: Vec<u64>,
+    hashes: Vec<u64>,
+}
+
+#[pymethods]
+impl PyChunks {
+    #[getter]
+    fn offsets(&self, py: Python<'_>) -> PyResult<PyObject> {
+        u64_array(py, &self.offsets)
+    }
+
+    #[getter]
+    fn lengths(&self, py: Python<'_>) -> PyResult<PyObject> {
+        u64_array(py, &self.lengths)
+    }
+
+    #[getter]
+    fn hashes(&self, py: Python<'_>) -> PyResult<PyObject> {
+        u64_array(py, &self.hashes)
+    }
+
+    /// Statistics over the chunk lengths, see `statistics()`.
+    fn statistics<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        statistics(py, self.lengths.clone())
+    }
+
+    fn __len__(&self) -> usize {
+        self.lengths.len()
+    }
+}
+
+///
+/// The FastCDC chunker, see [FastCDC](RustFastCDC).\
+/// Use `set_content_length()` and `cut()` for incremental chunking, or `chunk()`
+/// for a whole buffer.
+///
+#[pyclass(name = "FastCDC", module = "fastcdc_alt")]
+pub struct PyFastCDC {
+    inner: RustFastCDC,
+}
+
+#[pymethods]
+impl PyFastCDC {
+    #[new]
+    #[pyo3(signature = (min_size, avg_size, max_size, level = 1, content_length = None))]
+    fn new(min_size: u32, avg_size: u32, max_size: u32, level: u32, content_length: Option<usize>) -> PyResult<Self> {
+        let inner = RustFastCDC::new_advanced(min_size, avg_size, max_size, normalization(level)?, content_length)
+            .map_err(to_py_error)?;
+
+        Ok(Self { inner })
+    }
+
+    /// Set the length of the content to chunk, see [FastCDC::set_content_length](RustFastCDC::set_content_length).
+    fn set_content_length(&mut self, length: usize) {
+        self.inner.set_content_length(length);
+    }
+
+    /// Try to identify the next cut point, see [FastCDC::cut](RustFastCDC::cut).\
+    /// Returns `None` if more data or a new content length is needed.
+    fn cut(&mut self, py: Python<'_>, buffer: &[u8]) -> Option<PyChunk> {
+        let inner = &mut self.inner;
+        py.allow_threads(|| inner.cut(buffer)).map(PyChunk::from)
+    }
+
+    /// Find all chunks of the buffer, resetting the content length to its length.
+    fn chunk(&mut self, py: Python<'_>, buffer: &[u8]) -> PyChunks {
+        let inner = &mut self.inner;
+        py.allow_threads(|| {
+            let mut chunks = PyChunks {
+                offsets: Vec::new(),
+                lengths: Vec::new(),
+                hashes: Vec::new(),
+            };
+            for chunk in inner.as_iterator(buffer) {
+                chunks.offsets.push(chunk.offset as u64);
+                chunks.lengths.push(chunk.get_length() as u64);
+                chunks.hashes.push(chunk.hash);
+            }
+            chunks
+        })
+    }
+}
+
+/// Reads from a Python file object with a `readinto()` or `read()` method.
+struct PyReader {
+    file: PyObject,
+    /// Buffer passed to `readinto`, reused across reads.
+    buffer: Option<Py<PyByteArray>>,
+}
+
+impl Read for PyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Python::with_gil(|py| {
+            let file = self.file.bind(py);
+            let result = if file.hasattr("readinto")? {
+                let target = match &self.buffer {
+                    Some(buffer) => buffer.bind(py).clone(),
+                    None => PyByteArray::new_bound(py, &[]),
+                };
+                if target.len() != buf.len() {
+                    target.resize(buf.len())?;
+                }
+                let count = file.call_method1("readinto", (&target,))?.extract::<Option<usize>>()?.unwrap_or(0);
+                let count = count.min(buf.len()).min(target.len());
+                // No Python code runs while the contents are borrowed.
+                buf[..count].copy_from_slice(unsafe { &target.as_bytes()[..count] });
+                self.buffer = Some(target.unbind());
+                count
+            } else {
+                let data = file.call_method1("read", (buf.len(),))?;
+                let data = data.extract::<&[u8]>()?;
+                let count = data.len().min(buf.len());
+                buf[..count].copy_from_slice(&data[..count]);
+                count
+            };
+            Ok(result)
+        }).map_err(|error: PyErr| io::Error::other(error.to_string()))
+    }
+}
+
+///
+/// The streaming chunker over a binary Python file object, see [StreamCDC](RustStreamCDC).\
+/// Iterating yields `(data, chunk)` tuples.
+///
+#[pyclass(name = "StreamCDC", module = "fastcdc_alt")]
+pub struct PyStreamCDC {
+    inner: RustStreamCDC<PyReader>,
+}
+
+#[pymethods]
+impl PyStreamCDC {
+    #[new]
+    #[pyo3(signature = (file, min_size, avg_size, max_size, level = 1))]
+    fn new(file: PyObject, min_size: u32, avg_size: u32, max_size: u32, level: u32) -> PyResult<Self> {
+        let inner = RustStreamCDC::new_advanced(PyReader { file, buffer: None }, min_size, avg_size, max_size, normalization(level)?)
+            .map_err(to_py_error)?;
+
+        Ok(Self { inner })
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__<'py>(&mut self, py: Python<'py>) -> PyResult<Option<(Bound<'py, PyBytes>, PyChunk)>> {
+        let inner = &mut self.inner;
+        match py.allow_threads(|| inner.next()) {
+            Some(Ok((data, chunk))) => Ok(Some((PyBytes::new_bound(py, &data), chunk.into()))),
+            Some(Err(error)) => Err(to_py_error(error)),
+            None => Ok(None),
+        }
+    }
+}
+
+///
+/// Statistics over chunk lengths: the number of chunks, the total, minimum, maximum
+/// and mean length and the standard deviation of the lengths.
+///
+#[pyfunction]
+fn statistics(py: Python<'_>, lengths: Vec<u64>) -> PyResult<Bound<'_, PyDict>> {
+    let count = lengths.len();
+    let total = lengths.iter().sum::<u64>();
+    let mean = if count > 0 { total as f64 / count as f64 } else { 0.0 };
+    let variance = if count > 0 {
+        lengths.iter().map(|&length| (length as f64 - mean).powi(2)).sum::<f64>() / count as f64
+    } else {
+        0.0
+    };
+
+    let result = PyDict::new_bound(py);
+    result.set_item("count", count)?;
+    result.set_item("total", total)?;
+    result.set_item("min", lengths.iter().min().copied().unwrap_or(0))?;
+    result.set_item("max", lengths.iter().max().copied().unwrap_or(0))?;
+    result.set_item("mean", mean)?;
+    result.set_item("stddev", variance.sqrt())?;
+    Ok(result)
+}
+
+/// The `fastcdc_alt` Python module.
+#[pymodule]
+fn fastcdc_alt(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyChunk>()?;
+    module.add_class::<PyChunks>()?;
+    module.add_class::<PyFastCDC>()?;
+    module.add_class::<PyStreamCDC>()?;
+    module.add_function(wrap_pyfunction!(statistics, module)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::PyModule;
+
+    fn run(script: &str) {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let module = PyModule::new_bound(py, "fastcdc_alt").unwrap();
+            fastcdc_alt(&module).unwrap();
+            let globals = PyDict::new_bound(py);
+            globals.set_item("fastcdc_alt", module).unwrap();
+            if let Err(error) = py.run_bound(script, Some(&globals), None) {
+                error.print(py);
+                panic!("python test failed");
+            }
+        });
+    }
+
+    #[test]
+    fn test_python_chunk_vectors() {
+        run(r#"
+contents = open("test/fixtures/SekienAkashita.jpg", "rb").read()
+chunks = fastcdc_alt.FastCDC(4096, 16384, 65535).chunk(contents)
+assert len(chunks) == 5
+assert chunks.offsets.typecode == "Q"
+assert list(chunks.offsets) == [0, 21325, 38465, 66549, 84766]
+assert list(chunks.lengths) == [21325, 17140, 28084, 18217, 24700]
+assert list(chunks.hashes) == [17968276318003433923, 8197189939299398838, 13019990849178155730, 4509236223063678303, 2504464741100432583]
+
+chunks = fastcdc_alt.FastCDC(8192, 16384, 32768, level=3).chunk(contents)
+assert list(chunks.lengths) == [17350, 19911, 17426, 17519, 19940, 17320]
+assert list(chunks.hashes)[0] == 10718006254707412376
+
+stats = chunks.statistics()
+assert stats["count"] == 6 and stats["total"] == len(contents)
+assert stats["min"] == 17320 and stats["max"] == 19940
+"#);
+    }
+
+    #[test]
+    fn test_python_cut_and_stream() {
+        run(r#"
+import io
+contents = open("test/fixtures/SekienAkashita.jpg", "rb").read()
+expected = [(17968276318003433923, 21325), (8197189939299398838, 17140), (13019990849178155730, 28084), (4509236223063678303, 18217), (2504464741100432583, 24700)]
+
+chunker = fastcdc_alt.FastCDC(4096, 16384, 65535)
+chunker.set_content_length(len(contents))
+cursor = 0
+found = []
+while cursor < len(contents):
+    chunk = chunker.cut(contents[cursor:])
+    found.append((chunk.hash, chunk.length))
+    cursor += chunk.cutpoint
+assert found == expected
+
+streamed = [(chunk.hash, len(data)) for data, chunk in fastcdc_alt.StreamCDC(io.BytesIO(contents), 4096, 16384, 65535)]
+assert streamed == expected
+
+try:
+    fastcdc_alt.FastCDC(32, 16384, 65535)
+    assert False
+except ValueError:
+    pass
+"#);
+    }
+}
@@ -71,6 +71,8 @@ pub struct AsyncStreamCDC<R> {
     processed: usize,
     /// True when the source produces no more data.
     eof: bool,
+    /// Forced cut points and the veto callback.
+    cuts: StreamCuts,
 }
 
 impl<R: AsyncRead + Unpin> AsyncStreamCDC<R> {
@@ -101,9 +103,39 @@ impl<R: AsyncRead + Unpin> AsyncStreamCDC<R> {
             source,
             processed: 0,
             eof: false,
+            cuts: StreamCuts::default(),
         })
     }
 
+    ///
+    /// Use a gear table generated from the given seed, see [FastCDC::set_gear_seed].
+    ///
+    pub fn set_gear_seed(&mut self, seed: u64) {
+        self.inner.set_gear_seed(seed)
+    }
+
+    ///
+    /// Force a cut point at the given offset relative to the start of the stream,
+    /// see [StreamCDC::force_cut].
+    ///
+    pub fn force_cut(&mut self, offset: usize) {
+        self.cuts.forced.insert(offset);
+    }
+
+    ///
+    /// Remove all pending forced cut points.
+    ///
+    pub fn clear_forced_cuts(&mut self) {
+        self.cuts.forced.clear();
+    }
+
+    ///
+    /// Reject candidate cut points with the given callback, see [StreamCDC::set_veto].
+    ///
+    pub fn set_veto<V: FnMut(usize) -> bool + Send + 'static>(&mut self, veto: V) {
+        self.cuts.veto = Some(Box::new(veto));
+    }
+
     /// Fill the buffer with data from the source, returning the number of bytes
     /// read (zero if end of source has been reached).
     async fn fill_buffer(&mut self) -> Result<usize, Error> {
@@ -149,16 +181,15 @@ impl<R: AsyncRead + Unpin> AsyncStreamCDC<R> {
         if self.length == 0 {
             Err(Error::Empty)
         } else {
-            self.inner.set_content_length(self.length);
-
-            let chunk = self.inner.cut(&self.buffer[..self.length]).ok_or(Error::Empty)?;
+            let chunk = self.cuts.cut(&mut self.inner, self.processed, &self.buffer[..self.length]).ok_or(Error::Empty)?;
             let data = self.drain_bytes(chunk.cutpoint)?;
 
             let cutpoint = self.processed + chunk.cutpoint;
             let chunk = Chunk {
                 hash: chunk.hash,
                 offset: self.processed as isize,
-                cutpoint
+                cutpoint,
+                zero: chunk.zero
             };
 
             self.processed = cutpoint;
@@ -314,4 +345,30 @@ mod tests {
         }
         assert_eq!(index, 5);
     }
+    #[cfg_attr(all(feature = "tokio", not(feature = "futures")), tokio::test)]
+    #[cfg_attr(all(feature = "futures", not(feature = "tokio")), futures_test::test)]
+    async fn test_force_cut_and_veto() {
+        let contents = std::fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        let mut chunker = crate::v2020::FastCDC::new(4096, 16384, 65535).unwrap();
+        chunker.force_cut(80000);
+        chunker.set_content_length(contents.len());
+        let mut expected = Vec::new();
+        let mut cursor = 0;
+        while let Some(chunk) = chunker.cut_with_veto(&contents[cursor..], |offset| offset == 38465) {
+            expected.push((cursor as isize, cursor + chunk.cutpoint));
+            cursor += chunk.cutpoint;
+        }
+
+        let mut chunker = AsyncStreamCDC::new(contents.as_ref(), 4096, 16384, 65535).unwrap();
+        chunker.force_cut(80000);
+        chunker.set_veto(|offset| offset == 38465);
+        let chunks = chunker.as_stream().collect::<Vec<_>>().await;
+        let chunks: Vec<(isize, usize)> = chunks
+            .into_iter()
+            .map(|result| result.unwrap().1)
+            .map(|chunk| (chunk.offset, chunk.cutpoint))
+            .collect();
+        assert_eq!(chunks, expected);
+        assert!(chunks.iter().any(|&(_, cutpoint)| cutpoint == 80000));
+    }
 }
@@ -0,0 +1,201 @@
+//
+// Copyright (c) 2023 Florian Gäbler
+//
+
+use super::*;
+
+///
+/// A chunker whose sizes and normalization level are compile-time constants, for
+/// deployments that always use the same parameters.\
+/// \
+/// The parameters are validated at compile time against the same limits as
+/// [FastCDC::new_advanced()], and the masks are constants as well, so the compiler
+/// can fold the size comparisons and specialize the hashing loop for them.
+/// `LEVEL` is the normalization level from 0 to 3, see [Normalization].\
+/// \
+/// The chunks are identical to those of a [FastCDC] with the same parameters. Only
+/// whole buffers can be chunked, for streams use [StreamCDC].
+///
+/// ```no_run
+/// # use fastcdc_alt::FastCDCConst;
+/// let contents = std::fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+/// let chunker = FastCDCConst::<4096, 16384, 65535, 1>::new();
+/// for chunk in chunker.as_iterator(&contents) {
+///     println!("offset={} size={}", chunk.offset, chunk.get_length());
+/// }
+/// ```
+///
+/// Invalid parameters fail to compile:
+/// ```compile_fail
+/// # use fastcdc_alt::FastCDCConst;
+/// let chunker = FastCDCConst::<32, 16384, 65535, 1>::new();
+/// ```
+///
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FastCDCConst<const MIN: u32, const AVG: u32, const MAX: u32, const LEVEL: u32>;
+
+impl<const MIN: u32, const AVG: u32, const MAX: u32, const LEVEL: u32> FastCDCConst<MIN, AVG, MAX, LEVEL> {
+    const VALID: () = {
+        assert!(MIN >= MINIMUM_MIN, "min_size < MINIMUM_MIN");
+        assert!(MIN <= MINIMUM_MAX, "min_size > MINIMUM_MAX");
+        assert!(AVG >= AVERAGE_MIN, "avg_size < AVERAGE_MIN");
+        assert!(AVG <= AVERAGE_MAX, "avg_size > AVERAGE_MAX");
+        assert!(MAX >= MAXIMUM_MIN, "max_size < MAXIMUM_MIN");
+        assert!(MAX <= MAXIMUM_MAX, "max_size > MAXIMUM_MAX");
+        assert!(LEVEL <= 3, "normalization level > 3");
+    };
+
+    const MIN_SIZE: usize = MIN as usize;
+    const AVG_SIZE: usize = AVG as usize;
+    const MAX_SIZE: usize = MAX as usize;
+    const MASK_S: u64 = MASKS[(logarithm2(AVG) + LEVEL) as usize];
+    const MASK_L: u64 = MASKS[(logarithm2(AVG) - LEVEL) as usize];
+
+    ///
+    /// Construct a `FastCDCConst`, failing to compile if the parameters are invalid.
+    ///
+    pub const fn new() -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::VALID;
+        Self
+    }
+
+    ///
+    /// Find the first chunk of the buffer, taking the buffer as the whole remaining content.\
+    /// Returns `None` if the buffer is empty.
+    ///
+    pub fn cut(&self, buffer: &[u8]) -> Option<Chunk> {
+        let mut remaining = buffer.len();
+        if remaining == 0 {
+            return None;
+        }
+
+        let mut center = Self::AVG_SIZE;
+        if remaining > Self::MAX_SIZE {
+            remaining = Self::MAX_SIZE;
+        } else if remaining < center {
+            center = remaining;
+        }
+
+        if remaining < Self::MIN_SIZE {
+            return Some(Self::chunk(buffer, 0, remaining));
+        }
+
+        let first = Self::MIN_SIZE / 2;
+        let end = remaining / 2;
+        let switch = if center / 2 > first { (center / 2).min(end) } else { end };
+
+        let mut hash = 0;
+        let found = scan_pairs(&DEFAULT_GEAR, &buffer[2 * first..2 * switch], &mut hash, Self::MASK_S, Self::MASK_S << 1, |_| false)
+            .map(|offset| 2 * first + offset)
+            .or_else(|| {
+                scan_pairs(&DEFAULT_GEAR, &buffer[2 * switch..2 * end], &mut hash, Self::MASK_L, Self::MASK_L << 1, |_| false)
+                    .map(|offset| 2 * switch + offset)
+            });
+
+        Some(Self::chunk(buffer, hash, found.unwrap_or(remaining)))
+    }
+
+    ///
+    /// Construct a [FastCDCConstIterator] over the chunks of the buffer.
+    ///
+    pub fn as_iterator<'a>(&self, buffer: &'a [u8]) -> FastCDCConstIterator<'a, MIN, AVG, MAX, LEVEL> {
+        FastCDCConstIterator {
+            inner: *self,
+            cursor: 0,
+            buffer
+        }
+    }
+
+    fn chunk(buffer: &[u8], hash: u64, cutpoint: usize) -> Chunk {
+        let data = &buffer[..cutpoint];
+        Chunk {
+            hash,
+            offset: 0,
+            cutpoint,
+            zero: data[0] == 0 && zero_prefix(data) == data.len()
+        }
+    }
+}
+
+///
+/// Iterator over the chunks of a buffer, see [FastCDCConst::as_iterator()].\
+/// The offsets of the chunks are relative to the start of the buffer.
+///
+pub struct FastCDCConstIterator<'a, const MIN: u32, const AVG: u32, const MAX: u32, const LEVEL: u32> {
+    inner: FastCDCConst<MIN, AVG, MAX, LEVEL>,
+    cursor: usize,
+    buffer: &'a [u8]
+}
+
+impl<const MIN: u32, const AVG: u32, const MAX: u32, const LEVEL: u32> Iterator for FastCDCConstIterator<'_, MIN, AVG, MAX, LEVEL> {
+    type Item = Chunk;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let chunk = self.inner.cut(&self.buffer[self.cursor..])?;
+        let offset = self.cursor;
+        self.cursor += chunk.cutpoint;
+
+        Some(Chunk {
+            hash: chunk.hash,
+            offset: offset as isize,
+            cutpoint: self.cursor,
+            zero: chunk.zero
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::vectors::*;
+    use std::fs;
+
+    fn assert_chunks<const MIN: u32, const AVG: u32, const MAX: u32, const LEVEL: u32>(contents: &[u8], expected: &[(u64, usize)]) {
+        let chunker = FastCDCConst::<MIN, AVG, MAX, LEVEL>::new();
+        let chunks = chunker.as_iterator(contents).map(|chunk| (chunk.hash, chunk.get_length())).collect::<Vec<_>>();
+        assert_eq!(chunks, expected);
+    }
+
+    #[test]
+    fn test_const_masks() {
+        let chunker = FastCDC::new_advanced(4096, 16384, 65535, Normalization::Level2, None).unwrap();
+        assert_eq!(FastCDCConst::<4096, 16384, 65535, 2>::MASK_S, chunker.mask_s);
+        assert_eq!(FastCDCConst::<4096, 16384, 65535, 2>::MASK_L, chunker.mask_l);
+    }
+
+    #[test]
+    fn test_const_sekien_vectors() {
+        let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        assert_chunks::<4096, 16384, 65535, 1>(&contents, &SEKIEN_16K_CHUNKS);
+        assert_chunks::<8192, 32768, 131072, 1>(&contents, &SEKIEN_32K_CHUNKS);
+        assert_chunks::<16384, 65536, 262144, 1>(&contents, &SEKIEN_64K_CHUNKS);
+        assert_chunks::<4096, 16384, 65535, 0>(&contents, &SEKIEN_16K_NC_0_CHUNKS);
+        assert_chunks::<8192, 16384, 32768, 3>(&contents, &SEKIEN_16K_NC_3_CHUNKS);
+    }
+
+    #[test]
+    fn test_const_matches_runtime() {
+        let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        let mut mixed = vec![0u8; 5000];
+        mixed.extend_from_slice(&contents[..30000]);
+        mixed.extend_from_slice(&[0u8; 70000]);
+        mixed.extend_from_slice(&contents[50000..]);
+
+        let mut chunker = FastCDC::new(64, 256, 1024).unwrap();
+        let expected = chunker.as_iterator(&mixed).collect::<Vec<_>>();
+        assert_eq!(FastCDCConst::<64, 256, 1024, 1>::new().as_iterator(&mixed).collect::<Vec<_>>(), expected);
+
+        let mut chunker = FastCDC::new(4096, 16384, 65535).unwrap();
+        let expected = chunker.as_iterator(&mixed).collect::<Vec<_>>();
+        assert_eq!(FastCDCConst::<4096, 16384, 65535, 1>::new().as_iterator(&mixed).collect::<Vec<_>>(), expected);
+
+        // Zero runs with an average that is not a power of two.
+        let mut chunker = FastCDC::new(3000, 10000, 40000).unwrap();
+        let expected = chunker.as_iterator(&mixed).collect::<Vec<_>>();
+        assert!(expected.iter().any(|chunk| chunk.zero));
+        assert_eq!(FastCDCConst::<3000, 10000, 40000, 1>::new().as_iterator(&mixed).collect::<Vec<_>>(), expected);
+
+        assert!(FastCDCConst::<4096, 16384, 65535, 1>::new().cut(&[]).is_none());
+    }
+}
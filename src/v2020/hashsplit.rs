@@ -0,0 +1,344 @@
+//
+// Copyright (c) 2023 Florian Gäbler
+//
+
+use super::*;
+
+///
+/// A group of consecutive chunks (or lower level superchunks) as produced by
+/// the [HashSplitter].\
+/// See the individual fields for more documentation.
+///
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct SuperChunk {
+    /// The level of this superchunk in the tree, starting at 1 for superchunks
+    /// that directly contain chunks.
+    pub level: u32,
+
+    /// The gear hash of the last chunk contained in this superchunk.
+    pub hash: u64,
+
+    /// The absolute offset at which this superchunk starts.
+    pub offset: usize,
+
+    /// The absolute cut point at which this superchunk ends.
+    pub cutpoint: usize,
+
+    /// The number of direct children, which are chunks for level 1 and
+    /// superchunks of the level below otherwise.
+    pub children: usize,
+
+    /// The total number of chunks covered by this superchunk.
+    pub chunks: usize,
+}
+
+impl SuperChunk {
+    #[inline(always)]
+    pub fn get_length(&self) -> usize {
+        self.cutpoint - self.offset
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct OpenNode {
+    hash: u64,
+    offset: usize,
+    cutpoint: usize,
+    children: usize,
+    chunks: usize,
+}
+
+///
+/// Assigns content-defined levels to chunks and groups them into a
+/// deterministic tree of [SuperChunk]s, similar to the hashsplit fanout of bup.\
+/// \
+/// The level of a chunk is derived from the zero bits of [Chunk::hash] beyond
+/// the bits judged by the masks of the [FastCDC] instance that produced it.
+/// Each `fanout_bits` consecutive zero bits raise the level by one, so on
+/// average every `2^fanout_bits`th boundary of a level is also a boundary of
+/// the level above. The level depends on the hash alone, so a chunk whose cut
+/// point was not determined by the data (the maximum size was reached, the data
+/// ended or the cut was forced) gets a level above 0 if its hash happens to pass
+/// the masks.\
+/// \
+/// Feed the chunks in order via [push()](HashSplitter::push) and call
+/// [finish()](HashSplitter::finish) after the last one. Superchunks are
+/// returned as soon as they are complete, with the root of the tree last.
+///
+/// ```no_run
+/// # use std::fs;
+/// # use fastcdc_alt::{FastCDC, HashSplitter};
+/// let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+/// let mut chunker = FastCDC::new(4096, 16384, 65535).unwrap();
+/// let mut splitter = HashSplitter::new(&chunker, 4).unwrap();
+///
+/// let mut superchunks = Vec::new();
+/// for chunk in chunker.as_iterator(&contents) {
+///     superchunks.extend(splitter.push(&chunk));
+/// }
+/// superchunks.extend(splitter.finish());
+///
+/// for superchunk in superchunks {
+///     println!("level={} offset={} size={}", superchunk.level, superchunk.offset, superchunk.get_length());
+/// }
+/// ```
+///
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct HashSplitter {
+    fanout_bits: u32,
+    shift: u32,
+    masks: [u64; 4],
+    /// The nodes under construction, where index 0 holds the level 1 node.
+    open: Vec<Option<OpenNode>>,
+    /// Number of bytes covered by the chunks pushed so far.
+    processed: usize,
+}
+
+impl HashSplitter {
+    ///
+    /// Create a new `HashSplitter` for chunks produced by the given chunker.
+    ///
+    /// The `fanout_bits` must be at least 1 and leave room for at least one
+    /// level in the hash bits that are not covered by the chunker masks.
+    ///
+    pub fn new(chunker: &FastCDC, fanout_bits: u32) -> Result<Self, Error> {
+        let masks = chunker.judged_masks();
+        let shift = chunker.free_bits_shift();
+
+        if fanout_bits == 0 {
+            return Err(Error::Other("fanout_bits must not be zero".to_string()))
+        } else if fanout_bits > 64 - shift {
+            return Err(Error::Other(format!("{} > {} (fanout_bits > free hash bits)", fanout_bits, 64 - shift)))
+        }
+
+        Ok(Self {
+            fanout_bits,
+            shift,
+            masks,
+            open: Vec::new(),
+            processed: 0,
+        })
+    }
+
+    ///
+    /// The highest level that can be assigned to a chunk.
+    ///
+    pub fn max_level(&self) -> u32 {
+        (64 - self.shift) / self.fanout_bits
+    }
+
+    ///
+    /// Determine the level of the given chunk.
+    ///
+    pub fn level(&self, chunk: &Chunk) -> u32 {
+        if chunk.hash == 0 || self.masks.iter().all(|mask| chunk.hash & mask != 0) {
+            return 0;
+        }
+
+        let zeros = (chunk.hash >> self.shift).trailing_zeros().min(64 - self.shift);
+        zeros / self.fanout_bits
+    }
+
+    ///
+    /// Add the next chunk to the tree and return the superchunks completed by it.\
+    /// \
+    /// Only the length of the chunk is taken into account for the offsets,
+    /// so chunks returned by [FastCDC::cut] can be pushed directly.
+    ///
+    pub fn push(&mut self, chunk: &Chunk) -> Vec<SuperChunk> {
+        let level = self.level(chunk);
+        let offset = self.processed;
+        self.processed += chunk.get_length();
+
+        self.add_child(0, chunk.hash, offset, 1);
+
+        let mut completed = Vec::new();
+        for index in 0..level as usize {
+            if let Some(superchunk) = self.close(index) {
+                completed.push(superchunk);
+            }
+        }
+
+        completed
+    }
+
+    ///
+    /// Complete the tree and return the remaining superchunks.\
+    /// The last returned superchunk is the root of the tree, unless the root
+    /// was already returned by [push()](HashSplitter::push).\
+    /// Afterwards the `HashSplitter` can be used for a new tree.
+    ///
+    pub fn finish(&mut self) -> Vec<SuperChunk> {
+        let mut completed = Vec::new();
+
+        let top = match self.open.iter().rposition(Option::is_some) {
+            Some(top) => top,
+            None => {
+                self.processed = 0;
+                return completed;
+            }
+        };
+
+        for index in 0..top {
+            if let Some(superchunk) = self.close(index) {
+                completed.push(superchunk);
+            }
+        }
+
+        // A single child on the top level is already the root.
+        let root = self.open[top].take().unwrap();
+        if root.children > 1 || top == 0 {
+            completed.push(SuperChunk {
+                level: top as u32 + 1,
+                hash: root.hash,
+                offset: root.offset,
+                cutpoint: root.cutpoint,
+                children: root.children,
+                chunks: root.chunks,
+            });
+        }
+
+        self.open.clear();
+        self.processed = 0;
+
+        completed
+    }
+
+    fn add_child(&mut self, index: usize, hash: u64, offset: usize, chunks: usize) {
+        if self.open.len() <= index {
+            self.open.resize(index + 1, None);
+        }
+
+        let node = self.open[index].get_or_insert(OpenNode {
+            hash,
+            offset,
+            cutpoint: offset,
+            children: 0,
+            chunks: 0,
+        });
+        node.hash = hash;
+        node.cutpoint = self.processed;
+        node.children += 1;
+        node.chunks += chunks;
+    }
+
+    fn close(&mut self, index: usize) -> Option<SuperChunk> {
+        let node = self.open.get_mut(index)?.take()?;
+        self.add_child(index + 1, node.hash, node.offset, node.chunks);
+
+        Some(SuperChunk {
+            level: index as u32 + 1,
+            hash: node.hash,
+            offset: node.offset,
+            cutpoint: node.cutpoint,
+            children: node.children,
+            chunks: node.chunks,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pseudo_random_bytes(seed: u64, length: usize) -> Vec<u8> {
+        let mut state = seed;
+        (0..length).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 32) as u8
+        }).collect()
+    }
+
+    fn split(fanout_bits: u32, contents: &[u8]) -> Vec<SuperChunk> {
+        let mut chunker = FastCDC::new(256, 1024, 8192).unwrap();
+        let mut splitter = HashSplitter::new(&chunker, fanout_bits).unwrap();
+        let mut superchunks = Vec::new();
+        for chunk in chunker.as_iterator(contents) {
+            superchunks.extend(splitter.push(&chunk));
+        }
+        superchunks.extend(splitter.finish());
+        superchunks
+    }
+
+    #[test]
+    fn test_fanout_bits_invalid() {
+        let chunker = FastCDC::new(4096, 16384, 65535).unwrap();
+        assert!(HashSplitter::new(&chunker, 0).is_err());
+        assert!(HashSplitter::new(&chunker, 16).is_err());
+        assert_eq!(HashSplitter::new(&chunker, 4).unwrap().max_level(), 3);
+    }
+
+    #[test]
+    fn test_level() {
+        let chunker = FastCDC::new(4096, 16384, 65535).unwrap();
+        let splitter = HashSplitter::new(&chunker, 2).unwrap();
+        let level = |hash| splitter.level(&Chunk { hash, offset: 0, cutpoint: 0, zero: false });
+        assert_eq!(level(0), 0);
+        assert_eq!(level(u64::MAX), 0);
+        assert_eq!(level(1 << 63), 7);
+        assert_eq!(level(1 << 53), 2);
+        assert_eq!(level(0x3), 7);
+    }
+
+    #[test]
+    fn test_tree_covers_content() {
+        let contents = pseudo_random_bytes(0x2545f4914f6cdd1d, 4 * 1024 * 1024);
+        let superchunks = split(2, &contents);
+
+        let root = superchunks.last().unwrap();
+        assert!(root.level > 1);
+        assert_eq!(root.offset, 0);
+        assert_eq!(root.cutpoint, contents.len());
+
+        for level in 1..=root.level {
+            let mut cursor = 0;
+            for superchunk in superchunks.iter().filter(|s| s.level == level) {
+                assert_eq!(superchunk.offset, cursor);
+                cursor = superchunk.cutpoint;
+            }
+            assert_eq!(cursor, contents.len());
+        }
+
+        let chunks = FastCDC::new(256, 1024, 8192).unwrap().as_iterator(&contents).count();
+        assert_eq!(root.chunks, chunks);
+    }
+
+    #[test]
+    fn test_tree_shift_resistant() {
+        let contents = pseudo_random_bytes(0x9e3779b97f4a7c15, 2 * 1024 * 1024);
+        let mut shifted = pseudo_random_bytes(7, 333);
+        shifted.extend_from_slice(&contents);
+
+        let original = split(2, &contents);
+        let modified = split(2, &shifted);
+
+        // After the first level 2 boundary, all boundaries are the same.
+        let level2 = |superchunks: &[SuperChunk], delta: usize| {
+            superchunks.iter()
+                .filter(|s| s.level == 2)
+                .skip(1)
+                .map(|s| s.cutpoint - delta)
+                .collect::<Vec<_>>()
+        };
+        let expected = level2(&original, 0);
+        assert!(expected.len() > 2);
+        assert_eq!(level2(&modified, 333)[1..], expected[1..]);
+    }
+
+    #[test]
+    fn test_single_chunk() {
+        let contents = pseudo_random_bytes(1, 100);
+        let superchunks = split(4, &contents);
+        assert_eq!(superchunks, vec![SuperChunk {
+            level: 1,
+            hash: 0,
+            offset: 0,
+            cutpoint: 100,
+            children: 1,
+            chunks: 1,
+        }]);
+        assert!(split(4, &[]).is_empty());
+    }
+}
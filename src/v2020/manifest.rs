@@ -0,0 +1,799 @@
+//
+// Copyright (c) 2023 Florian Gäbler
+//
+
+use super::*;
+use core::convert::TryInto;
+use std::io::{Read, Write};
+
+/// Magic bytes at the start of a manifest.
+const MAGIC: [u8; 8] = *b"FCDCMNFT";
+
+/// Version of the manifest format written by [ManifestWriter].
+pub const MANIFEST_VERSION: u8 = 1;
+
+/// Maximum width of the digest stored with each chunk.
+pub const MANIFEST_DIGEST_MAX: usize = 64;
+
+///
+/// The chunker parameters a manifest was written with.\
+/// \
+/// Chunks are only reproducible with the same sizes, normalization level and gear
+/// table, so these are stored in the header of every manifest.
+///
+#[derive(Debug, Clone, Copy)]
+pub struct ManifestHeader {
+    pub min_size: u32,
+    pub avg_size: u32,
+    pub max_size: u32,
+    pub level: Normalization,
+    /// Identifies the gear table by its seed, see [FastCDC::set_gear_seed], 0 for the default table.
+    pub gear_seed: u64,
+    /// Width in bytes of the digest stored with each chunk, 0 if none are stored.
+    pub digest_length: usize,
+    /// Identifies the algorithm of the digests for the readers of the manifest, 0 if
+    /// unspecified. The values are defined by the application.
+    pub digest_algorithm: u8,
+}
+
+impl ManifestHeader {
+    ///
+    /// The header for chunks of the given chunker, with digests of the given width.
+    ///
+    pub fn for_chunker(chunker: &FastCDC, digest_length: usize) -> Self {
+        Self {
+            min_size: chunker.min_size as u32,
+            avg_size: chunker.avg_size as u32,
+            max_size: chunker.max_size as u32,
+            level: Normalization::from_bits(chunker.normalization).unwrap(),
+            gear_seed: chunker.gear_seed(),
+            digest_length,
+            digest_algorithm: 0,
+        }
+    }
+
+    ///
+    /// Construct a chunker with the parameters of the header.
+    ///
+    pub fn chunker(&self) -> Result<FastCDC, Error> {
+        let mut chunker = FastCDC::new_advanced(self.min_size, self.avg_size, self.max_size, self.level, None)?;
+        chunker.set_gear_seed(self.gear_seed);
+        Ok(chunker)
+    }
+}
+
+///
+/// A chunk recorded in a manifest.
+///
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct ManifestEntry {
+    /// The offset of the chunk in the chunked content.
+    pub offset: u64,
+    pub length: usize,
+    /// The gear hash value as of the end of the chunk, see [Chunk::hash].
+    pub hash: u64,
+    /// The digest of the chunk, empty if the manifest stores none.
+    pub digest: Vec<u8>,
+}
+
+///
+/// Why the chunks of some data diverge from those recorded in a manifest.
+///
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum DivergenceReason {
+    /// The data matches the recorded chunks, but is cut differently, so the manifest was
+    /// written with other parameters, a different gear table or another cutting option.
+    DifferentParameters,
+    /// The data differs from the data the manifest was written for.
+    DataChanged,
+    /// The manifest cannot be read, is truncated or fails its checksum.
+    CorruptManifest,
+}
+
+///
+/// The first divergence found by [ManifestReader::verify()].
+///
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ManifestDivergence {
+    pub reason: DivergenceReason,
+    /// The index of the first chunk that diverges.
+    pub chunk: u64,
+    /// The offset of that chunk in the data.
+    pub offset: u64,
+    /// What exactly diverges.
+    pub message: String,
+}
+
+impl fmt::Display for ManifestDivergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self.reason {
+            DivergenceReason::DifferentParameters => "different parameters",
+            DivergenceReason::DataChanged => "data changed",
+            DivergenceReason::CorruptManifest => "corrupt manifest",
+        };
+        write!(f, "{} at chunk {} (offset {}): {}", reason, self.chunk, self.offset, self.message)
+    }
+}
+
+impl std::error::Error for ManifestDivergence {}
+
+///
+/// Writes a manifest of chunks to a [Write] sink, one chunk at a time.\
+/// \
+/// The format is versioned and compact. All integers are little endian:
+///
+/// | Field | Encoding |
+/// |---|---|
+/// | magic | the 8 bytes `FCDCMNFT` |
+/// | version | `u8`, currently 1 |
+/// | normalization level | `u8` |
+/// | digest width | `u8`, 0 to 64 |
+/// | digest algorithm | `u8`, defined by the application, 0 if unspecified |
+/// | min, avg and max size | 3 × `u32` |
+/// | gear table ID | `u64`, the seed of the gear table |
+/// | chunks | per chunk: LEB128 varint of the zigzag-encoded difference to the previous chunk length plus one, the gear hash as `u64` and the digest |
+/// | end of chunks | the varint 0 |
+/// | footer | chunk count and total length as `u64`, CRC-32 of all preceding bytes as `u32` |
+///
+/// The footer is only written by [ManifestWriter::finish()], so [ManifestReader] detects
+/// manifests that were truncated or not finished.
+///
+/// ```no_run
+/// # use fastcdc_alt::{FastCDC, ManifestHeader, ManifestWriter};
+/// let contents = std::fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+/// let mut chunker = FastCDC::new(4096, 16384, 65535).unwrap();
+/// let header = ManifestHeader::for_chunker(&chunker, 0);
+/// let file = std::fs::File::create("SekienAkashita.manifest").unwrap();
+/// let mut writer = ManifestWriter::new(file, header).unwrap();
+/// for chunk in chunker.as_iterator(&contents) {
+///     writer.write_chunk(&chunk, &[]).unwrap();
+/// }
+/// writer.finish().unwrap();
+/// ```
+///
+pub struct ManifestWriter<W: Write> {
+    output: W,
+    header: ManifestHeader,
+    crc: Crc32,
+    previous_length: u64,
+    count: u64,
+    total: u64,
+}
+
+impl<W: Write> ManifestWriter<W> {
+    ///
+    /// Write the header to the output.
+    ///
+    pub fn new(output: W, header: ManifestHeader) -> Result<Self, Error> {
+        if header.digest_length > MANIFEST_DIGEST_MAX {
+            return Err(Error::Other(format!("{} > {} (digest_length > MANIFEST_DIGEST_MAX)", header.digest_length, MANIFEST_DIGEST_MAX)));
+        }
+
+        let mut writer = Self { output, header, crc: Crc32::new(), previous_length: 0, count: 0, total: 0 };
+        let mut bytes = Vec::with_capacity(32);
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&[MANIFEST_VERSION, header.level.bits() as u8, header.digest_length as u8, header.digest_algorithm]);
+        bytes.extend_from_slice(&header.min_size.to_le_bytes());
+        bytes.extend_from_slice(&header.avg_size.to_le_bytes());
+        bytes.extend_from_slice(&header.max_size.to_le_bytes());
+        bytes.extend_from_slice(&header.gear_seed.to_le_bytes());
+        writer.write_bytes(&bytes)?;
+        Ok(writer)
+    }
+
+    ///
+    /// Record the next chunk with its digest, which must have the width given in the header.\
+    /// Only the length and hash of the chunk are stored, the offsets follow from the lengths.
+    ///
+    pub fn write_chunk(&mut self, chunk: &Chunk, digest: &[u8]) -> Result<(), Error> {
+        self.write_entry(chunk.get_length(), chunk.hash, digest)
+    }
+
+    ///
+    /// Record the next chunk by its length, hash and digest.\
+    /// The length must be between 1 and [MAXIMUM_MAX], like that of any chunk.
+    ///
+    pub fn write_entry(&mut self, length: usize, hash: u64, digest: &[u8]) -> Result<(), Error> {
+        if length == 0 {
+            return Err(Error::Other(String::from("manifest chunks cannot be empty")));
+        } else if length > MAXIMUM_MAX as usize {
+            return Err(Error::Other(format!("manifest chunk of {} bytes exceeds the maximum of {}", length, MAXIMUM_MAX)));
+        } else if digest.len() != self.header.digest_length {
+            return Err(Error::Other(format!("digest of {} bytes in a manifest of {} byte digests", digest.len(), self.header.digest_length)));
+        }
+
+        let length = length as u64;
+        let delta = length.wrapping_sub(self.previous_length) as i64;
+        let mut bytes = [0; 10 + 8 + MANIFEST_DIGEST_MAX];
+        let mut size = encode_varint(zigzag(delta) + 1, &mut bytes);
+        bytes[size..size + 8].copy_from_slice(&hash.to_le_bytes());
+        size += 8;
+        bytes[size..size + digest.len()].copy_from_slice(digest);
+        size += digest.len();
+        self.write_bytes(&bytes[..size])?;
+
+        self.previous_length = length;
+        self.count += 1;
+        self.total += length;
+        Ok(())
+    }
+
+    ///
+    /// Write the footer and flush, returning the output.
+    ///
+    pub fn finish(mut self) -> Result<W, Error> {
+        let mut bytes = [0; 17];
+        bytes[1..9].copy_from_slice(&self.count.to_le_bytes());
+        bytes[9..].copy_from_slice(&self.total.to_le_bytes());
+        self.write_bytes(&bytes)?;
+        let crc = self.crc.value();
+        self.output.write_all(&crc.to_le_bytes())?;
+        self.output.flush()?;
+        Ok(self.output)
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.crc.update(bytes);
+        self.output.write_all(bytes)?;
+        Ok(())
+    }
+}
+
+///
+/// Reads a manifest written by [ManifestWriter] from a [Read] source.\
+/// \
+/// The header is read on construction, then the entries are read one at a time by
+/// iterating. The footer is checked after the last entry, so the iterator yields an
+/// error instead of ending if the manifest is truncated or corrupt.
+///
+/// ```no_run
+/// # use fastcdc_alt::ManifestReader;
+/// let file = std::fs::File::open("SekienAkashita.manifest").unwrap();
+/// let reader = ManifestReader::new(std::io::BufReader::new(file)).unwrap();
+/// println!("average size {}", reader.header().avg_size);
+/// for entry in reader {
+///     let entry = entry.unwrap();
+///     println!("offset={} length={} hash={}", entry.offset, entry.length, entry.hash);
+/// }
+/// ```
+///
+pub struct ManifestReader<R: Read> {
+    input: R,
+    header: ManifestHeader,
+    crc: Crc32,
+    previous_length: u64,
+    count: u64,
+    total: u64,
+    done: bool,
+}
+
+impl<R: Read> ManifestReader<R> {
+    ///
+    /// Read the header from the input.\
+    /// Fails if the input is not a manifest or has an unsupported version.
+    ///
+    pub fn new(input: R) -> Result<Self, Error> {
+        let mut reader = Self {
+            input,
+            header: ManifestHeader {
+                min_size: 0,
+                avg_size: 0,
+                max_size: 0,
+                level: Normalization::Level1,
+                gear_seed: 0,
+                digest_length: 0,
+                digest_algorithm: 0,
+            },
+            crc: Crc32::new(),
+            previous_length: 0,
+            count: 0,
+            total: 0,
+            done: false,
+        };
+
+        let mut bytes = [0; 32];
+        reader.read_bytes(&mut bytes)?;
+        if bytes[..8] != MAGIC {
+            return Err(Error::Other(String::from("not a manifest")));
+        } else if bytes[8] != MANIFEST_VERSION {
+            return Err(Error::Other(format!("unsupported manifest version {}", bytes[8])));
+        }
+        let level = Normalization::from_bits(bytes[9] as u32)
+            .ok_or_else(|| Error::Other(format!("invalid normalization level {} in manifest", bytes[9])))?;
+        if bytes[10] as usize > MANIFEST_DIGEST_MAX {
+            return Err(Error::Other(String::from("corrupt manifest header")));
+        }
+
+        reader.header = ManifestHeader {
+            min_size: u32::from_le_bytes(bytes[12..16].try_into().unwrap()),
+            avg_size: u32::from_le_bytes(bytes[16..20].try_into().unwrap()),
+            max_size: u32::from_le_bytes(bytes[20..24].try_into().unwrap()),
+            level,
+            gear_seed: u64::from_le_bytes(bytes[24..32].try_into().unwrap()),
+            digest_length: bytes[10] as usize,
+            digest_algorithm: bytes[11],
+        };
+        Ok(reader)
+    }
+
+    ///
+    /// The chunker parameters and digests of the manifest.
+    ///
+    pub fn header(&self) -> &ManifestHeader {
+        &self.header
+    }
+
+    ///
+    /// Chunk the data with the parameters of the header and check that every recorded
+    /// boundary, length, hash and digest matches, reporting the first divergence.\
+    /// \
+    /// `digest` computes the digest of a chunk, with the algorithm the manifest was
+    /// written with. It is only called if the manifest stores digests.\
+    /// \
+    /// A chunk whose recorded range still has the recorded digest but is cut differently
+    /// is reported as [DivergenceReason::DifferentParameters], any other difference in
+    /// the data as [DivergenceReason::DataChanged]. Without digests, only recorded
+    /// chunks longer than the maximum size reveal different parameters. The remainder
+    /// of the manifest is read after a divergence, so a corrupt manifest is always
+    /// reported as such.
+    ///
+    /// ```no_run
+    /// # use fastcdc_alt::ManifestReader;
+    /// let contents = std::fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+    /// let file = std::fs::File::open("SekienAkashita.manifest").unwrap();
+    /// let reader = ManifestReader::new(std::io::BufReader::new(file)).unwrap();
+    /// if let Err(divergence) = reader.verify(&contents, |_| []) {
+    ///     println!("{}", divergence);
+    /// }
+    /// ```
+    ///
+    pub fn verify<F, D>(mut self, data: &[u8], mut digest: F) -> Result<(), ManifestDivergence>
+    where
+        F: FnMut(&[u8]) -> D,
+        D: AsRef<[u8]>,
+    {
+        let header = self.header;
+        let mut chunker = header.chunker().map_err(|error| ManifestDivergence {
+            reason: DivergenceReason::CorruptManifest,
+            chunk: 0,
+            offset: 0,
+            message: format!("invalid parameters in header: {}", error),
+        })?;
+        let mut chunks = chunker.as_iterator(data);
+
+        let mut index = 0;
+        let mismatch = loop {
+            let (entry, chunk) = match (self.next(), chunks.next()) {
+                (None, None) => return Ok(()),
+                (Some(Err(error)), _) => break Err(corrupt(error, index, self.total)),
+                (Some(Ok(entry)), chunk) => (entry, chunk),
+                (None, Some(chunk)) => {
+                    break Err(ManifestDivergence {
+                        reason: DivergenceReason::DataChanged,
+                        chunk: index,
+                        offset: chunk.offset as u64,
+                        message: format!("data continues past the recorded {} bytes", self.total),
+                    })
+                }
+            };
+
+            let matches = chunk.is_some_and(|chunk| {
+                chunk.get_length() == entry.length
+                    && chunk.hash == entry.hash
+                    && (header.digest_length == 0 || digest(&data[chunk.offset as usize..chunk.cutpoint]).as_ref() == entry.digest)
+            });
+            if !matches {
+                break Ok((entry, chunk));
+            }
+            index += 1;
+        };
+
+        // Check the integrity of the remaining manifest, which takes precedence.
+        let offset = self.total;
+        for entry in self.by_ref() {
+            if let Err(error) = entry {
+                return Err(corrupt(error, index, offset));
+            }
+        }
+        let (entry, chunk) = mismatch?;
+        Err(self.diverge(data, &entry, chunk, &mut digest, index))
+    }
+
+    fn diverge<F, D>(&self, data: &[u8], entry: &ManifestEntry, chunk: Option<Chunk>, digest: &mut F, index: u64) -> ManifestDivergence
+    where
+        F: FnMut(&[u8]) -> D,
+        D: AsRef<[u8]>,
+    {
+        let found = match chunk {
+            Some(chunk) => format!("{} bytes with hash {}", chunk.get_length(), chunk.hash),
+            None => String::from("the end of the data"),
+        };
+        let message = format!("recorded {} bytes with hash {}, found {}", entry.length, entry.hash, found);
+        let end = entry.offset as usize + entry.length;
+
+        // The last chunk was cut by the end of the data, so it grows with appended data.
+        let appended = end as u64 == self.total && data.len() > end;
+        let unchanged = self.header.digest_length > 0 && end <= data.len() && digest(&data[entry.offset as usize..end]).as_ref() == entry.digest;
+        let reason = if !appended && (entry.length > self.header.max_size as usize || unchanged) {
+            DivergenceReason::DifferentParameters
+        } else {
+            DivergenceReason::DataChanged
+        };
+        ManifestDivergence { reason, chunk: index, offset: entry.offset, message }
+    }
+
+    fn read_entry(&mut self) -> Result<Option<ManifestEntry>, Error> {
+        let value = self.read_varint()?;
+        if value == 0 {
+            self.read_footer()?;
+            return Ok(None);
+        }
+
+        let length = self.previous_length.wrapping_add(unzigzag(value - 1) as u64);
+        if length == 0 || length > MAXIMUM_MAX as u64 {
+            return Err(Error::Other(format!("corrupt manifest entry {}", self.count)));
+        }
+        let mut hash = [0; 8];
+        self.read_bytes(&mut hash)?;
+        let mut digest = vec![0; self.header.digest_length];
+        self.read_bytes(&mut digest)?;
+
+        let entry = ManifestEntry { offset: self.total, length: length as usize, hash: u64::from_le_bytes(hash), digest };
+        self.previous_length = length;
+        self.count += 1;
+        self.total += length;
+        Ok(Some(entry))
+    }
+
+    fn read_footer(&mut self) -> Result<(), Error> {
+        let mut bytes = [0; 16];
+        self.read_bytes(&mut bytes)?;
+        let expected = self.crc.value();
+        let mut crc = [0; 4];
+        self.read_bytes(&mut crc)?;
+
+        let count = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+        let total = u64::from_le_bytes(bytes[8..].try_into().unwrap());
+        if count != self.count || total != self.total {
+            Err(Error::Other(format!(
+                "manifest footer records {} chunks of {} bytes, found {} chunks of {} bytes",
+                count, total, self.count, self.total
+            )))
+        } else if u32::from_le_bytes(crc) != expected {
+            Err(Error::Other(String::from("manifest checksum mismatch")))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn read_varint(&mut self) -> Result<u64, Error> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let mut byte = [0];
+            self.read_bytes(&mut byte)?;
+            value |= ((byte[0] & 0x7f) as u64) << shift;
+            if byte[0] & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error::Other(format!("corrupt manifest entry {}", self.count)))
+    }
+
+    fn read_bytes(&mut self, bytes: &mut [u8]) -> Result<(), Error> {
+        match self.input.read_exact(bytes) {
+            Ok(()) => {
+                self.crc.update(bytes);
+                Ok(())
+            }
+            Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => {
+                Err(Error::Other(format!("truncated manifest after {} chunks", self.count)))
+            }
+            Err(error) => Err(Error::IoError(error)),
+        }
+    }
+}
+
+impl<R: Read> Iterator for ManifestReader<R> {
+    type Item = Result<ManifestEntry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let result = self.read_entry().transpose();
+        self.done = !matches!(result, Some(Ok(_)));
+        result
+    }
+}
+
+fn corrupt(error: Error, chunk: u64, offset: u64) -> ManifestDivergence {
+    let message = match error {
+        Error::Other(message) => message,
+        error => error.to_string(),
+    };
+    ManifestDivergence { reason: DivergenceReason::CorruptManifest, chunk, offset, message }
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+fn encode_varint(mut value: u64, bytes: &mut [u8]) -> usize {
+    let mut size = 0;
+    while value >= 0x80 {
+        bytes[size] = value as u8 | 0x80;
+        value >>= 7;
+        size += 1;
+    }
+    bytes[size] = value as u8;
+    size + 1
+}
+
+/// CRC-32 as used by zlib and PNG.
+struct Crc32(u32);
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut value = index as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            value = if value & 1 == 1 { (value >> 1) ^ 0xedb88320 } else { value >> 1 };
+            bit += 1;
+        }
+        table[index] = value;
+        index += 1;
+    }
+    table
+};
+
+impl Crc32 {
+    fn new() -> Self {
+        Crc32(0xffffffff)
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = CRC32_TABLE[((self.0 ^ *byte as u32) & 0xff) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    fn value(&self) -> u32 {
+        !self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn sekien_manifest(digest_length: usize) -> Vec<u8> {
+        let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        let mut chunker = FastCDC::new(4096, 16384, 65535).unwrap();
+        let mut writer = ManifestWriter::new(Vec::new(), ManifestHeader::for_chunker(&chunker, digest_length)).unwrap();
+        for chunk in chunker.as_iterator(&contents) {
+            let digest = vec![chunk.cutpoint as u8; digest_length];
+            writer.write_chunk(&chunk, &digest).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let bytes = sekien_manifest(32);
+        // Header, five entries of a varint length delta, hash and digest, and the footer.
+        assert_eq!(bytes.len(), 32 + (3 + 2 + 3 + 3 + 2) + 5 * (8 + 32) + 1 + 20);
+
+        let reader = ManifestReader::new(&bytes[..]).unwrap();
+        let header = *reader.header();
+        assert_eq!((header.min_size, header.avg_size, header.max_size, header.gear_seed), (4096, 16384, 65535, 0));
+        assert_eq!(header.digest_length, 32);
+        assert_eq!(header.level.bits(), 1);
+
+        let entries = reader.collect::<Result<Vec<_>, _>>().unwrap();
+        let chunks = entries.iter().map(|entry| (entry.hash, entry.length)).collect::<Vec<_>>();
+        assert_eq!(chunks, super::super::vectors::SEKIEN_16K_CHUNKS);
+        assert_eq!(entries[1].offset, 21325);
+        assert_eq!(entries[1].digest, vec![(21325 + 17140) as u8; 32]);
+    }
+
+    #[test]
+    fn test_manifest_header_chunker() {
+        let mut chunker = FastCDC::new_advanced(8192, 16384, 32768, Normalization::Level3, None).unwrap();
+        chunker.set_gear_seed(42);
+        let header = ManifestHeader::for_chunker(&chunker, 0);
+        assert_eq!(header.gear_seed, 42);
+        assert_eq!(header.level.bits(), 3);
+
+        let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        let expected = chunker.as_iterator(&contents).collect::<Vec<_>>();
+        assert_eq!(header.chunker().unwrap().as_iterator(&contents).collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn test_manifest_varint_deltas() {
+        let header = ManifestHeader { min_size: 64, avg_size: 256, max_size: 1024, level: Normalization::Level0, gear_seed: 7, digest_length: 0, digest_algorithm: 9 };
+        let lengths = [1usize, 1, 300, 16_777_216, 2, 127, 128];
+        let mut writer = ManifestWriter::new(Vec::new(), header).unwrap();
+        for (index, length) in lengths.iter().enumerate() {
+            writer.write_entry(*length, index as u64, &[]).unwrap();
+        }
+        let bytes = writer.finish().unwrap();
+
+        let reader = ManifestReader::new(&bytes[..]).unwrap();
+        assert_eq!(reader.header().digest_algorithm, 9);
+        let entries = reader.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(entries.iter().map(|entry| entry.length).collect::<Vec<_>>(), lengths);
+        assert_eq!(entries.iter().map(|entry| entry.hash).collect::<Vec<_>>(), [0, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(entries[4].offset, 1 + 1 + 300 + 16_777_216);
+    }
+
+    #[test]
+    fn test_manifest_invalid_entries() {
+        let header = ManifestHeader { min_size: 64, avg_size: 256, max_size: 1024, level: Normalization::Level1, gear_seed: 0, digest_length: 4, digest_algorithm: 0 };
+        let mut writer = ManifestWriter::new(Vec::new(), header).unwrap();
+        assert!(writer.write_entry(0, 0, &[0; 4]).is_err());
+        assert!(writer.write_entry(MAXIMUM_MAX as usize + 1, 0, &[0; 4]).is_err());
+        assert!(writer.write_entry(MAXIMUM_MAX as usize, 0, &[0; 4]).is_ok());
+        assert!(writer.write_entry(10, 0, &[0; 3]).is_err());
+        assert!(ManifestWriter::new(Vec::new(), ManifestHeader { digest_length: 65, ..header }).is_err());
+    }
+
+    #[test]
+    fn test_manifest_truncated() {
+        let bytes = sekien_manifest(16);
+        for length in [0, 10, 31, 32, 40, bytes.len() - 21, bytes.len() - 20, bytes.len() - 1] {
+            let result = ManifestReader::new(&bytes[..length]).and_then(|reader| reader.collect::<Result<Vec<_>, _>>());
+            assert!(result.is_err(), "truncated to {} bytes", length);
+        }
+        assert!(ManifestReader::new(&bytes[..]).unwrap().all(|entry| entry.is_ok()));
+    }
+
+    #[test]
+    fn test_manifest_corrupt() {
+        let bytes = sekien_manifest(0);
+        // Flipping any bit of the entries or footer is detected.
+        for index in 32..bytes.len() {
+            let mut corrupt = bytes.clone();
+            corrupt[index] ^= 0x10;
+            let result = ManifestReader::new(&corrupt[..]).and_then(|reader| reader.collect::<Result<Vec<_>, _>>());
+            assert!(result.is_err(), "corrupt byte {}", index);
+        }
+
+        let mut corrupt = bytes.clone();
+        corrupt[8] = 2;
+        assert!(ManifestReader::new(&corrupt[..]).is_err());
+        corrupt = bytes.clone();
+        corrupt[0] = b'X';
+        assert!(ManifestReader::new(&corrupt[..]).is_err());
+    }
+
+    fn crc_digest(data: &[u8]) -> [u8; 4] {
+        let mut crc = Crc32::new();
+        crc.update(data);
+        crc.value().to_le_bytes()
+    }
+
+    fn verified_manifest(header: ManifestHeader, chunker: &mut FastCDC, contents: &[u8]) -> Vec<u8> {
+        let mut writer = ManifestWriter::new(Vec::new(), header).unwrap();
+        for chunk in chunker.as_iterator(contents) {
+            let digest = &crc_digest(&contents[chunk.offset as usize..chunk.cutpoint])[..header.digest_length];
+            writer.write_chunk(&chunk, digest).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    fn verify(manifest: &[u8], contents: &[u8]) -> Result<(), ManifestDivergence> {
+        ManifestReader::new(manifest).unwrap().verify(contents, crc_digest)
+    }
+
+    #[test]
+    fn test_manifest_verify() {
+        let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        let mut chunker = FastCDC::new(4096, 16384, 65535).unwrap();
+        for digest_length in [0, 4] {
+            let header = ManifestHeader::for_chunker(&chunker, digest_length);
+            let manifest = verified_manifest(header, &mut chunker, &contents);
+            assert_eq!(verify(&manifest, &contents), Ok(()));
+        }
+        let manifest = verified_manifest(ManifestHeader::for_chunker(&chunker, 0), &mut chunker, &[]);
+        assert_eq!(verify(&manifest, &[]), Ok(()));
+    }
+
+    #[test]
+    fn test_manifest_verify_data_changed() {
+        let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        let mut chunker = FastCDC::new(4096, 16384, 65535).unwrap();
+        for digest_length in [0, 4] {
+            let manifest = verified_manifest(ManifestHeader::for_chunker(&chunker, digest_length), &mut chunker, &contents);
+
+            // Without digests, only changes that move a cut point or its hash are seen.
+            let mut changed = contents.clone();
+            changed[21325 + 17140 - 10] ^= 1;
+            let divergence = verify(&manifest, &changed).unwrap_err();
+            assert_eq!((divergence.reason, divergence.chunk, divergence.offset), (DivergenceReason::DataChanged, 1, 21325));
+
+            let divergence = verify(&manifest, &contents[..100000]).unwrap_err();
+            assert_eq!((divergence.reason, divergence.chunk), (DivergenceReason::DataChanged, 4));
+
+            let longer = [&contents[..], b"appended"].concat();
+            let divergence = verify(&manifest, &longer).unwrap_err();
+            assert_eq!(divergence.reason, DivergenceReason::DataChanged);
+        }
+    }
+
+    #[test]
+    fn test_manifest_verify_same_cuts_changed_digest() {
+        // A change that keeps the cut points is only caught by the digests.
+        let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        let mut chunker = FastCDC::new(4096, 16384, 65535).unwrap();
+        let manifest = verified_manifest(ManifestHeader::for_chunker(&chunker, 4), &mut chunker, &contents);
+        let header = ManifestHeader::for_chunker(&chunker, 4);
+        let mut writer = ManifestWriter::new(Vec::new(), header).unwrap();
+        for (index, entry) in ManifestReader::new(&manifest[..]).unwrap().enumerate() {
+            let entry = entry.unwrap();
+            let digest = if index == 2 { [0; 4] } else { crc_digest(&contents[entry.offset as usize..][..entry.length]) };
+            writer.write_entry(entry.length, entry.hash, &digest).unwrap();
+        }
+        let divergence = verify(&writer.finish().unwrap(), &contents).unwrap_err();
+        assert_eq!((divergence.reason, divergence.chunk), (DivergenceReason::DataChanged, 2));
+    }
+
+    #[test]
+    fn test_manifest_verify_different_parameters() {
+        let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        let chunker = FastCDC::new(4096, 16384, 65535).unwrap();
+        // Chunks cut with another gear seed or sizes than the header records.
+        let mut seeded = FastCDC::new(4096, 16384, 65535).unwrap();
+        seeded.set_gear_seed(42);
+        let mut larger = FastCDC::new(16384, 65536, 262144).unwrap();
+        for other in [&mut seeded, &mut larger] {
+            let manifest = verified_manifest(ManifestHeader::for_chunker(&chunker, 4), other, &contents);
+            let divergence = verify(&manifest, &contents).unwrap_err();
+            assert_eq!((divergence.reason, divergence.chunk, divergence.offset), (DivergenceReason::DifferentParameters, 0, 0));
+        }
+
+        // Without digests, only chunks above the maximum size give it away.
+        let manifest = verified_manifest(ManifestHeader::for_chunker(&chunker, 0), &mut larger, &contents);
+        assert_eq!(verify(&manifest, &contents).unwrap_err().reason, DivergenceReason::DifferentParameters);
+    }
+
+    #[test]
+    fn test_manifest_verify_corrupt() {
+        let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        let mut chunker = FastCDC::new(4096, 16384, 65535).unwrap();
+        let manifest = verified_manifest(ManifestHeader::for_chunker(&chunker, 4), &mut chunker, &contents);
+
+        let mut corrupt = manifest.clone();
+        corrupt[40] ^= 0x10;
+        assert_eq!(verify(&corrupt, &contents).unwrap_err().reason, DivergenceReason::CorruptManifest);
+
+        // A corrupt footer takes precedence over changed data.
+        let mut changed = contents.clone();
+        changed[0] ^= 1;
+        corrupt = manifest.clone();
+        *corrupt.last_mut().unwrap() ^= 1;
+        let divergence = verify(&corrupt, &changed).unwrap_err();
+        assert_eq!((divergence.reason, divergence.chunk), (DivergenceReason::CorruptManifest, 0));
+        assert!(divergence.to_string().starts_with("corrupt manifest at chunk 0"), "{}", divergence);
+
+        // Sizes that FastCDC rejects.
+        corrupt = manifest.clone();
+        corrupt[12..16].copy_from_slice(&1u32.to_le_bytes());
+        let divergence = ManifestReader::new(&corrupt[..]).unwrap().verify(&contents, crc_digest).unwrap_err();
+        assert_eq!(divergence.reason, DivergenceReason::CorruptManifest);
+    }
+
+    #[test]
+    fn test_crc32() {
+        let mut crc = Crc32::new();
+        crc.update(b"123456789");
+        assert_eq!(crc.value(), 0xcbf43926);
+    }
+}
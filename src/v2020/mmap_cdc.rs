@@ -0,0 +1,214 @@
+//
+// Copyright (c) 2023 Florian Gäbler
+//
+
+use super::*;
+use memmap2::{Mmap, MmapOptions};
+use std::convert::TryFrom;
+use std::fs::File;
+use std::path::Path;
+
+/// Default size of the mapped window.
+pub const DEFAULT_WINDOW_SIZE: usize = 64 * 1024 * 1024;
+
+///
+/// A chunker for files which maps the file into memory in windows of a fixed size,
+/// instead of reading it into a buffer like [StreamCDC].\
+/// \
+/// Only one window of the file is mapped at a time, which is moved along the file as the
+/// chunks are found, so the address space usage stays low even for large files.
+/// Sequential access is advised to the kernel for each window.
+/// The cut points are identical to those of [FastCDC] for the whole file.\
+/// \
+/// The offsets of a [Chunk] are limited to `isize::MAX`. On 32-bit targets, chunks
+/// beyond 2 GiB are therefore returned as an error instead.\
+/// \
+/// The chunks are returned by [next_chunk()](MmapCDC::next_chunk) together with a slice
+/// borrowing the current mapping, so no data is copied.\
+/// \
+/// Note:\
+/// Like any memory-mapped access, the behaviour is undefined if the file is modified
+/// while it is being chunked.
+///
+/// ```no_run
+/// # use fastcdc_alt::chunk_file;
+/// let mut chunker = chunk_file("test/fixtures/SekienAkashita.jpg", 4096, 16384, 65535).unwrap();
+/// while let Some(result) = chunker.next_chunk() {
+///     let (data, chunk) = result.unwrap();
+///     println!("offset={} size={}", chunk.offset, data.len());
+/// }
+/// ```
+///
+pub struct MmapCDC {
+    inner: FastCDC,
+    file: File,
+    /// Length of the file.
+    length: u64,
+    /// Number of bytes mapped at once.
+    window_size: usize,
+    /// The current window, if mapped.
+    map: Option<Mmap>,
+    /// Offset in the file at which the current window starts.
+    map_offset: u64,
+    /// Offset in the file at which the next chunk starts.
+    offset: u64,
+}
+
+impl MmapCDC {
+    ///
+    /// Construct a `MmapCDC` that will process the given file from its start.
+    ///
+    /// Uses chunk size normalization level 1 by default.
+    ///
+    pub fn new(file: File, min_size: u32, avg_size: u32, max_size: u32) -> Result<Self, Error> {
+        Self::new_advanced(file, min_size, avg_size, max_size, Normalization::Level1)
+    }
+
+    ///
+    /// Create a new `MmapCDC` with the given normalization level.
+    ///
+    pub fn new_advanced(
+        file: File,
+        min_size: u32,
+        avg_size: u32,
+        max_size: u32,
+        level: Normalization,
+    ) -> Result<Self, Error> {
+        let inner = FastCDC::new_advanced(min_size, avg_size, max_size, level, None)?;
+        let length = file.metadata()?.len();
+
+        Ok(Self {
+            inner,
+            file,
+            length,
+            window_size: DEFAULT_WINDOW_SIZE.max(max_size as usize),
+            map: None,
+            map_offset: 0,
+            offset: 0,
+        })
+    }
+
+    ///
+    /// Set the number of bytes mapped at once, which defaults to [DEFAULT_WINDOW_SIZE].\
+    /// The window size must be at least the maximum chunk size.
+    ///
+    pub fn set_window_size(&mut self, window_size: usize) -> Result<(), Error> {
+        if window_size < self.inner.max_size {
+            return Err(Error::Other(format!("{} < {} (window_size < max_size)", window_size, self.inner.max_size)))
+        }
+
+        self.window_size = window_size;
+        Ok(())
+    }
+
+    ///
+    /// Find the next chunk in the file, returning the chunk data from the current
+    /// mapping together with the chunk, whose offsets are relative to the start of the file.\
+    /// Returns `None` once the end of the file has been reached.
+    ///
+    pub fn next_chunk(&mut self) -> Option<Result<(&[u8], Chunk), Error>> {
+        if self.offset >= self.length {
+            return None;
+        }
+
+        if let Err(error) = self.map_window() {
+            return Some(Err(error));
+        }
+
+        let map = self.map.as_ref()?;
+        let start = (self.offset - self.map_offset) as usize;
+        let window = &map[start..];
+
+        self.inner.set_content_length(window.len());
+        let chunk = match self.inner.cut(window) {
+            Some(chunk) => chunk,
+            None => return Some(Err(Error::Other("no cut point found in the mapped window".to_string()))),
+        };
+
+        let end = self.offset + chunk.cutpoint as u64;
+        let (offset, cutpoint) = match (isize::try_from(self.offset), isize::try_from(end)) {
+            (Ok(offset), Ok(cutpoint)) => (offset, cutpoint as usize),
+            _ => return Some(Err(Error::Other(format!("chunk end {} exceeds the chunk offsets of this target", end)))),
+        };
+        self.offset = end;
+
+        Some(Ok((&window[..chunk.cutpoint], Chunk {
+            hash: chunk.hash,
+            offset,
+            cutpoint,
+            zero: chunk.zero
+        })))
+    }
+
+    /// Map a new window if the current one does not contain the next chunk.
+    fn map_window(&mut self) -> Result<(), Error> {
+        if let Some(map) = &self.map {
+            let end = self.map_offset + map.len() as u64;
+            if end == self.length || self.offset + self.inner.max_size as u64 <= end {
+                return Ok(());
+            }
+        }
+
+        // Unmap the previous window first to keep the address space usage low.
+        self.map = None;
+
+        let length = (self.length - self.offset).min(self.window_size as u64) as usize;
+        let map = unsafe { MmapOptions::new().offset(self.offset).len(length).map(&self.file)? };
+        #[cfg(unix)]
+        map.advise(memmap2::Advice::Sequential)?;
+
+        self.map = Some(map);
+        self.map_offset = self.offset;
+
+        Ok(())
+    }
+}
+
+///
+/// Open the file at the given path and construct a [MmapCDC] for it.
+///
+pub fn chunk_file<P: AsRef<Path>>(path: P, min_size: u32, avg_size: u32, max_size: u32) -> Result<MmapCDC, Error> {
+    MmapCDC::new(File::open(path)?, min_size, avg_size, max_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_window_size_invalid() {
+        let mut chunker = chunk_file("test/fixtures/SekienAkashita.jpg", 4096, 16384, 65535).unwrap();
+        assert!(chunker.set_window_size(65534).is_err());
+        assert!(chunker.set_window_size(65535).is_ok());
+    }
+
+    #[test]
+    fn test_mmap_chunks_match_fastcdc() {
+        let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        let mut chunker = FastCDC::new(4096, 16384, 65535).unwrap();
+        let expected = chunker.as_iterator(&contents).collect::<Vec<_>>();
+
+        for window_size in [65535, 100000, DEFAULT_WINDOW_SIZE] {
+            let mut chunker = chunk_file("test/fixtures/SekienAkashita.jpg", 4096, 16384, 65535).unwrap();
+            chunker.set_window_size(window_size).unwrap();
+
+            let mut chunks = Vec::new();
+            while let Some(result) = chunker.next_chunk() {
+                let (data, chunk) = result.unwrap();
+                assert_eq!(data, &contents[chunk.offset as usize..chunk.cutpoint]);
+                chunks.push(chunk);
+            }
+            assert_eq!(chunks, expected);
+        }
+    }
+
+    #[test]
+    fn test_mmap_empty_file() {
+        let path = std::env::temp_dir().join(format!("fastcdc-mmap-empty-{}", std::process::id()));
+        File::create(&path).unwrap();
+        let mut chunker = chunk_file(&path, 4096, 16384, 65535).unwrap();
+        assert!(chunker.next_chunk().is_none());
+        fs::remove_file(&path).unwrap();
+    }
+}
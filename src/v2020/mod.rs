@@ -31,9 +31,72 @@
 //! \
 //! The [StreamCDC] implementation is similar to [FastCDC] except that it will
 //! read data from a [Read] into an internal buffer of `max_size` and produce
-//! (`Vec<u8>`, [Chunk]) tuples from the `Iterator`.
-use std::fmt;
+//! (`Vec<u8>`, [Chunk]) tuples from the `Iterator`.\
+//! \
+//! The [HashSplitter] uses the [Chunk] hash to assign content-defined levels
+//! to the chunks and to group them into a tree of [SuperChunk]s, while the
+//! [ProllyTreeBuilder] builds a content-defined Merkle tree over chunk digests.
+//! The [MultiCDC] chunker finds nested boundaries at several average sizes in
+//! a single pass, and the [TarCDC] chunker aligns the cut points of a tar
+//! archive with its members.\
+//! \
+//! Large buffers can be chunked on several threads with
+//! [chunk_parallel()](FastCDC::chunk_parallel), which produces the same chunks
+//! as the [FastCDCIterator], while the [ChunkPipeline] chunks many files on a
+//! bounded pool of workers.\
+//! \
+//! For fixed parameters, the [FastCDCConst] chunker takes them as const generics,
+//! validating them at compile time and specializing the hashing loop for them.
+use alloc::boxed::Box;
+use alloc::collections::BTreeSet;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "std")]
 use std::io::Read;
+#[cfg(all(feature = "std", target_os = "linux"))]
+use std::fs::File;
+#[cfg(all(feature = "std", target_os = "linux"))]
+use std::os::unix::io::AsRawFd;
+
+mod const_cdc;
+pub use const_cdc::*;
+mod hashsplit;
+pub use hashsplit::*;
+mod nested;
+pub use nested::*;
+#[cfg(feature = "std")]
+mod manifest;
+#[cfg(feature = "std")]
+pub use manifest::*;
+#[cfg(feature = "std")]
+mod parallel;
+#[cfg(feature = "std")]
+mod pipeline;
+#[cfg(feature = "std")]
+pub use pipeline::*;
+#[cfg(feature = "std")]
+mod prolly;
+#[cfg(feature = "std")]
+pub use prolly::*;
+#[cfg(feature = "std")]
+mod tar_cdc;
+#[cfg(feature = "std")]
+pub use tar_cdc::*;
+#[cfg(all(feature = "std", target_os = "linux"))]
+mod sparse;
+
+#[cfg(feature = "mmap")]
+mod mmap_cdc;
+#[cfg(feature = "mmap")]
+pub use mmap_cdc::*;
+
+#[cfg(all(feature = "io_uring", target_os = "linux"))]
+mod uring_cdc;
+#[cfg(all(feature = "io_uring", target_os = "linux"))]
+pub use uring_cdc::*;
 
 #[cfg(any(feature = "tokio", feature = "futures"))]
 mod async_stream_cdc;
@@ -275,6 +338,17 @@ impl Normalization {
             Normalization::Level3 => 3,
         }
     }
+
+    #[cfg(feature = "std")]
+    pub(self) fn from_bits(bits: u32) -> Option<Self> {
+        match bits {
+            0 => Some(Normalization::Level0),
+            1 => Some(Normalization::Level1),
+            2 => Some(Normalization::Level2),
+            3 => Some(Normalization::Level3),
+            _ => None,
+        }
+    }
 }
 
 impl fmt::Display for Normalization {
@@ -298,8 +372,20 @@ pub struct Chunk {
     /// [FastCDC::cut] may set a negative value if the chunk starts at a previous buffer.
     pub offset: isize,
 
-    /// The cut point in the given buffer at which this chunk ends.
+    /// The cut point in the given buffer at which this chunk ends.\
+    /// \
+    /// Note:\
+    /// [FastCDC::cut] may set a negative value, stored as its two's complement, if a
+    /// record-aligned chunk ends at a previous buffer (see [FastCDC::set_record_delimiter]).
     pub cutpoint: usize,
+
+    /// Whether the chunk consists only of zero bytes.\
+    /// \
+    /// Note:\
+    /// The flag is set for every chunker. When zero runs are skipped, such chunks are also
+    /// found without hashing them, as long as zero runs cannot contain cut points for the
+    /// masks of the chunker, which is the case for typical sizes.
+    pub zero: bool,
 }
 
 impl Chunk {
@@ -312,12 +398,89 @@ impl Chunk {
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Context {
     remaining: usize,
+    position: usize,
     processed: usize,
     last_cycle_incomplete: bool,
     mask_ls: u64,
     mask: u64,
     index: usize,
-    hash: u64
+    hash: u64,
+    /// End of the window in which a record delimiter is searched, if searching.
+    search_end: Option<usize>,
+    /// Position of the next byte to examine for a record delimiter.
+    search_pos: usize,
+    /// Position of the content-defined cut point the search started at.
+    candidate: usize,
+    /// Number of delimiter bytes matched so far.
+    matched: usize,
+    /// True as long as all bytes of the current chunk seen so far are zero and
+    /// zero runs are skipped without hashing.
+    zero_run: bool,
+    /// Offset of the first non-zero byte of the current chunk, `usize::MAX` if
+    /// all bytes seen so far are zero.
+    nonzero: usize
+}
+
+/// A gear table, together with its values shifted left by one bit.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Gear {
+    seed: u64,
+    table: [u64; 256],
+    table_ls: [u64; 256],
+}
+
+impl Gear {
+    /// Generate the gear table for the seed with SplitMix64.
+    fn seeded(seed: u64) -> Self {
+        let mut state = seed;
+        let mut table = [0; 256];
+        for value in table.iter_mut() {
+            state = state.wrapping_add(0x9e3779b97f4a7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            *value = z ^ (z >> 31);
+        }
+
+        Self {
+            seed,
+            table,
+            table_ls: table.map(|value| value << 1),
+        }
+    }
+}
+
+/// The default gear table, which is used for the seed zero.
+static DEFAULT_GEAR: Gear = Gear {
+    seed: 0,
+    table: GEAR,
+    table_ls: GEAR_LS,
+};
+
+/// The gear table of a chunker, which is only stored if seeded.
+fn gear_table(gear: &Option<Box<Gear>>) -> &Gear {
+    gear.as_deref().unwrap_or(&DEFAULT_GEAR)
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Alignment {
+    size: usize,
+    /// The minimum chunk size rounded up to the alignment.
+    min_size: usize,
+    /// The maximum chunk size rounded down to the alignment.
+    max_size: usize,
+    /// Number of leading hash bits that must be zero before and after the center.
+    bits_s: u32,
+    bits_l: u32,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct RecordDelimiter {
+    delimiter: Vec<u8>,
+    /// Length of the longest proper prefix of the delimiter which is also a
+    /// suffix, for each prefix of the delimiter.
+    failure: Vec<usize>,
+    window: usize,
 }
 
 ///
@@ -359,6 +522,14 @@ pub struct FastCDC {
     mask_l: u64,
     mask_s_ls: u64,
     mask_l_ls: u64,
+    normalization: u32,
+    /// True if zero runs cannot contain cut points, so they can be skipped.
+    zero_runs: bool,
+    /// The seeded gear table, if any, otherwise the default table is used.
+    gear: Option<Box<Gear>>,
+    forced_cuts: BTreeSet<usize>,
+    alignment: Option<Alignment>,
+    record: Option<RecordDelimiter>,
     context: Context
 }
 
@@ -406,15 +577,22 @@ impl FastCDC {
 
         let context = Context {
             remaining: content_length.unwrap_or(0),
+            position: 0,
             processed: 0,
             last_cycle_incomplete: false,
             mask_ls: mask_s_ls,
             mask: mask_s,
             index: (min_size / 2) as usize,
             hash: 0,
+            search_end: None,
+            search_pos: 0,
+            candidate: 0,
+            matched: 0,
+            zero_run: false,
+            nonzero: usize::MAX,
         };
 
-        Ok(Self {
+        let mut chunker = Self {
             min_size: min_size as usize,
             avg_size: avg_size as usize,
             max_size: max_size as usize,
@@ -422,8 +600,18 @@ impl FastCDC {
             mask_l,
             mask_s_ls,
             mask_l_ls,
+            normalization,
+            zero_runs: false,
+            gear: None,
+            forced_cuts: BTreeSet::new(),
+            alignment: None,
+            record: None,
             context
-        })
+        };
+        chunker.zero_runs = chunker.zero_runs_cut_free();
+        chunker.context.zero_run = chunker.zero_runs;
+
+        Ok(chunker)
     }
 
     ///
@@ -434,19 +622,441 @@ impl FastCDC {
     ///
     pub fn set_content_length(&mut self, length: usize) {
         self.context.remaining = length;
+        self.context.position = 0;
+
+        self.recycle_context(0);
+    }
+
+    ///
+    /// The offset of the next chunk relative to the start of the content,
+    /// which is the sum of the lengths of all chunks since the last call to
+    /// [set_content_length()](FastCDC::set_content_length).
+    ///
+    pub fn position(&self) -> usize {
+        self.context.position
+    }
+
+    ///
+    /// Force a cut point at the given offset relative to the start of the content.\
+    /// \
+    /// The data on both sides of a forced cut point is chunked exactly as if it were
+    /// separate content, so a chunk never spans the given offset. Offsets at or before
+    /// the [position()](FastCDC::position) of the next chunk are ignored.\
+    /// Forced cut points are kept until they have been passed, even across calls to
+    /// [set_content_length()](FastCDC::set_content_length), or until
+    /// [clear_forced_cuts()](FastCDC::clear_forced_cuts) is called.\
+    /// When chunking a stream, use `StreamCDC::force_cut` instead, which takes offsets
+    /// relative to the start of the stream.
+    ///
+    pub fn force_cut(&mut self, offset: usize) {
+        self.forced_cuts.insert(offset);
+    }
+
+    ///
+    /// Remove all pending forced cut points.
+    ///
+    pub fn clear_forced_cuts(&mut self) {
+        self.forced_cuts.clear();
+    }
+
+    ///
+    /// Align the cut points to the ends of records, which are terminated by the given delimiter.\
+    /// \
+    /// Once a content-defined cut point has been found, the cut point is moved to the end
+    /// of the first occurrence of the delimiter that starts at or after it, as long as
+    /// it ends within `window` bytes. Otherwise the content-defined cut point is kept.
+    /// The maximum chunk size, forced cut points and the end of the content still limit
+    /// the window, and chunks that reach the maximum size without a candidate are not
+    /// aligned.\
+    /// \
+    /// [cut()](FastCDC::cut) continues the search across buffer boundaries. If no delimiter
+    /// is found and the content-defined cut point lies in a previous buffer, the chunk is
+    /// returned with a negative cut point (`chunk.cutpoint as isize`), and the next passed
+    /// buffer has to start at that cut point again. Buffers that hold `max_size` bytes from
+    /// the start of the chunk, as passed by [as_iterator()](FastCDC::as_iterator) and
+    /// [StreamCDC], always contain the window.\
+    /// This method resets the internal context like
+    /// [set_content_length()](FastCDC::set_content_length) does.
+    ///
+    /// ```no_run
+    /// # use fastcdc_alt::FastCDC;
+    /// let contents = std::fs::read("events.jsonl").unwrap();
+    /// let mut chunker = FastCDC::new(4096, 16384, 65535).unwrap();
+    /// chunker.set_record_delimiter(b"\n", 4096).unwrap();
+    /// for chunk in chunker.as_iterator(&contents) {
+    ///     println!("offset={} size={}", chunk.offset, chunk.get_length());
+    /// }
+    /// ```
+    ///
+    pub fn set_record_delimiter(&mut self, delimiter: &[u8], window: usize) -> Result<(), Error> {
+        if self.alignment.is_some() {
+            return Err(Error::Other("record delimiters cannot be combined with an alignment".to_string()))
+        } else if delimiter.is_empty() {
+            return Err(Error::Other("the record delimiter must not be empty".to_string()))
+        } else if window < delimiter.len() {
+            return Err(Error::Other(format!("{} < {} (window < delimiter length)", window, delimiter.len())))
+        }
+
+        let mut failure = vec![0; delimiter.len()];
+        let mut matched = 0;
+        for index in 1..delimiter.len() {
+            while matched > 0 && delimiter[index] != delimiter[matched] {
+                matched = failure[matched - 1];
+            }
+            if delimiter[index] == delimiter[matched] {
+                matched += 1;
+            }
+            failure[index] = matched;
+        }
+
+        self.record = Some(RecordDelimiter {
+            delimiter: delimiter.to_vec(),
+            failure,
+            window,
+        });
+        self.recycle_context(0);
+
+        Ok(())
+    }
+
+    ///
+    /// Stop aligning the cut points to record delimiters.\
+    /// This method resets the internal context like
+    /// [set_content_length()](FastCDC::set_content_length) does.
+    ///
+    pub fn clear_record_delimiter(&mut self) {
+        self.record = None;
+        self.recycle_context(0);
+    }
+
+    ///
+    /// Only accept cut points at multiples of the given alignment, e.g. for storage
+    /// layers writing chunks with `O_DIRECT`.\
+    /// \
+    /// In this mode the gear hash is computed over the 64 bytes preceding each aligned
+    /// position, which is judged by requiring enough leading zero bits to keep the
+    /// expected average chunk size, with normalized chunking applied as usual.
+    /// The minimum chunk size is rounded up and the maximum chunk size is rounded down
+    /// to the alignment. Only the last chunk of the content (or before a forced cut
+    /// point) may have an unaligned length, so all other chunks start and end at aligned
+    /// offsets relative to the start of the content. Chunks cut at the maximum size or
+    /// at the end of the content have a hash of zero.\
+    /// This method resets the internal context like
+    /// [set_content_length()](FastCDC::set_content_length) does.
+    ///
+    /// ```no_run
+    /// # use fastcdc_alt::FastCDC;
+    /// let contents = std::fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+    /// let mut chunker = FastCDC::new(4096, 16384, 65536).unwrap();
+    /// chunker.set_alignment(4096).unwrap();
+    /// for chunk in chunker.as_iterator(&contents) {
+    ///     println!("offset={} size={}", chunk.offset, chunk.get_length());
+    /// }
+    /// ```
+    ///
+    pub fn set_alignment(&mut self, alignment: usize) -> Result<(), Error> {
+        let min_size = self.min_size.div_ceil(alignment.max(1)) * alignment;
+        let max_size = self.max_size - self.max_size % alignment.max(1);
+
+        if self.record.is_some() {
+            return Err(Error::Other("an alignment cannot be combined with record delimiters".to_string()))
+        } else if !alignment.is_power_of_two() || alignment < 64 {
+            return Err(Error::Other(format!("{} is not a power of two of at least 64 (alignment)", alignment)))
+        } else if min_size > max_size {
+            return Err(Error::Other(format!("{} > {} (aligned min_size > aligned max_size)", min_size, max_size)))
+        }
+
+        let bits = logarithm2(self.avg_size as u32).saturating_sub(alignment.trailing_zeros());
+        self.alignment = Some(Alignment {
+            size: alignment,
+            min_size,
+            max_size,
+            bits_s: bits + self.normalization,
+            bits_l: bits.saturating_sub(self.normalization),
+        });
+        self.zero_runs = self.zero_runs_cut_free();
+        self.recycle_context(0);
 
+        Ok(())
+    }
+
+    ///
+    /// Stop aligning the cut points.\
+    /// This method resets the internal context like
+    /// [set_content_length()](FastCDC::set_content_length) does.
+    ///
+    pub fn clear_alignment(&mut self) {
+        self.alignment = None;
+        self.zero_runs = self.zero_runs_cut_free();
         self.recycle_context(0);
     }
 
+    ///
+    /// Use a gear table generated from the given seed instead of the default table.\
+    /// \
+    /// Chunkers with different seeds find different cut points for the same data, which
+    /// keeps the chunk boundaries from being predictable without knowing the seed. The
+    /// seed zero selects the default table, which is used by the original FastCDC
+    /// implementations.\
+    /// This method resets the internal context like
+    /// [set_content_length()](FastCDC::set_content_length) does.
+    ///
+    pub fn set_gear_seed(&mut self, seed: u64) {
+        self.gear = if seed == 0 { None } else { Some(Box::new(Gear::seeded(seed))) };
+        self.zero_runs = self.zero_runs_cut_free();
+        self.recycle_context(0);
+    }
+
+    ///
+    /// The seed of the gear table, which is zero for the default table.
+    ///
+    pub fn gear_seed(&self) -> u64 {
+        gear_table(&self.gear).seed
+    }
+
     #[inline(always)]
     fn recycle_context(&mut self, processed: usize) {
         self.context.remaining -= processed;
+        self.context.position += processed;
         self.context.processed = 0;
         self.context.last_cycle_incomplete = false;
         self.context.mask_ls = self.mask_s_ls;
         self.context.mask = self.mask_s;
-        self.context.index = self.min_size / 2;
+        self.context.index = match &self.alignment {
+            Some(alignment) => alignment.min_size - 64,
+            None => self.min_size / 2,
+        };
         self.context.hash = 0;
+        self.context.search_end = None;
+        self.context.search_pos = 0;
+        self.context.candidate = 0;
+        self.context.matched = 0;
+        self.context.zero_run = self.zero_runs;
+        self.context.nonzero = usize::MAX;
+    }
+
+    /// Look for the first non-zero byte of the current chunk in the buffer, as long
+    /// as all bytes before it are zero.
+    #[inline(always)]
+    fn find_nonzero(&mut self, buffer: &[u8], end: usize) {
+        if self.context.nonzero == usize::MAX {
+            let length = buffer.len().min(end.saturating_sub(self.context.processed));
+            let zeros = zero_prefix(&buffer[..length]);
+            if zeros < length {
+                self.context.nonzero = self.context.processed + zeros;
+            }
+        }
+    }
+
+    /// Whether the current chunk consists only of zero bytes if it ends at the given offset.
+    #[inline(always)]
+    fn is_zero(&self, end: usize) -> bool {
+        end <= self.context.nonzero
+    }
+
+    /// Check whether no hash of a run of zero bytes is accepted as a cut point.
+    fn zero_runs_cut_free(&self) -> bool {
+        let gear = gear_table(&self.gear);
+        match &self.alignment {
+            Some(alignment) => alignment.bits_l > 0 && zero_run_hash(gear, 64) >> (64 - alignment.bits_l) != 0,
+            None => (1..=64).map(|length| zero_run_hash(gear, length)).all(|hash| {
+                [self.mask_s, self.mask_l, self.mask_s_ls, self.mask_l_ls].iter().all(|mask| hash & mask != 0)
+            }),
+        }
+    }
+
+    /// Skip the zero bytes at the start of the current chunk without hashing them.\
+    /// Returns the result of the cut if the chunk consists only of zero bytes or the
+    /// buffer is exhausted, otherwise the context continues after the zero run.
+    #[inline(never)]
+    fn cut_zeros(&mut self, buffer: &[u8], remaining: usize, center: usize) -> Option<Option<Chunk>> {
+        let end = match &self.alignment {
+            Some(alignment) if remaining >= self.min_size => remaining.min(alignment.max_size),
+            _ => remaining,
+        };
+
+        let length = buffer.len().min(end - self.context.processed);
+        let zeros = self.context.nonzero.saturating_sub(self.context.processed).min(length);
+        if zeros == length {
+            if self.context.processed + length < end {
+                self.context.processed += buffer.len();
+                return Some(None);
+            }
+
+            let hash = if remaining < self.min_size || self.alignment.is_some() {
+                0
+            } else {
+                zero_run_hash(gear_table(&self.gear), 2 * (remaining / 2) - 2 * (self.min_size / 2))
+            };
+            let result = Some(Chunk {
+                hash,
+                offset: -(self.context.processed as isize),
+                cutpoint: length,
+                zero: true
+            });
+
+            self.recycle_context(end);
+
+            return Some(result);
+        }
+
+        // Continue with the state the hash would have after the zero run.
+        let position = self.context.processed + zeros;
+        self.context.zero_run = false;
+        if remaining < self.min_size {
+            return None;
+        }
+
+        match &self.alignment {
+            Some(alignment) => {
+                let pos = alignment.min_size.max((position / alignment.size + 1) * alignment.size);
+                if position > pos - 64 {
+                    self.context.index = position;
+                    self.context.hash = zero_run_hash(gear_table(&self.gear), position - (pos - 64));
+                } else {
+                    self.context.index = pos - 64;
+                }
+            }
+            None => {
+                self.context.index = (self.min_size / 2).max(position / 2);
+                let hashed = 2 * self.context.index - 2 * (self.min_size / 2);
+                if 2 * self.context.index < self.context.processed {
+                    // The first byte of the pair is in the previous buffer.
+                    self.context.hash = zero_run_hash(gear_table(&self.gear), hashed + 1);
+                    self.context.last_cycle_incomplete = true;
+                } else {
+                    self.context.hash = zero_run_hash(gear_table(&self.gear), hashed);
+                }
+                if self.context.index >= center / 2 && center / 2 > self.min_size / 2 {
+                    self.context.mask_ls = self.mask_l_ls;
+                    self.context.mask = self.mask_l;
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Find the next aligned cut point, where the index is the next byte to hash.
+    fn cut_aligned<V: FnMut(usize) -> bool>(&mut self, buffer: &[u8], remaining: usize, center: usize, mut veto: V) -> Option<Chunk> {
+        let (size, max_size, bits_s, bits_l) = match &self.alignment {
+            Some(alignment) => (alignment.size, alignment.max_size, alignment.bits_s, alignment.bits_l),
+            None => return None,
+        };
+        let end = remaining.min(max_size);
+
+        loop {
+            let pos = (self.context.index / size + 1) * size;
+            if pos >= end {
+                break;
+            }
+
+            while self.context.index < pos {
+                let pos_in_buffer = self.context.index - self.context.processed;
+                if pos_in_buffer >= buffer.len() {
+                    self.context.processed += buffer.len();
+                    return None;
+                }
+
+                self.context.hash = (self.context.hash << 1).wrapping_add(gear_table(&self.gear).table[buffer[pos_in_buffer] as usize]);
+                self.context.index += 1;
+            }
+
+            let bits = if pos < center { bits_s } else { bits_l };
+            if (bits == 0 || self.context.hash >> (64 - bits) == 0) && !veto(self.context.position + pos) {
+                let result = Some(Chunk {
+                    hash: self.context.hash,
+                    offset: -(self.context.processed as isize),
+                    cutpoint: pos - self.context.processed,
+                    zero: self.is_zero(pos)
+                });
+
+                self.recycle_context(pos);
+
+                return result;
+            }
+
+            self.context.hash = 0;
+            self.context.index = pos + size - 64;
+        }
+
+        let cutpoint = end - self.context.processed;
+        if cutpoint > buffer.len() {
+            self.context.processed += buffer.len();
+            return None;
+        }
+
+        let result = Some(Chunk {
+            hash: 0,
+            offset: -(self.context.processed as isize),
+            cutpoint,
+            zero: self.is_zero(end)
+        });
+
+        self.recycle_context(end);
+
+        result
+    }
+
+    /// Start searching for a record delimiter at the given candidate cut point.
+    #[inline(never)]
+    fn start_record_search(&mut self, buffer: &[u8], cut: usize, remaining: usize) -> Option<Chunk> {
+        let window = self.record.as_ref().map_or(0, |record| record.window);
+        self.context.search_end = Some((cut + window).min(remaining));
+        self.context.search_pos = cut;
+        self.context.candidate = cut;
+        self.context.matched = 0;
+
+        self.search_record(buffer)
+    }
+
+    /// Continue searching for a record delimiter within the window.
+    fn search_record(&mut self, buffer: &[u8]) -> Option<Chunk> {
+        let processed = self.context.processed;
+        let end = self.context.search_end? - processed;
+        let start = self.context.search_pos - processed;
+
+        let mut found = None;
+        if let Some(record) = self.record.as_ref() {
+            let delimiter = &record.delimiter;
+            let mut matched = self.context.matched;
+            for (index, byte) in buffer[start..end.min(buffer.len())].iter().enumerate() {
+                while matched > 0 && *byte != delimiter[matched] {
+                    matched = record.failure[matched - 1];
+                }
+                if *byte == delimiter[matched] {
+                    matched += 1;
+                }
+                if matched == delimiter.len() {
+                    found = Some(start + index + 1);
+                    break;
+                }
+            }
+            self.context.matched = matched;
+        }
+
+        let cutpoint = match found {
+            Some(cutpoint) => cutpoint,
+            // Fall back to the content-defined cut point, which may lie in a previous buffer.
+            None if end <= buffer.len() => (self.context.candidate as isize - processed as isize) as usize,
+            None => {
+                self.context.search_pos = processed + buffer.len();
+                self.context.processed += buffer.len();
+                return None;
+            }
+        };
+
+        let end = processed.wrapping_add(cutpoint);
+        let result = Some(Chunk {
+            hash: self.context.hash,
+            offset: -(processed as isize),
+            cutpoint,
+            zero: self.is_zero(end)
+        });
+
+        self.recycle_context(end);
+
+        result
     }
 
     ///
@@ -504,7 +1114,54 @@ impl FastCDC {
         &mut self,
         buffer: &[u8]
     ) -> Option<Chunk> {
+        self.cut_with_veto(buffer, |_| false)
+    }
+
+    ///
+    /// Try to identify the next cut point in the data, like [cut()](FastCDC::cut),
+    /// while letting the caller reject candidate cut points.\
+    /// \
+    /// The `veto` callback is invoked with the offset of each content-defined
+    /// candidate cut point, relative to the start of the content (see
+    /// [position()](FastCDC::position)), and rejects the candidate by returning `true`.
+    /// The search then continues as if the hash did not match. Cut points at the
+    /// maximum chunk size, at forced cut points and at the end of the content
+    /// cannot be rejected.
+    ///
+    /// ```no_run
+    /// # use fastcdc_alt::FastCDC;
+    /// let contents = std::fs::read("records.bin").unwrap();
+    /// let record_starts: Vec<usize> = vec![0, 4711, 9000];
+    ///
+    /// let mut chunker = FastCDC::new(4096, 16384, 65535).unwrap();
+    /// chunker.set_content_length(contents.len());
+    ///
+    /// let mut cursor = 0;
+    /// while let Some(chunk) = chunker.cut_with_veto(&contents[cursor..], |offset| record_starts.binary_search(&offset).is_err()) {
+    ///     cursor += chunk.cutpoint;
+    /// }
+    /// ```
+    ///
+    #[inline]
+    pub fn cut_with_veto<V: FnMut(usize) -> bool>(
+        &mut self,
+        buffer: &[u8],
+        mut veto: V
+    ) -> Option<Chunk> {
+        self.find_nonzero(buffer, self.context.remaining.min(self.max_size));
+        if self.context.search_end.is_some() {
+            return self.search_record(buffer);
+        }
+
         let mut remaining = self.context.remaining;
+        while let Some(&offset) = self.forced_cuts.first() {
+            if offset > self.context.position {
+                remaining = remaining.min(offset - self.context.position);
+                break;
+            }
+            self.forced_cuts.remove(&offset);
+        }
+
         let mut center = self.avg_size;
         if remaining > self.max_size {
             remaining = self.max_size;
@@ -512,6 +1169,12 @@ impl FastCDC {
             center = remaining;
         }
 
+        if self.context.zero_run && remaining > 0 {
+            if let Some(result) = self.cut_zeros(buffer, remaining, center) {
+                return result;
+            }
+        }
+
         if remaining < self.min_size {
             return if remaining == 0 {
                 None
@@ -521,23 +1184,38 @@ impl FastCDC {
                     self.context.processed += buffer.len();
                     None
                 } else {
-                    Some(Chunk {
+                    let result = Some(Chunk {
                         hash: 0,
                         offset: -(self.context.processed as isize),
-                        cutpoint
-                    })
+                        cutpoint,
+                        zero: self.is_zero(remaining)
+                    });
+
+                    self.recycle_context(remaining);
+
+                    result
                 }
             }
         }
 
+        if self.alignment.is_some() {
+            return self.cut_aligned(buffer, remaining, center, veto);
+        }
+
+        let gear = gear_table(&self.gear);
         if self.context.last_cycle_incomplete {
-            self.context.hash = self.context.hash.wrapping_add(GEAR[buffer[0] as usize]);
-            if (self.context.hash & self.context.mask) == 0 {
-                let pos = self.context.index * 2;
+            self.context.hash = self.context.hash.wrapping_add(gear.table[buffer[0] as usize]);
+            let pos = self.context.index * 2;
+            if (self.context.hash & self.context.mask) == 0 && !veto(self.context.position + pos + 1) {
+                if self.record.is_some() {
+                    return self.start_record_search(buffer, pos + 1, remaining);
+                }
+
                 let result = Some(Chunk {
                     hash: self.context.hash,
                     offset: -(self.context.processed as isize),
-                    cutpoint: 0
+                    cutpoint: 0,
+                    zero: self.is_zero(pos + 1)
                 });
 
                 self.recycle_context(pos + 1);
@@ -554,6 +1232,10 @@ impl FastCDC {
             }
         }
 
+        if self.context.processed + buffer.len() >= remaining {
+            return self.cut_contiguous(buffer, remaining, center, veto);
+        }
+
         while self.context.index < remaining / 2 {
             let pos = self.context.index * 2;
             let pos_in_buffer = pos - self.context.processed;
@@ -562,12 +1244,17 @@ impl FastCDC {
                 return None;
             }
 
-            self.context.hash = (self.context.hash << 2).wrapping_add(GEAR_LS[buffer[pos_in_buffer] as usize]);
-            if (self.context.hash & self.context.mask_ls) == 0 {
+            self.context.hash = (self.context.hash << 2).wrapping_add(gear.table_ls[buffer[pos_in_buffer] as usize]);
+            if (self.context.hash & self.context.mask_ls) == 0 && !veto(self.context.position + pos) {
+                if self.record.is_some() {
+                    return self.start_record_search(buffer, pos, remaining);
+                }
+
                 let result = Some(Chunk {
                     hash: self.context.hash,
                     offset: -(self.context.processed as isize),
-                    cutpoint: pos_in_buffer
+                    cutpoint: pos_in_buffer,
+                    zero: self.is_zero(pos)
                 });
 
                 self.recycle_context(pos);
@@ -581,12 +1268,17 @@ impl FastCDC {
                 return None;
             }
 
-            self.context.hash = self.context.hash.wrapping_add(GEAR[buffer[pos_in_buffer + 1] as usize]);
-            if (self.context.hash & self.context.mask) == 0 {
+            self.context.hash = self.context.hash.wrapping_add(gear.table[buffer[pos_in_buffer + 1] as usize]);
+            if (self.context.hash & self.context.mask) == 0 && !veto(self.context.position + pos + 1) {
+                if self.record.is_some() {
+                    return self.start_record_search(buffer, pos + 1, remaining);
+                }
+
                 let result = Some(Chunk {
                     hash: self.context.hash,
                     offset: -(self.context.processed as isize),
-                    cutpoint: pos_in_buffer + 1
+                    cutpoint: pos_in_buffer + 1,
+                    zero: self.is_zero(pos + 1)
                 });
 
                 self.recycle_context(pos + 1);
@@ -603,12 +1295,77 @@ impl FastCDC {
         }
 
         // If all else fails, return the largest chunk. This will happen with
-        // pathological data, such as all zeroes.
+        // pathological data, such as all zeroes. With an odd size, its last byte
+        // may be in the next buffer.
+        let cutpoint = remaining - self.context.processed;
+        if cutpoint > buffer.len() {
+            self.context.processed += buffer.len();
+            return None;
+        }
+
         let hash = self.context.hash;
         let result = Some(Chunk {
             hash,
             offset: -(self.context.processed as isize),
-            cutpoint: remaining - self.context.processed
+            cutpoint,
+            zero: self.is_zero(remaining)
+        });
+
+        self.recycle_context(remaining);
+
+        result
+    }
+
+    /// Find the cut point of a chunk whose remaining bytes are all in the buffer,
+    /// keeping the hashing state in locals instead of the context.
+    fn cut_contiguous<V: FnMut(usize) -> bool>(&mut self, buffer: &[u8], remaining: usize, center: usize, mut veto: V) -> Option<Chunk> {
+        let processed = self.context.processed;
+        let end = remaining / 2;
+        let switch = center / 2;
+        let mut index = self.context.index;
+        let mut hash = self.context.hash;
+        let mut mask = self.context.mask;
+        let mut mask_ls = self.context.mask_ls;
+
+        while index < end {
+            let stop = if index < switch { switch.min(end) } else { end };
+            let bytes = &buffer[2 * index - processed..2 * stop - processed];
+            let found = scan_pairs(gear_table(&self.gear), bytes, &mut hash, mask, mask_ls, |offset| {
+                veto(self.context.position + 2 * index + offset)
+            });
+
+            if let Some(offset) = found {
+                let pos = 2 * index + offset;
+                self.context.hash = hash;
+                if self.record.is_some() {
+                    return self.start_record_search(buffer, pos, remaining);
+                }
+
+                let result = Some(Chunk {
+                    hash,
+                    offset: -(processed as isize),
+                    cutpoint: pos - processed,
+                    zero: self.is_zero(pos)
+                });
+
+                self.recycle_context(pos);
+
+                return result;
+            }
+
+            index = stop;
+            if index == switch {
+                mask = self.mask_l;
+                mask_ls = self.mask_l_ls;
+            }
+        }
+
+        // If all else fails, return the largest chunk.
+        let result = Some(Chunk {
+            hash,
+            offset: -(processed as isize),
+            cutpoint: remaining - processed,
+            zero: self.is_zero(remaining)
         });
 
         self.recycle_context(remaining);
@@ -628,6 +1385,16 @@ impl FastCDC {
             buffer
         }
     }
+
+    /// The masks that may have been applied to the hash of a chunk at its cut point.
+    fn judged_masks(&self) -> [u64; 4] {
+        [self.mask_s, self.mask_l, self.mask_s_ls, self.mask_l_ls]
+    }
+
+    /// The index of the lowest hash bit above all bits judged by the masks.
+    fn free_bits_shift(&self) -> u32 {
+        64 - self.judged_masks().iter().fold(0, |acc, mask| acc | mask).leading_zeros()
+    }
 }
 
 pub struct FastCDCIterator<'a, 'b> {
@@ -654,7 +1421,8 @@ impl<'a, 'b> Iterator for FastCDCIterator<'a, 'b> {
         let chunk = Chunk {
             hash: chunk.hash,
             offset: self.cursor as isize,
-            cutpoint
+            cutpoint,
+            zero: chunk.zero
         };
 
         self.cursor = cutpoint;
@@ -680,6 +1448,7 @@ pub enum Error {
     /// End of source data reached.
     Empty,
     /// An I/O error occurred.
+    #[cfg(feature = "std")]
     IoError(std::io::Error),
     /// Something unexpected happened.
     Other(String),
@@ -691,14 +1460,17 @@ impl fmt::Display for Error {
     }
 }
 
+#[cfg(feature = "std")]
 impl std::error::Error for Error {}
 
+#[cfg(feature = "std")]
 impl From<std::io::Error> for Error {
     fn from(error: std::io::Error) -> Self {
         Error::IoError(error)
     }
 }
 
+#[cfg(feature = "std")]
 impl From<Error> for std::io::Error {
     fn from(error: Error) -> Self {
         match error {
@@ -709,6 +1481,34 @@ impl From<Error> for std::io::Error {
     }
 }
 
+/// Forced cut points and the veto callback of a streaming chunker, with offsets
+/// relative to the start of the stream.
+#[cfg(feature = "std")]
+#[derive(Default)]
+struct StreamCuts {
+    forced: BTreeSet<usize>,
+    veto: Option<Box<dyn FnMut(usize) -> bool + Send>>,
+}
+
+#[cfg(feature = "std")]
+impl StreamCuts {
+    /// Find the next chunk in the buffer, which holds the rest of the content and
+    /// starts at the given offset of the stream.
+    fn cut(&mut self, inner: &mut FastCDC, processed: usize, buffer: &[u8]) -> Option<Chunk> {
+        inner.set_content_length(buffer.len());
+        inner.clear_forced_cuts();
+        self.forced = self.forced.split_off(&(processed + 1));
+        if let Some(&offset) = self.forced.first() {
+            inner.force_cut(offset - processed);
+        }
+
+        match &mut self.veto {
+            Some(veto) => inner.cut_with_veto(buffer, |offset| veto(processed + offset)),
+            None => inner.cut(buffer),
+        }
+    }
+}
+
 ///
 /// The FastCDC chunker implementation from 2020 with streaming support.\
 ///\
@@ -729,6 +1529,7 @@ impl From<Error> for std::io::Error {
 /// }
 /// ```
 ///
+#[cfg(feature = "std")]
 pub struct StreamCDC<R: Read> {
     inner: FastCDC,
 
@@ -743,9 +1544,15 @@ pub struct StreamCDC<R: Read> {
     /// Number of bytes read from the source so far.
     processed: usize,
     /// True when the source produces no more data.
-    eof: bool
+    eof: bool,
+    /// Forced cut points and the veto callback.
+    cuts: StreamCuts,
+    /// Data extents of a sparse file source, if holes are skipped.
+    #[cfg(target_os = "linux")]
+    holes: Option<sparse::Holes>
 }
 
+#[cfg(feature = "std")]
 impl<R: Read> StreamCDC<R> {
     ///
     /// Construct a `StreamCDC` that will process bytes from the given source.
@@ -774,9 +1581,72 @@ impl<R: Read> StreamCDC<R> {
             source,
             processed: 0,
             eof: false,
+            cuts: StreamCuts::default(),
+            #[cfg(target_os = "linux")]
+            holes: None,
         })
     }
 
+    ///
+    /// Align the cut points to the ends of records, see [FastCDC::set_record_delimiter].
+    ///
+    pub fn set_record_delimiter(&mut self, delimiter: &[u8], window: usize) -> Result<(), Error> {
+        self.inner.set_record_delimiter(delimiter, window)
+    }
+
+    ///
+    /// Only accept cut points at multiples of the given alignment, see [FastCDC::set_alignment].
+    ///
+    pub fn set_alignment(&mut self, alignment: usize) -> Result<(), Error> {
+        self.inner.set_alignment(alignment)
+    }
+
+    ///
+    /// Use a gear table generated from the given seed, see [FastCDC::set_gear_seed].
+    ///
+    pub fn set_gear_seed(&mut self, seed: u64) {
+        self.inner.set_gear_seed(seed)
+    }
+
+    ///
+    /// Force a cut point at the given offset relative to the start of the stream,
+    /// see [FastCDC::force_cut].\
+    /// Offsets at or before the end of the last returned chunk are ignored.
+    ///
+    /// ```no_run
+    /// # use std::fs::File;
+    /// # use std::io::Read;
+    /// # use fastcdc_alt::StreamCDC;
+    /// let first = File::open("first.bin").unwrap();
+    /// let length = first.metadata().unwrap().len() as usize;
+    /// let source = first.chain(File::open("second.bin").unwrap());
+    /// let mut chunker = StreamCDC::new(source, 4096, 16384, 65535).unwrap();
+    /// chunker.force_cut(length);
+    /// for result in chunker {
+    ///     let (_data, chunk) = result.unwrap();
+    ///     println!("offset={} length={}", chunk.offset, chunk.get_length());
+    /// }
+    /// ```
+    ///
+    pub fn force_cut(&mut self, offset: usize) {
+        self.cuts.forced.insert(offset);
+    }
+
+    ///
+    /// Remove all pending forced cut points.
+    ///
+    pub fn clear_forced_cuts(&mut self) {
+        self.cuts.forced.clear();
+    }
+
+    ///
+    /// Reject candidate cut points with the given callback, see [FastCDC::cut_with_veto].\
+    /// The callback is invoked with offsets relative to the start of the stream.
+    ///
+    pub fn set_veto<V: FnMut(usize) -> bool + Send + 'static>(&mut self, veto: V) {
+        self.cuts.veto = Some(Box::new(veto));
+    }
+
     /// Fill the buffer with data from the source, returning the number of bytes
     /// read (zero if end of source has been reached).
     fn fill_buffer(&mut self) -> Result<usize, Error> {
@@ -786,12 +1656,34 @@ impl<R: Read> StreamCDC<R> {
         } else {
             let mut all_bytes_read = 0;
             while !self.eof && self.length < self.capacity {
-                let bytes_read = self.source.read(&mut self.buffer[self.length..])?;
+                let mut end = self.capacity;
+
+                #[cfg(target_os = "linux")]
+                if let Some(holes) = &mut self.holes {
+                    let (zeros, data) = holes.next_extent(self.capacity - self.length)?;
+                    if zeros > 0 {
+                        self.buffer[self.length..self.length + zeros].fill(0);
+                        self.length += zeros;
+                        all_bytes_read += zeros;
+                        continue;
+                    } else if data == 0 {
+                        self.eof = true;
+                        continue;
+                    }
+                    end = self.length + data;
+                }
+
+                let bytes_read = self.source.read(&mut self.buffer[self.length..end])?;
                 if bytes_read == 0 {
                     self.eof = true;
                 } else {
                     self.length += bytes_read;
                     all_bytes_read += bytes_read;
+
+                    #[cfg(target_os = "linux")]
+                    if let Some(holes) = &mut self.holes {
+                        holes.advance(bytes_read);
+                    }
                 }
             }
             Ok(all_bytes_read)
@@ -822,16 +1714,15 @@ impl<R: Read> StreamCDC<R> {
         if self.length == 0 {
             Err(Error::Empty)
         } else {
-            self.inner.set_content_length(self.length);
-
-            let chunk = self.inner.cut(&self.buffer[..self.length]).ok_or(Error::Empty)?;
+            let chunk = self.cuts.cut(&mut self.inner, self.processed, &self.buffer[..self.length]).ok_or(Error::Empty)?;
             let data = self.drain_bytes(chunk.cutpoint)?;
 
             let cutpoint = self.processed + chunk.cutpoint;
             let chunk = Chunk {
                 hash: chunk.hash,
                 offset: self.processed as isize,
-                cutpoint
+                cutpoint,
+                zero: chunk.zero
             };
 
             self.processed = cutpoint;
@@ -841,6 +1732,35 @@ impl<R: Read> StreamCDC<R> {
     }
 }
 
+#[cfg(all(feature = "std", target_os = "linux"))]
+impl StreamCDC<File> {
+    ///
+    /// Skip the holes of a sparse file instead of reading them.\
+    /// \
+    /// The data extents are found with `SEEK_DATA` and `SEEK_HOLE`, starting at the
+    /// current position of the file. Holes are passed to the chunker as zero bytes,
+    /// which are chunked without hashing them, so the resulting chunks are the same
+    /// as when reading the file. Fails if the file does not support seeking.
+    ///
+    /// ```no_run
+    /// # use std::fs::File;
+    /// # use fastcdc_alt::StreamCDC;
+    /// let source = File::open("disk.img").unwrap();
+    /// let mut chunker = StreamCDC::new(source, 4096, 16384, 65535).unwrap();
+    /// chunker.skip_holes().unwrap();
+    /// for result in chunker {
+    ///     let (_data, chunk) = result.unwrap();
+    ///     println!("offset={} size={} zero={}", chunk.offset, chunk.get_length(), chunk.zero);
+    /// }
+    /// ```
+    ///
+    pub fn skip_holes(&mut self) -> Result<(), Error> {
+        self.holes = Some(sparse::Holes::new(self.source.as_raw_fd())?);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
 impl<R: Read> Iterator for StreamCDC<R> {
     type Item = Result<(Vec<u8>, Chunk), Error>;
 
@@ -854,18 +1774,114 @@ impl<R: Read> Iterator for StreamCDC<R> {
     }
 }
 
+/// Hash the byte pairs until a candidate cut point is not vetoed, returning its offset
+/// within the bytes. The hash is left at the cut point, or after the last pair.
+#[inline(always)]
+fn scan_pairs<V: FnMut(usize) -> bool>(gear: &Gear, bytes: &[u8], hash: &mut u64, mask: u64, mask_ls: u64, mut veto: V) -> Option<usize> {
+    let mut current = *hash;
+    let mut step = |pair: &[u8], offset: usize, veto: &mut V| {
+        current = (current << 2).wrapping_add(gear.table_ls[pair[0] as usize]);
+        if (current & mask_ls) == 0 && !veto(offset) {
+            return Some(offset);
+        }
+        current = current.wrapping_add(gear.table[pair[1] as usize]);
+        if (current & mask) == 0 && !veto(offset + 1) {
+            return Some(offset + 1);
+        }
+        None
+    };
+
+    // Four pairs per block, so the loop over the block is unrolled.
+    let blocks = bytes.chunks_exact(8);
+    let rest = blocks.remainder();
+    let mut found = None;
+    'blocks: for (index, block) in blocks.enumerate() {
+        for pair in 0..4 {
+            found = step(&block[2 * pair..2 * pair + 2], 8 * index + 2 * pair, &mut veto);
+            if found.is_some() {
+                break 'blocks;
+            }
+        }
+    }
+    if found.is_none() {
+        let start = bytes.len() - rest.len();
+        found = rest.chunks_exact(2).enumerate().find_map(|(index, pair)| step(pair, start + 2 * index, &mut veto));
+    }
+
+    *hash = current;
+    found
+}
+
+/// The gear hash after the given number of zero bytes, which is constant from 64 bytes on.
+fn zero_run_hash(gear: &Gear, length: usize) -> u64 {
+    (0..length.min(64)).fold(0, |hash: u64, _| (hash << 1).wrapping_add(gear.table[0]))
+}
+
+/// The number of leading zero bytes.
+fn zero_prefix(bytes: &[u8]) -> usize {
+    let blocks = bytes.chunks_exact(32).take_while(|block| block.iter().fold(0, |acc, &byte| acc | byte) == 0).count();
+    let start = blocks * 32;
+    start + bytes[start..].iter().take_while(|&&byte| byte == 0).count()
+}
+
 ///
 /// Base-2 logarithm function for unsigned 32-bit integers.
 ///
-pub fn logarithm2(value: u32) -> u32 {
-    f64::from(value).log2().round() as u32
+pub const fn logarithm2(value: u32) -> u32 {
+    if value == 0 {
+        return 0;
+    }
+
+    // Round up if value >= 2^(floor + 0.5), i.e. value^2 >= 2^(2 * floor + 1).
+    let floor = 31 - value.leading_zeros();
+    if (value as u64) * (value as u64) >= 1 << (2 * floor + 1) {
+        floor + 1
+    } else {
+        floor
+    }
+}
+
+/// Expected hashes and lengths of the chunks of SekienAkashita.jpg, shared by the tests.
+#[cfg(test)]
+pub(crate) mod vectors {
+    pub(crate) const SEKIEN_16K_CHUNKS: [(u64, usize); 5] = [
+        (17968276318003433923, 21325),
+        (8197189939299398838, 17140),
+        (13019990849178155730, 28084),
+        (4509236223063678303, 18217),
+        (2504464741100432583, 24700),
+    ];
+    pub(crate) const SEKIEN_32K_CHUNKS: [(u64, usize); 2] = [
+        (15733367461443853673, 66549),
+        (6321136627705800457, 42917),
+    ];
+    pub(crate) const SEKIEN_64K_CHUNKS: [(u64, usize); 1] = [
+        (2504464741100432583, 109466),
+    ];
+    pub(crate) const SEKIEN_16K_NC_0_CHUNKS: [(u64, usize); 5] = [
+        (443122261039895162, 6634),
+        (15733367461443853673, 59915),
+        (10460176299449652894, 25597),
+        (6197802202431009942, 5237),
+        (6321136627705800457, 12083),
+    ];
+    pub(crate) const SEKIEN_16K_NC_3_CHUNKS: [(u64, usize); 6] = [
+        (10718006254707412376, 17350),
+        (13104072099671895560, 19911),
+        (12322483109039221194, 17426),
+        (16009206469796846404, 17519),
+        (2473608525189754172, 19940),
+        (2504464741100432583, 17320),
+    ];
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use md5::{Digest, Md5};
-    use std::fs::{self, File};
+    use std::fs;
+    #[cfg(feature = "std")]
+    use std::fs::File;
 
     #[test]
     fn test_logarithm2() {
@@ -1142,6 +2158,7 @@ mod tests {
         assert_eq!(format!("{err}"), "chunker error: Empty");
     }
 
+    #[cfg(feature = "std")]
     #[test]
     fn test_stream_sekien_16k_chunks() {
         let file_result = File::open("test/fixtures/SekienAkashita.jpg");
@@ -1250,4 +2267,468 @@ mod tests {
     fn test_cut_sekien_16k_nc_3_cross_buffer_incomplete_cycle() {
         cut_sekien_16k_nc_3_cross_buffer(true);
     }
+
+    #[test]
+    fn test_cut_tail_below_minimum() {
+        let array = [0u8; 100];
+        let mut chunker = FastCDC::new_advanced(128, 256, 1024, Normalization::Level1, Some(100)).unwrap();
+        let chunk = chunker.cut(&array).unwrap();
+        assert_eq!(chunk.hash, 0);
+        assert_eq!(chunk.cutpoint, 100);
+        assert_eq!(chunker.cut(&[]), None);
+    }
+
+    #[test]
+    fn test_force_cut_sekien() {
+        let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        let split = 50_000;
+
+        let mut chunker = FastCDC::new(4096, 16384, 65535).unwrap();
+        let mut expected = chunker.as_iterator(&contents[..split]).collect::<Vec<_>>();
+        expected.extend(chunker.as_iterator(&contents[split..]).map(|chunk| Chunk {
+            hash: chunk.hash,
+            offset: chunk.offset + split as isize,
+            cutpoint: chunk.cutpoint + split,
+            zero: chunk.zero
+        }));
+
+        chunker.force_cut(split);
+        let chunks = chunker.as_iterator(&contents).collect::<Vec<_>>();
+        assert_eq!(chunks, expected);
+
+        // The forced cut point has been passed and is no longer applied.
+        let chunks = chunker.as_iterator(&contents).collect::<Vec<_>>();
+        assert_eq!(chunks.len(), 5);
+    }
+
+    #[test]
+    fn test_force_cut_cross_buffer() {
+        let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        let mut chunker = FastCDC::new(4096, 16384, 65535).unwrap();
+        for offset in [1000, 21325, 30001, 100_000] {
+            chunker.force_cut(offset);
+        }
+        let expected = chunker.as_iterator(&contents).collect::<Vec<_>>();
+        assert!(expected.iter().any(|chunk| chunk.offset == 30001));
+
+        for offset in [1000, 21325, 30001, 100_000] {
+            chunker.force_cut(offset);
+        }
+        chunker.set_content_length(contents.len());
+        let mut cursor = 0;
+        let mut chunks = Vec::new();
+        for buffer in contents.chunks(4005) {
+            let mut buffer_cursor = 0;
+            while let Some(chunk) = chunker.cut(&buffer[buffer_cursor..]) {
+                buffer_cursor += chunk.cutpoint;
+                chunks.push((cursor, chunker.position()));
+                cursor = chunker.position();
+                if buffer_cursor == buffer.len() {
+                    break;
+                }
+            }
+        }
+        let expected = expected.iter().map(|chunk| (chunk.offset as usize, chunk.cutpoint)).collect::<Vec<_>>();
+        assert_eq!(chunks, expected);
+    }
+
+    fn records(seed: u64, count: usize, delimiter: &[u8]) -> Vec<u8> {
+        let mut state = seed;
+        let mut contents = Vec::new();
+        for _ in 0..count {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let length = 20 + (state % 200) as usize;
+            contents.extend((0..length).map(|index| b'a' + ((state >> (index % 48)) % 26) as u8));
+            contents.extend_from_slice(delimiter);
+        }
+        contents
+    }
+
+    #[test]
+    fn test_record_delimiter_invalid() {
+        let mut chunker = FastCDC::new(256, 1024, 4096).unwrap();
+        assert!(chunker.set_record_delimiter(b"", 1024).is_err());
+        assert!(chunker.set_record_delimiter(b"\r\n", 1).is_err());
+        assert!(chunker.set_record_delimiter(b"\r\n", 2).is_ok());
+    }
+
+    #[test]
+    fn test_record_aligned_chunks() {
+        let contents = records(0x2545f4914f6cdd1d, 5000, b"\n");
+        let mut chunker = FastCDC::new(256, 1024, 4096).unwrap();
+        chunker.set_record_delimiter(b"\n", 1024).unwrap();
+        let chunks = chunker.as_iterator(&contents).collect::<Vec<_>>();
+
+        assert!(chunks.len() > 100);
+        assert!(chunks.iter().filter(|chunk| chunk.get_length() == 4096).count() * 10 < chunks.len());
+        assert_eq!(chunks.last().unwrap().cutpoint, contents.len());
+        // Only chunks whose window is limited by the maximum size may miss a delimiter.
+        for chunk in &chunks {
+            assert!(contents[..chunk.cutpoint].ends_with(b"\n") || chunk.get_length() + 1024 > 4096);
+        }
+
+        // Cross-buffer results are the same, also for the multi-byte delimiter.
+        for delimiter in [&b"\n"[..], &b"\r\n"[..]] {
+            let contents = records(0x9e3779b97f4a7c15, 5000, delimiter);
+            chunker.set_record_delimiter(delimiter, 1024).unwrap();
+            let expected = chunker.as_iterator(&contents).map(|chunk| chunk.cutpoint).collect::<Vec<_>>();
+
+            chunker.set_content_length(contents.len());
+            let mut cutpoints = Vec::new();
+            let mut offset = 0;
+            for buffer in contents.chunks(333) {
+                let mut cursor = 0;
+                while let Some(chunk) = chunker.cut(&buffer[cursor..]) {
+                    cursor += chunk.cutpoint;
+                    cutpoints.push(offset + cursor);
+                    if cursor == buffer.len() {
+                        break;
+                    }
+                }
+                offset += buffer.len();
+            }
+            assert_eq!(cutpoints, expected);
+        }
+    }
+
+    #[test]
+    fn test_record_delimiter_window_exceeded() {
+        let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        let mut chunker = FastCDC::new(4096, 16384, 65535).unwrap();
+        chunker.set_record_delimiter(b"not in the file", 100).unwrap();
+        let chunks = chunker.as_iterator(&contents).map(|chunk| chunk.cutpoint).collect::<Vec<_>>();
+        assert_eq!(chunks, vec![21325, 38465, 66549, 84766, 109466]);
+
+        // The content-defined cut point at 21325 has passed when the next buffer arrives,
+        // so the next buffer starts at the negative cut point again.
+        chunker.set_content_length(contents.len());
+        assert_eq!(chunker.cut(&contents[..21400]), None);
+        let chunk = chunker.cut(&contents[21400..]).unwrap();
+        assert_eq!((chunk.offset, chunk.cutpoint as isize, chunk.get_length()), (-21400, -75, 21325));
+
+        let mut split = vec![21325];
+        let mut cursor = 21325;
+        while let Some(chunk) = chunker.cut(&contents[cursor..]) {
+            cursor += chunk.cutpoint;
+            split.push(cursor);
+        }
+        assert_eq!(split, chunks);
+    }
+
+    #[test]
+    fn test_cut_with_veto() {
+        let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        let mut chunker = FastCDC::new(4096, 16384, 65535).unwrap();
+        chunker.set_content_length(contents.len());
+
+        let mut candidates = Vec::new();
+        let mut cursor = 0;
+        while let Some(chunk) = chunker.cut_with_veto(&contents[cursor..], |offset| {
+            candidates.push(offset);
+            offset == 21325
+        }) {
+            cursor += chunk.cutpoint;
+            assert_ne!(cursor, 21325);
+        }
+        assert_eq!(cursor, contents.len());
+        assert!(candidates.contains(&21325));
+
+        let mut chunker = FastCDC::new(4096, 16384, 65535).unwrap();
+        chunker.set_content_length(contents.len());
+        let chunk = chunker.cut_with_veto(&contents, |_| true).unwrap();
+        assert_eq!(chunk.cutpoint, 65535);
+    }
+
+    /// Reader returning at most the given number of bytes per read.
+    #[cfg(feature = "std")]
+    struct ShortReader<'a> {
+        data: &'a [u8],
+        size: usize,
+    }
+
+    #[cfg(feature = "std")]
+    impl Read for ShortReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let length = buf.len().min(self.size).min(self.data.len());
+            buf[..length].copy_from_slice(&self.data[..length]);
+            self.data = &self.data[length..];
+            Ok(length)
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_stream_force_cut() {
+        let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        let part = &contents[..50000];
+        let source = [part, part].concat();
+
+        let mut chunker = FastCDC::new(4096, 16384, 65535).unwrap();
+        let expected: Vec<(isize, usize)> = chunker
+            .as_iterator(part)
+            .map(|chunk| (chunk.offset, chunk.cutpoint))
+            .collect();
+        let expected: Vec<(isize, usize)> = expected
+            .iter()
+            .copied()
+            .chain(expected.iter().map(|&(offset, cutpoint)| (offset + part.len() as isize, cutpoint + part.len())))
+            .collect();
+
+        let reader = ShortReader { data: &source, size: 1000 };
+        let mut stream = StreamCDC::new(reader, 4096, 16384, 65535).unwrap();
+        stream.force_cut(part.len());
+        let chunks: Vec<(isize, usize)> = stream
+            .map(|result| result.unwrap().1)
+            .map(|chunk| (chunk.offset, chunk.cutpoint))
+            .collect();
+        assert_eq!(chunks, expected);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_stream_veto() {
+        let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        let mut chunker = FastCDC::new(4096, 16384, 65535).unwrap();
+        chunker.set_content_length(contents.len());
+        let mut expected = Vec::new();
+        let mut cursor = 0;
+        while let Some(chunk) = chunker.cut_with_veto(&contents[cursor..], |offset| offset == 38465) {
+            expected.push((cursor as isize, cursor + chunk.cutpoint));
+            cursor += chunk.cutpoint;
+        }
+
+        let reader = ShortReader { data: &contents, size: 1000 };
+        let mut stream = StreamCDC::new(reader, 4096, 16384, 65535).unwrap();
+        stream.set_veto(|offset| offset == 38465);
+        let chunks: Vec<(isize, usize)> = stream
+            .map(|result| result.unwrap().1)
+            .map(|chunk| (chunk.offset, chunk.cutpoint))
+            .collect();
+        assert_eq!(chunks, expected);
+        assert!(chunks.iter().all(|&(_, cutpoint)| cutpoint != 38465));
+    }
+
+    #[test]
+    fn test_alignment_invalid() {
+        let mut chunker = FastCDC::new(4096, 16384, 65535).unwrap();
+        assert!(chunker.set_alignment(0).is_err());
+        assert!(chunker.set_alignment(32).is_err());
+        assert!(chunker.set_alignment(4000).is_err());
+        assert!(chunker.set_alignment(65536).is_err());
+        assert!(chunker.set_alignment(4096).is_ok());
+        assert!(chunker.set_record_delimiter(b"\n", 1024).is_err());
+
+        chunker.clear_alignment();
+        chunker.set_record_delimiter(b"\n", 1024).unwrap();
+        assert!(chunker.set_alignment(4096).is_err());
+    }
+
+    #[test]
+    fn test_aligned_chunks() {
+        let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        let mut chunker = FastCDC::new(4096, 16384, 65535).unwrap();
+        chunker.set_alignment(512).unwrap();
+        let chunks = chunker.as_iterator(&contents).collect::<Vec<_>>();
+
+        assert!(chunks.len() > 2);
+        assert_eq!(chunks.last().unwrap().cutpoint, contents.len());
+        for chunk in &chunks[..chunks.len() - 1] {
+            assert_eq!(chunk.cutpoint % 512, 0);
+            assert!(chunk.get_length() >= 4096);
+            assert!(chunk.get_length() <= 65024);
+        }
+
+        // Cross-buffer results are the same.
+        let expected = chunks.iter().map(|chunk| chunk.cutpoint).collect::<Vec<_>>();
+        for size in [333, 4096, 10000] {
+            chunker.set_content_length(contents.len());
+            let mut cutpoints = Vec::new();
+            let mut offset = 0;
+            for buffer in contents.chunks(size) {
+                let mut cursor = 0;
+                while let Some(chunk) = chunker.cut(&buffer[cursor..]) {
+                    cursor += chunk.cutpoint;
+                    cutpoints.push(offset + cursor);
+                    if cursor == buffer.len() {
+                        break;
+                    }
+                }
+                offset += buffer.len();
+            }
+            assert_eq!(cutpoints, expected);
+        }
+
+        #[cfg(feature = "std")]
+        {
+            let mut chunker = StreamCDC::new(contents.as_slice(), 4096, 16384, 65535).unwrap();
+            chunker.set_alignment(512).unwrap();
+            let streamed = chunker.map(|chunk| chunk.unwrap().1.cutpoint).collect::<Vec<_>>();
+            assert_eq!(streamed, expected);
+        }
+    }
+
+    fn sparse_contents() -> Vec<u8> {
+        let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        let mut sparse = vec![0; 70000];
+        for (index, run) in [100, 5000, 200000, 63, 17000].iter().enumerate() {
+            let start = index * 20000;
+            sparse.extend_from_slice(&contents[start..start + 20000]);
+            sparse.resize(sparse.len() + run, 0);
+        }
+        sparse
+    }
+
+    fn cut_buffers(chunker: &mut FastCDC, contents: &[u8], size: usize) -> Vec<Chunk> {
+        chunker.set_content_length(contents.len());
+        let mut chunks = Vec::new();
+        let mut offset = 0;
+        for buffer in contents.chunks(size) {
+            let mut cursor = 0;
+            while let Some(chunk) = chunker.cut(&buffer[cursor..]) {
+                let start = (offset + cursor) as isize + chunk.offset;
+                cursor += chunk.cutpoint;
+                chunks.push(Chunk { offset: start, cutpoint: offset + cursor, ..chunk });
+                if cursor == buffer.len() {
+                    break;
+                }
+            }
+            offset += buffer.len();
+        }
+        chunks
+    }
+
+    #[test]
+    fn test_zero_runs_flagged() {
+        let mut chunker = FastCDC::new_advanced(64, 256, 1024, Normalization::Level1, None).unwrap();
+        assert!(chunker.zero_runs);
+        let array = [0u8; 10240];
+        for chunk in chunker.as_iterator(&array) {
+            assert!(chunk.zero);
+            assert_eq!(chunk.hash, 14169102344523991076);
+            assert_eq!(chunk.get_length(), 1024);
+        }
+
+        let contents = sparse_contents();
+        let mut chunker = FastCDC::new(4096, 16384, 65535).unwrap();
+        assert!(chunker.zero_runs);
+        let chunks = chunker.as_iterator(&contents).collect::<Vec<_>>();
+        assert!(chunks.iter().filter(|chunk| chunk.zero).count() >= 3);
+        for chunk in &chunks {
+            let data = &contents[chunk.offset as usize..chunk.cutpoint];
+            assert_eq!(chunk.zero, data.iter().all(|&byte| byte == 0));
+        }
+
+        // Zero chunks cut by the data after the run, in one or several buffers.
+        let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        let mut runs = Vec::new();
+        for index in 0..300 {
+            runs.extend_from_slice(&contents[index * 300..][..100 + index * 37 % 900]);
+            runs.resize(runs.len() + 3000, 0);
+        }
+        let mut chunker = FastCDC::new(777, 1000, 1999).unwrap();
+        for size in [runs.len(), 4096, 333] {
+            let chunks = cut_buffers(&mut chunker, &runs, size);
+            for chunk in &chunks {
+                let data = &runs[chunk.offset as usize..chunk.cutpoint];
+                assert_eq!(chunk.zero, data.iter().all(|&byte| byte == 0), "{:?}", chunk);
+            }
+            assert!(chunks.iter().any(|chunk| chunk.zero));
+        }
+    }
+
+    #[test]
+    fn test_odd_maximum_cross_buffer() {
+        // The last byte of a chunk of the odd maximum size can start the next buffer.
+        let contents = vec![0; 10000];
+        let mut chunker = FastCDC::new(777, 1000, 1999).unwrap();
+        chunker.zero_runs = false;
+        let expected = chunker.as_iterator(&contents).collect::<Vec<_>>();
+        assert!(expected.iter().all(|chunk| chunk.get_length() == 1999 || chunk.cutpoint == 10000));
+        for size in [1998, 1000, 333] {
+            assert_eq!(cut_buffers(&mut chunker, &contents, size), expected);
+        }
+    }
+
+    #[test]
+    fn test_zero_runs_cut_points_unchanged() {
+        let contents = sparse_contents();
+        for alignment in [None, Some(512)] {
+            let mut chunker = FastCDC::new(4096, 16384, 65535).unwrap();
+            if let Some(alignment) = alignment {
+                chunker.set_alignment(alignment).unwrap();
+            }
+            let mut hashed = chunker.clone();
+            hashed.zero_runs = false;
+            let expected = cut_buffers(&mut hashed, &contents, contents.len());
+            assert!(expected.iter().any(|chunk| chunk.zero));
+
+            for size in [contents.len(), 10000, 4096, 333] {
+                assert_eq!(cut_buffers(&mut chunker, &contents, size), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_gear_seed() {
+        let contents = sparse_contents();
+        let mut chunker = FastCDC::new(4096, 16384, 65535).unwrap();
+        let expected = chunker.as_iterator(&contents).collect::<Vec<_>>();
+
+        chunker.set_gear_seed(0);
+        assert_eq!(chunker.gear_seed(), 0);
+        assert_eq!(chunker.as_iterator(&contents).collect::<Vec<_>>(), expected);
+
+        chunker.set_gear_seed(0x2545f4914f6cdd1d);
+        assert_eq!(chunker.gear_seed(), 0x2545f4914f6cdd1d);
+        let seeded = chunker.as_iterator(&contents).collect::<Vec<_>>();
+        assert_ne!(seeded, expected);
+        for size in [10000, 4096, 333] {
+            assert_eq!(cut_buffers(&mut chunker, &contents, size), seeded);
+        }
+
+        #[cfg(feature = "std")]
+        {
+            assert_eq!(chunker.chunk_parallel(&contents, 4), seeded);
+            let mut stream = StreamCDC::new(contents.as_slice(), 4096, 16384, 65535).unwrap();
+            stream.set_gear_seed(0x2545f4914f6cdd1d);
+            let streamed = stream.map(|result| result.unwrap().1).collect::<Vec<_>>();
+            assert_eq!(streamed, seeded);
+        }
+    }
+
+    #[cfg(all(feature = "std", target_os = "linux"))]
+    #[test]
+    fn test_stream_skip_holes() {
+        use std::io::{Seek, SeekFrom, Write};
+
+        let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        let path = std::env::temp_dir().join(format!("fastcdc-sparse-{}.img", std::process::id()));
+        let mut file = File::create(&path).unwrap();
+        for (index, hole) in [300000u64, 5000, 1 << 20].iter().enumerate() {
+            file.seek(SeekFrom::Current(*hole as i64)).unwrap();
+            file.write_all(&contents[index * 30000..(index + 1) * 30000]).unwrap();
+        }
+        let length = file.stream_position().unwrap() + 200000;
+        file.set_len(length).unwrap();
+        drop(file);
+
+        let sparse = fs::read(&path).unwrap();
+        let mut chunker = FastCDC::new(4096, 16384, 65535).unwrap();
+        let expected = chunker.as_iterator(&sparse).collect::<Vec<_>>();
+
+        let mut stream = StreamCDC::new(File::open(&path).unwrap(), 4096, 16384, 65535).unwrap();
+        stream.skip_holes().unwrap();
+        let mut chunks = Vec::new();
+        for result in stream {
+            let (data, chunk) = result.unwrap();
+            assert_eq!(data, &sparse[chunk.offset as usize..chunk.cutpoint]);
+            chunks.push(chunk);
+        }
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(chunks, expected);
+        assert!(chunks.iter().filter(|chunk| chunk.zero).count() > 10);
+    }
 }
@@ -0,0 +1,349 @@
+//
+// Copyright (c) 2023 Florian Gäbler
+//
+
+use super::*;
+
+///
+/// Represents a chunk identified by the [MultiCDC] chunker.\
+/// See the individual fields for more documentation.
+///
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct NestedChunk {
+    /// The chunk of the finest level.
+    pub chunk: Chunk,
+
+    /// The index of the coarsest level that also has a boundary at the cut point of the chunk.\
+    /// \
+    /// A chunk of every level up to and including this one ends here. It starts at the
+    /// cut point of the last preceding [NestedChunk] whose level is at least as high.
+    pub level: usize,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Resolution {
+    /// The number of hash bits beyond the masks that must be zero.
+    bits: u32,
+    min_size: usize,
+    /// Length at which a boundary is forced, which leaves room for one
+    /// more chunk of the finest level before `max_size` is exceeded.
+    limit: usize,
+    /// Number of bytes since the last boundary of this level.
+    length: usize,
+}
+
+///
+/// A chunker producing boundaries at several average sizes from a single scan of the data.\
+/// \
+/// The finest level is chunked by a regular [FastCDC] instance. Each boundary of a
+/// coarser level is also a boundary of all finer levels: a boundary of the finest level
+/// is promoted to a coarser level when its hash has enough zero bits beyond the masks
+/// (see [HashSplitter]) and the chunk of that level has reached its minimum size.
+/// Normalization only applies to the finest level.\
+/// \
+/// To keep the nesting intact, a coarser level is cut at the boundary of the finest level
+/// after which another chunk of the finest level could exceed its maximum size. Such a
+/// forced boundary is also a boundary of all finer levels, even below their minimum size.\
+/// \
+/// Since the boundaries only depend on the chunks of the finest level, the results of
+/// [cut()](MultiCDC::cut) are the same regardless of how the data is split into buffers.
+///
+/// ```no_run
+/// # use std::fs;
+/// # use fastcdc_alt::MultiCDC;
+/// let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+/// let mut chunker = MultiCDC::new(&[(2048, 8192, 32768), (16384, 65536, 262144)]).unwrap();
+/// for nested in chunker.as_iterator(&contents) {
+///     println!("offset={} size={} level={}", nested.chunk.offset, nested.chunk.get_length(), nested.level);
+/// }
+/// ```
+///
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MultiCDC {
+    inner: FastCDC,
+    shift: u32,
+    masks: [u64; 4],
+    /// The levels above the finest one, ordered from fine to coarse.
+    resolutions: Vec<Resolution>,
+}
+
+impl MultiCDC {
+    ///
+    /// Construct a `MultiCDC` with the given (`min_size`, `avg_size`, `max_size`) tuples,
+    /// ordered from the finest to the coarsest level.
+    ///
+    /// Uses chunk size normalization level 1 by default.
+    ///
+    pub fn new(levels: &[(u32, u32, u32)]) -> Result<Self, Error> {
+        MultiCDC::new_advanced(levels, Normalization::Level1, None)
+    }
+
+    ///
+    /// Create a new `MultiCDC` with the given normalization level and pre-set content length.
+    ///
+    pub fn new_advanced(
+        levels: &[(u32, u32, u32)],
+        level: Normalization,
+        content_length: Option<usize>
+    ) -> Result<Self, Error> {
+        let (&(min_size, avg_size, max_size), coarser) = levels.split_first()
+            .ok_or_else(|| Error::Other("at least one level is required".to_string()))?;
+
+        let inner = FastCDC::new_advanced(min_size, avg_size, max_size, level, content_length)?;
+        let shift = inner.free_bits_shift();
+        let base_bits = logarithm2(avg_size);
+
+        let mut resolutions = Vec::with_capacity(coarser.len());
+        let mut previous = (avg_size, max_size);
+        for &(min, avg, max) in coarser {
+            // Validate the sizes the same way as for the finest level.
+            FastCDC::new_advanced(min, avg, max, level, None)?;
+
+            let bits = logarithm2(avg);
+            if bits <= logarithm2(previous.0) {
+                return Err(Error::Other(format!("{} <= {} (avg_size is not larger than the finer level)", avg, previous.0)))
+            } else if bits - base_bits > 64 - shift {
+                return Err(Error::Other(format!("{} > {} (avg_size ratio exceeds free hash bits)", bits - base_bits, 64 - shift)))
+            } else if max <= previous.1 {
+                return Err(Error::Other(format!("{} <= {} (max_size is not larger than the finer level)", max, previous.1)))
+            }
+
+            resolutions.push(Resolution {
+                bits: bits - base_bits,
+                min_size: min as usize,
+                limit: (max - max_size) as usize,
+                length: 0,
+            });
+            previous = (avg, max);
+        }
+
+        Ok(Self {
+            masks: inner.judged_masks(),
+            inner,
+            shift,
+            resolutions,
+        })
+    }
+
+    ///
+    /// The number of levels, including the finest one.
+    ///
+    pub fn levels(&self) -> usize {
+        self.resolutions.len() + 1
+    }
+
+    ///
+    /// Set the content length to which create chunks for.
+    /// This method resets the internal context of all levels.
+    ///
+    /// See [FastCDC::set_content_length].
+    ///
+    pub fn set_content_length(&mut self, length: usize) {
+        self.inner.set_content_length(length);
+
+        for resolution in self.resolutions.iter_mut() {
+            resolution.length = 0;
+        }
+    }
+
+    ///
+    /// Try to identify the next cut point of the finest level in the data.\
+    /// \
+    /// This method behaves exactly like [FastCDC::cut], but additionally returns
+    /// the coarsest level that has a boundary at the identified cut point.
+    /// The last chunk of the content is a boundary of all levels.
+    ///
+    pub fn cut(&mut self, buffer: &[u8]) -> Option<NestedChunk> {
+        let chunk = self.inner.cut(buffer)?;
+        let length = chunk.get_length();
+        let end = self.inner.context.remaining == 0;
+
+        for resolution in self.resolutions.iter_mut() {
+            resolution.length += length;
+        }
+
+        // A forced boundary on one level forces the boundaries of all finer levels.
+        let mut level = self.resolutions.iter()
+            .rposition(|resolution| end || resolution.length >= resolution.limit)
+            .map_or(0, |index| index + 1);
+
+        let judged = chunk.hash != 0 && self.masks.iter().any(|mask| chunk.hash & mask == 0);
+        let zeros = (chunk.hash >> self.shift).trailing_zeros();
+        while judged && level < self.resolutions.len() {
+            let resolution = &self.resolutions[level];
+            if zeros < resolution.bits || resolution.length < resolution.min_size {
+                break;
+            }
+            level += 1;
+        }
+
+        for resolution in self.resolutions[..level].iter_mut() {
+            resolution.length = 0;
+        }
+
+        Some(NestedChunk { chunk, level })
+    }
+
+    ///
+    /// Construct a [MultiCDCIterator] by mutably referencing the base [MultiCDC] instance.
+    ///
+    pub fn as_iterator<'a, 'b>(&'a mut self, buffer: &'b [u8]) -> MultiCDCIterator<'a, 'b> {
+        self.set_content_length(buffer.len());
+
+        MultiCDCIterator {
+            inner: self,
+            cursor: 0,
+            buffer
+        }
+    }
+}
+
+pub struct MultiCDCIterator<'a, 'b> {
+    inner: &'a mut MultiCDC,
+    cursor: usize,
+    buffer: &'b [u8]
+}
+
+impl<'a, 'b> Iterator for MultiCDCIterator<'a, 'b> {
+    type Item = NestedChunk;
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<NestedChunk> {
+        if self.cursor == self.buffer.len() {
+            return None;
+        }
+
+        let nested = self.inner.cut(&self.buffer[self.cursor..])?;
+
+        let cutpoint = self.cursor + nested.chunk.cutpoint;
+        let chunk = Chunk {
+            hash: nested.chunk.hash,
+            offset: self.cursor as isize,
+            cutpoint,
+            zero: nested.chunk.zero
+        };
+
+        self.cursor = cutpoint;
+
+        Some(NestedChunk { chunk, level: nested.level })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEVELS: [(u32, u32, u32); 3] = [(256, 1024, 4096), (2048, 8192, 32768), (16384, 65536, 262144)];
+
+    fn pseudo_random_bytes(seed: u64, length: usize) -> Vec<u8> {
+        let mut state = seed;
+        (0..length).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 32) as u8
+        }).collect()
+    }
+
+    /// Collect the (offset, length) pairs of each level.
+    fn levels(nested: &[NestedChunk]) -> Vec<Vec<(usize, usize)>> {
+        let mut levels = vec![Vec::new(); LEVELS.len()];
+        let mut starts = [0usize; 3];
+        for entry in nested {
+            for level in 0..=entry.level {
+                levels[level].push((starts[level], entry.chunk.cutpoint - starts[level]));
+                starts[level] = entry.chunk.cutpoint;
+            }
+        }
+        levels
+    }
+
+    #[test]
+    fn test_invalid_levels() {
+        assert!(MultiCDC::new(&[]).is_err());
+        assert!(MultiCDC::new(&[(256, 1024, 4096), (256, 1024, 8192)]).is_err());
+        assert!(MultiCDC::new(&[(256, 1024, 4096), (2048, 8192, 4096)]).is_err());
+        assert!(MultiCDC::new(&[(256, 1024, 4096), (63, 8192, 32768)]).is_err());
+        assert!(MultiCDC::new(&LEVELS).is_ok());
+    }
+
+    #[test]
+    fn test_finest_level_unchanged() {
+        let contents = pseudo_random_bytes(0x2545f4914f6cdd1d, 1024 * 1024);
+        let mut chunker = MultiCDC::new(&LEVELS).unwrap();
+        let nested = chunker.as_iterator(&contents).map(|nested| nested.chunk).collect::<Vec<_>>();
+        let mut fastcdc = FastCDC::new(256, 1024, 4096).unwrap();
+        let expected = fastcdc.as_iterator(&contents).collect::<Vec<_>>();
+        assert_eq!(nested, expected);
+    }
+
+    #[test]
+    fn test_nested_levels() {
+        let contents = pseudo_random_bytes(0x9e3779b97f4a7c15, 4 * 1024 * 1024);
+        let mut chunker = MultiCDC::new(&LEVELS).unwrap();
+        let nested = chunker.as_iterator(&contents).collect::<Vec<_>>();
+        let levels = levels(&nested);
+
+        for (index, chunks) in levels.iter().enumerate() {
+            assert_eq!(chunks.iter().map(|(_, length)| length).sum::<usize>(), contents.len());
+            assert!(chunks.iter().all(|(_, length)| *length <= LEVELS[index].2 as usize));
+            if index > 0 {
+                assert!(chunks.len() * 2 < levels[index - 1].len());
+                let finer = levels[index - 1].iter().map(|(offset, _)| offset).collect::<Vec<_>>();
+                assert!(chunks.iter().all(|(offset, _)| finer.contains(&offset)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_forced_cuts_nested() {
+        // All zeros only produce chunks of the maximum size on the finest level.
+        let contents = vec![0u8; 1024 * 1024];
+        let mut chunker = MultiCDC::new(&LEVELS).unwrap();
+        let nested = chunker.as_iterator(&contents).collect::<Vec<_>>();
+        let levels = levels(&nested);
+
+        assert!(levels[0].iter().all(|(_, length)| *length == 4096));
+        assert!(levels[1].iter().all(|(_, length)| *length <= 32768));
+        assert_eq!(levels[1][0].1, 32768 - 4096);
+        assert!(levels[2].iter().all(|(_, length)| *length <= 262144));
+    }
+
+    #[test]
+    fn test_cross_buffer() {
+        let contents = pseudo_random_bytes(0x853c49e6748fea9b, 1024 * 1024);
+        let mut chunker = MultiCDC::new(&LEVELS).unwrap();
+        let expected = chunker.as_iterator(&contents).collect::<Vec<_>>();
+
+        for buffer_len in [1001, 4096, 65537] {
+            chunker.set_content_length(contents.len());
+            let mut cursor = 0;
+            let mut unchunked = 0;
+            let mut nested = Vec::new();
+            while cursor < contents.len() {
+                let end = (cursor + buffer_len).min(contents.len());
+                match chunker.cut(&contents[cursor..end]) {
+                    Some(result) => {
+                        let absolute = cursor - unchunked;
+                        nested.push(NestedChunk {
+                            chunk: Chunk {
+                                hash: result.chunk.hash,
+                                offset: absolute as isize,
+                                cutpoint: cursor + result.chunk.cutpoint,
+                                zero: result.chunk.zero,
+                            },
+                            level: result.level,
+                        });
+                        cursor += result.chunk.cutpoint;
+                        unchunked = 0;
+                    }
+                    None => {
+                        unchunked += end - cursor;
+                        cursor = end;
+                    }
+                }
+            }
+            assert_eq!(nested, expected);
+        }
+    }
+}
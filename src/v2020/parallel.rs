@@ -0,0 +1,196 @@
+//
+// Copyright (c) 2023 Florian Gäbler
+//
+
+use super::*;
+use std::thread;
+
+/// Minimum length of a segment relative to the maximum chunk size.
+const MIN_SEGMENT_FACTOR: usize = 16;
+
+impl FastCDC {
+    ///
+    /// Find all chunks of the buffer on several threads, producing exactly the chunks
+    /// of [as_iterator()](FastCDC::as_iterator).\
+    /// \
+    /// The buffer is split into one segment per thread and each segment is chunked
+    /// speculatively, as if a chunk started at the beginning of the segment. Since the
+    /// cut point of a chunk only depends on where it starts, the speculative chunks of a
+    /// segment are accepted from the first chunk that starts where a chunk of the
+    /// sequential chain of the preceding segments starts. Until then, the chunks are
+    /// computed again from the end of the preceding segment, which usually only takes
+    /// a chunk or two.\
+    /// \
+    /// Passing zero for `threads` uses the available parallelism. Segments are at least
+    /// 16 times the maximum chunk size, so small buffers are chunked on fewer threads.
+    /// Forced cut points, the alignment and record delimiters of this chunker apply as
+    /// usual, while its own context is not used or changed.
+    ///
+    /// ```no_run
+    /// # use fastcdc_alt::FastCDC;
+    /// let contents = std::fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+    /// let chunker = FastCDC::new(4096, 16384, 65535).unwrap();
+    /// for chunk in chunker.chunk_parallel(&contents, 0) {
+    ///     println!("offset={} size={}", chunk.offset, chunk.get_length());
+    /// }
+    /// ```
+    ///
+    pub fn chunk_parallel(&self, buffer: &[u8], threads: usize) -> Vec<Chunk> {
+        let threads = if threads == 0 {
+            thread::available_parallelism().map_or(1, |threads| threads.get())
+        } else {
+            threads
+        };
+
+        let segments = threads.min(buffer.len() / (MIN_SEGMENT_FACTOR * self.max_size)).max(1);
+        let starts = (0..segments).map(|index| index * buffer.len() / segments).collect::<Vec<_>>();
+
+        self.chunk_segments(buffer, &starts)
+    }
+
+    /// Chunk the segments beginning at the given ascending offsets in parallel and
+    /// join them into the sequential chain. The first offset must be zero.
+    fn chunk_segments(&self, buffer: &[u8], starts: &[usize]) -> Vec<Chunk> {
+        let ends = starts.iter().skip(1).copied().chain(Some(buffer.len())).collect::<Vec<_>>();
+
+        let mut speculative = thread::scope(|scope| {
+            let handles = starts.iter().zip(&ends).map(|(&start, &end)| {
+                scope.spawn(move || self.chunk_range(buffer, start, |chunk| chunk.offset as usize >= end))
+            }).collect::<Vec<_>>();
+
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect::<Vec<_>>()
+        }).into_iter();
+
+        let mut chunks = speculative.next().unwrap_or_default();
+        for (segment, &end) in speculative.zip(&ends[1..]) {
+            let start = chunks.last().map_or(0, |chunk| chunk.cutpoint);
+            if start >= end {
+                continue;
+            }
+
+            // Repair the seam until the sequential chain meets a speculative chunk.
+            let mut repaired = self.chunk_range(buffer, start, |chunk| {
+                chunk.offset as usize >= end || segment.binary_search_by_key(&chunk.offset, |c| c.offset).is_ok()
+            });
+            let seam = repaired.last().map_or(start, |chunk| chunk.cutpoint);
+            chunks.append(&mut repaired);
+
+            if let Ok(index) = segment.binary_search_by_key(&(seam as isize), |c| c.offset) {
+                chunks.extend_from_slice(&segment[index..]);
+            }
+        }
+
+        chunks
+    }
+
+    /// Chunk the buffer from the given offset, as if a chunk started there, until a
+    /// chunk matches the predicate, which is not included.
+    fn chunk_range<P: Fn(&Chunk) -> bool>(&self, buffer: &[u8], start: usize, stop: P) -> Vec<Chunk> {
+        let mut chunker = self.clone();
+        chunker.context.remaining = buffer.len() - start;
+        chunker.context.position = start;
+        chunker.recycle_context(0);
+
+        let mut chunks = Vec::new();
+        let mut cursor = start;
+        while cursor < buffer.len() {
+            let chunk = match chunker.cut(&buffer[cursor..]) {
+                Some(chunk) => chunk,
+                None => break,
+            };
+            let chunk = Chunk {
+                hash: chunk.hash,
+                offset: cursor as isize,
+                cutpoint: cursor + chunk.cutpoint,
+                zero: chunk.zero
+            };
+            if stop(&chunk) {
+                break;
+            }
+
+            cursor = chunk.cutpoint;
+            chunks.push(chunk);
+        }
+
+        chunks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn pseudo_random_bytes(seed: u64, length: usize) -> Vec<u8> {
+        let mut state = seed;
+        (0..length).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 32) as u8
+        }).collect()
+    }
+
+    fn chunkers() -> Vec<FastCDC> {
+        let plain = FastCDC::new(256, 1024, 8192).unwrap();
+        let mut forced = plain.clone();
+        forced.force_cut(100000);
+        forced.force_cut(100001);
+        forced.force_cut(777777);
+        let mut aligned = plain.clone();
+        aligned.set_alignment(512).unwrap();
+        let mut records = plain.clone();
+        records.set_record_delimiter(b"\x00", 1024).unwrap();
+        vec![plain, forced, aligned, records]
+    }
+
+    #[test]
+    fn test_parallel_random_segments() {
+        let contents = pseudo_random_bytes(0x2545f4914f6cdd1d, 2 * 1024 * 1024);
+        let mut state = 0x9e3779b97f4a7c15_u64;
+        for chunker in chunkers() {
+            let expected = chunker.clone().as_iterator(&contents).collect::<Vec<_>>();
+
+            for count in [1, 2, 5, 13] {
+                let mut starts = (1..count).map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    (state % contents.len() as u64) as usize
+                }).collect::<Vec<_>>();
+                starts.push(0);
+                starts.sort_unstable();
+                starts.dedup();
+
+                assert_eq!(chunker.chunk_segments(&contents, &starts), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parallel_adjacent_segments() {
+        let contents = pseudo_random_bytes(7, 200000);
+        let chunker = FastCDC::new(256, 1024, 8192).unwrap();
+        let expected = chunker.clone().as_iterator(&contents).collect::<Vec<_>>();
+
+        // Segments shorter than the maximum chunk size, some of which are skipped entirely.
+        let starts = (0..contents.len()).step_by(3001).collect::<Vec<_>>();
+        assert_eq!(chunker.chunk_segments(&contents, &starts), expected);
+    }
+
+    #[test]
+    fn test_chunk_parallel() {
+        let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        let mut large = Vec::new();
+        for _ in 0..20 {
+            large.extend_from_slice(&contents);
+        }
+
+        let chunker = FastCDC::new(4096, 16384, 65535).unwrap();
+        let expected = chunker.clone().as_iterator(&large).collect::<Vec<_>>();
+        for threads in [0, 1, 2, 8] {
+            assert_eq!(chunker.chunk_parallel(&large, threads), expected);
+        }
+        assert!(chunker.chunk_parallel(&[], 4).is_empty());
+    }
+}
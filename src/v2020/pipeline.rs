@@ -0,0 +1,404 @@
+//
+// Copyright (c) 2023 Florian Gäbler
+//
+
+use super::*;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::File;
+use std::path::PathBuf;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// Function computing the digest of the data of a chunk.
+type Hasher = Arc<dyn Fn(&[u8]) -> Vec<u8> + Send + Sync>;
+
+///
+/// A chunk produced by the [ChunkPipeline].\
+/// See the individual fields for more documentation.
+///
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct PipelineChunk {
+    /// The chunk with offsets relative to the start of its file.
+    pub chunk: Chunk,
+
+    /// The data of the chunk.
+    pub data: Vec<u8>,
+
+    /// The digest of the data, if a hasher was set with
+    /// [set_hasher()](ChunkPipeline::set_hasher).
+    pub digest: Option<Vec<u8>>,
+}
+
+enum Source {
+    Path(PathBuf),
+    Reader(Box<dyn Read + Send>),
+}
+
+struct Message {
+    file_id: usize,
+    sequence: usize,
+    result: Result<PipelineChunk, Error>,
+}
+
+///
+/// Chunks many files or readers on a bounded pool of worker threads.\
+/// \
+/// Each source is chunked by a single worker with a [StreamCDC], so the chunks of a
+/// source are produced in order, while the chunks of different sources are
+/// interleaved. Optionally, the chunks are hashed on a separate pool of threads.
+/// All stages are connected by bounded queues, so a slow consumer blocks the
+/// workers and the memory usage stays bounded.\
+/// \
+/// The [PipelineIter] returned by [run_paths()](ChunkPipeline::run_paths) or
+/// [run_readers()](ChunkPipeline::run_readers) yields the index of the source in the
+/// input together with the chunk or the error that ended the chunking of that source.
+///
+/// ```no_run
+/// # use fastcdc_alt::ChunkPipeline;
+/// let mut pipeline = ChunkPipeline::new(4096, 16384, 65535).unwrap();
+/// pipeline.set_workers(4);
+/// pipeline.set_hasher(2, |data| data.iter().map(|byte| byte ^ 0x5a).take(8).collect());
+///
+/// let paths = vec!["a.bin", "b.bin", "c.bin"];
+/// for (file_id, result) in pipeline.run_paths(paths) {
+///     let chunk = result.unwrap();
+///     println!("file={} offset={} size={}", file_id, chunk.chunk.offset, chunk.data.len());
+/// }
+/// ```
+///
+#[derive(Clone)]
+pub struct ChunkPipeline {
+    min_size: u32,
+    avg_size: u32,
+    max_size: u32,
+    level: Normalization,
+    workers: usize,
+    queue_depth: usize,
+    hasher: Option<(usize, Hasher)>,
+}
+
+impl ChunkPipeline {
+    ///
+    /// Construct a `ChunkPipeline` with the given chunk sizes.
+    ///
+    /// Uses chunk size normalization level 1 and as many workers as the available
+    /// parallelism by default.
+    ///
+    pub fn new(min_size: u32, avg_size: u32, max_size: u32) -> Result<Self, Error> {
+        Self::new_advanced(min_size, avg_size, max_size, Normalization::Level1)
+    }
+
+    ///
+    /// Create a new `ChunkPipeline` with the given normalization level.
+    ///
+    pub fn new_advanced(min_size: u32, avg_size: u32, max_size: u32, level: Normalization) -> Result<Self, Error> {
+        // Validate the sizes once, instead of in each worker.
+        FastCDC::new_advanced(min_size, avg_size, max_size, level, None)?;
+
+        Ok(Self {
+            min_size,
+            avg_size,
+            max_size,
+            level,
+            workers: thread::available_parallelism().map_or(1, |workers| workers.get()),
+            queue_depth: 64,
+            hasher: None,
+        })
+    }
+
+    ///
+    /// Set the number of sources chunked at the same time, at least one.
+    ///
+    pub fn set_workers(&mut self, workers: usize) {
+        self.workers = workers.max(1);
+    }
+
+    ///
+    /// Set the number of chunks each queue between the stages can hold, at least one.
+    ///
+    pub fn set_queue_depth(&mut self, queue_depth: usize) {
+        self.queue_depth = queue_depth.max(1);
+    }
+
+    ///
+    /// Compute the digest of each chunk with the given function on a separate pool
+    /// of the given number of threads.
+    ///
+    pub fn set_hasher<F: Fn(&[u8]) -> Vec<u8> + Send + Sync + 'static>(&mut self, threads: usize, hasher: F) {
+        self.hasher = Some((threads.max(1), Arc::new(hasher)));
+    }
+
+    ///
+    /// Start chunking the files at the given paths.\
+    /// The files are only opened by the workers, so the number of open files is
+    /// bounded by the number of workers.
+    ///
+    pub fn run_paths<I, P>(&self, paths: I) -> PipelineIter
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<PathBuf>,
+    {
+        self.run(paths.into_iter().map(|path| Source::Path(path.into())).collect())
+    }
+
+    ///
+    /// Start chunking the given readers.
+    ///
+    pub fn run_readers<I, R>(&self, readers: I) -> PipelineIter
+    where
+        I: IntoIterator<Item = R>,
+        R: Read + Send + 'static,
+    {
+        self.run(readers.into_iter().map(|reader| Source::Reader(Box::new(reader) as Box<dyn Read + Send>)).collect())
+    }
+
+    fn run(&self, sources: VecDeque<Source>) -> PipelineIter {
+        let jobs = Arc::new(Mutex::new(sources.into_iter().enumerate().collect::<VecDeque<_>>()));
+        let (sender, receiver) = sync_channel(self.queue_depth);
+        let mut handles = Vec::new();
+
+        let (chunk_sender, receiver) = match &self.hasher {
+            None => (sender, receiver),
+            Some((threads, hasher)) => {
+                let (chunk_sender, chunk_receiver) = sync_channel::<Message>(self.queue_depth);
+                let chunk_receiver = Arc::new(Mutex::new(chunk_receiver));
+                for _ in 0..*threads {
+                    let chunk_receiver = chunk_receiver.clone();
+                    let sender = sender.clone();
+                    let hasher = hasher.clone();
+                    handles.push(thread::spawn(move || hash_messages(&chunk_receiver, &sender, &*hasher)));
+                }
+                (chunk_sender, receiver)
+            }
+        };
+
+        for _ in 0..self.workers {
+            let jobs = jobs.clone();
+            let sender = chunk_sender.clone();
+            let pipeline = self.clone();
+            handles.push(thread::spawn(move || pipeline.chunk_sources(&jobs, &sender)));
+        }
+
+        PipelineIter {
+            receiver: Some(receiver),
+            handles,
+            expected: HashMap::new(),
+            pending: HashMap::new(),
+            ready: VecDeque::new(),
+        }
+    }
+
+    /// Chunk sources until there are none left or the consumer is gone.
+    fn chunk_sources(&self, jobs: &Mutex<VecDeque<(usize, Source)>>, sender: &SyncSender<Message>) {
+        loop {
+            let (file_id, source) = match jobs.lock().ok().and_then(|mut jobs| jobs.pop_front()) {
+                Some(job) => job,
+                None => return,
+            };
+
+            let reader = match source {
+                Source::Reader(reader) => Ok(reader),
+                Source::Path(path) => File::open(path).map(|file| Box::new(file) as Box<dyn Read + Send>),
+            };
+
+            let chunker = reader.map_err(Error::from).and_then(|reader| {
+                StreamCDC::new_advanced(reader, self.min_size, self.avg_size, self.max_size, self.level)
+            });
+
+            let chunker = match chunker {
+                Ok(chunker) => chunker,
+                Err(error) => {
+                    if sender.send(Message { file_id, sequence: 0, result: Err(error) }).is_err() {
+                        return;
+                    }
+                    continue;
+                }
+            };
+
+            for (sequence, result) in chunker.enumerate() {
+                let failed = result.is_err();
+                let result = result.map(|(data, chunk)| PipelineChunk { chunk, data, digest: None });
+                if sender.send(Message { file_id, sequence, result }).is_err() {
+                    return;
+                }
+                if failed {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Add the digests to the chunks until the chunkers or the consumer are gone.
+fn hash_messages(receiver: &Mutex<Receiver<Message>>, sender: &SyncSender<Message>, hasher: &(dyn Fn(&[u8]) -> Vec<u8> + Send + Sync)) {
+    loop {
+        let message = match receiver.lock().ok().and_then(|receiver| receiver.recv().ok()) {
+            Some(message) => message,
+            None => return,
+        };
+
+        let result = message.result.map(|mut chunk| {
+            chunk.digest = Some(hasher(&chunk.data));
+            chunk
+        });
+        if sender.send(Message { result, ..message }).is_err() {
+            return;
+        }
+    }
+}
+
+///
+/// Iterator over the chunks produced by a [ChunkPipeline], yielding the index of the
+/// source with the chunk or the error that ended the chunking of that source.\
+/// Dropping the iterator stops the pipeline.
+///
+pub struct PipelineIter {
+    receiver: Option<Receiver<Message>>,
+    handles: Vec<JoinHandle<()>>,
+    /// Sequence number of the next chunk to yield for each source.
+    expected: HashMap<usize, usize>,
+    /// Chunks which were hashed out of order, per source.
+    pending: HashMap<usize, BTreeMap<usize, Message>>,
+    /// Chunks which are in order and can be yielded.
+    ready: VecDeque<Message>,
+}
+
+impl PipelineIter {
+    /// Queue the message and all pending messages of its source that follow it.
+    fn accept(&mut self, message: Message) {
+        let file_id = message.file_id;
+        let expected = self.expected.entry(file_id).or_insert(0);
+        if message.sequence != *expected {
+            self.pending.entry(file_id).or_default().insert(message.sequence, message);
+            return;
+        }
+
+        *expected += 1;
+        self.ready.push_back(message);
+
+        if let Some(pending) = self.pending.get_mut(&file_id) {
+            while let Some(message) = pending.remove(expected) {
+                *expected += 1;
+                self.ready.push_back(message);
+            }
+            if pending.is_empty() {
+                self.pending.remove(&file_id);
+            }
+        }
+    }
+}
+
+impl Iterator for PipelineIter {
+    type Item = (usize, Result<PipelineChunk, Error>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(message) = self.ready.pop_front() {
+                return Some((message.file_id, message.result));
+            }
+
+            let message = self.receiver.as_ref()?.recv().ok()?;
+            self.accept(message);
+        }
+    }
+}
+
+impl Drop for PipelineIter {
+    fn drop(&mut self) {
+        // Disconnect the workers blocked on sending, then wait for them.
+        self.receiver = None;
+        for handle in self.handles.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use md5::{Digest, Md5};
+    use std::fs;
+    use std::io::Cursor;
+
+    fn sources() -> Vec<Vec<u8>> {
+        let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        (0..12).map(|index| contents[index * 1000..].repeat(index % 3 + 1)).collect()
+    }
+
+    fn collect(iter: PipelineIter, count: usize) -> Vec<Vec<PipelineChunk>> {
+        let mut files = vec![Vec::new(); count];
+        for (file_id, result) in iter {
+            files[file_id].push(result.unwrap());
+        }
+        files
+    }
+
+    #[test]
+    fn test_pipeline_matches_stream() {
+        let sources = sources();
+        let mut pipeline = ChunkPipeline::new(4096, 16384, 65535).unwrap();
+        pipeline.set_workers(3);
+        pipeline.set_queue_depth(2);
+        let files = collect(pipeline.run_readers(sources.clone().into_iter().map(Cursor::new)), sources.len());
+
+        for (source, chunks) in sources.iter().zip(files) {
+            let expected = StreamCDC::new(source.as_slice(), 4096, 16384, 65535).unwrap()
+                .map(|result| result.unwrap())
+                .collect::<Vec<_>>();
+            let chunks = chunks.into_iter().map(|chunk| {
+                assert!(chunk.digest.is_none());
+                (chunk.data, chunk.chunk)
+            }).collect::<Vec<_>>();
+            assert_eq!(chunks, expected);
+        }
+    }
+
+    #[test]
+    fn test_pipeline_hashes_in_order() {
+        let sources = sources();
+        let mut pipeline = ChunkPipeline::new(4096, 16384, 65535).unwrap();
+        pipeline.set_workers(4);
+        pipeline.set_queue_depth(1);
+        pipeline.set_hasher(3, |data| Md5::digest(data).to_vec());
+        let files = collect(pipeline.run_readers(sources.clone().into_iter().map(Cursor::new)), sources.len());
+
+        for (source, chunks) in sources.iter().zip(files) {
+            let mut cursor = 0;
+            for chunk in chunks {
+                assert_eq!(chunk.chunk.offset as usize, cursor);
+                assert_eq!(chunk.data, &source[cursor..chunk.chunk.cutpoint]);
+                assert_eq!(chunk.digest.unwrap(), Md5::digest(&chunk.data).to_vec());
+                cursor = chunk.chunk.cutpoint;
+            }
+            assert_eq!(cursor, source.len());
+        }
+    }
+
+    #[test]
+    fn test_pipeline_paths() {
+        let pipeline = ChunkPipeline::new(4096, 16384, 65535).unwrap();
+        let paths = vec!["test/fixtures/SekienAkashita.jpg", "test/fixtures/does-not-exist", "test/fixtures/SekienAkashita.jpg"];
+        let mut chunks = vec![0; paths.len()];
+        let mut errors = Vec::new();
+        for (file_id, result) in pipeline.run_paths(paths) {
+            match result {
+                Ok(_) => chunks[file_id] += 1,
+                Err(_) => errors.push(file_id),
+            }
+        }
+        assert_eq!(chunks, vec![5, 0, 5]);
+        assert_eq!(errors, vec![1]);
+    }
+
+    #[test]
+    fn test_pipeline_dropped_early() {
+        let sources = sources();
+        let mut pipeline = ChunkPipeline::new(4096, 16384, 65535).unwrap();
+        pipeline.set_queue_depth(1);
+        pipeline.set_hasher(2, |data| data[..4].to_vec());
+        let mut iter = pipeline.run_readers(sources.into_iter().map(Cursor::new));
+        assert!(iter.next().is_some());
+        drop(iter);
+    }
+}
@@ -0,0 +1,446 @@
+//
+// Copyright (c) 2023 Florian Gäbler
+//
+
+use super::*;
+use std::collections::HashSet;
+
+///
+/// An entry of a [ProllyNode], referencing either a chunk (in leaf nodes) or
+/// a child node (in internal nodes).\
+/// See the individual fields for more documentation.
+///
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct ProllyEntry {
+    /// The digest of the chunk or child node.
+    pub digest: Vec<u8>,
+
+    /// The absolute offset at which the referenced data starts.
+    pub offset: usize,
+
+    /// The length of the referenced data.
+    pub length: usize,
+}
+
+///
+/// A node of a [ProllyTree].
+///
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ProllyNode {
+    /// The digest of this node, computed over the digests of all entries.
+    pub digest: Vec<u8>,
+
+    /// The entries of this node in offset order.
+    pub entries: Vec<ProllyEntry>,
+
+    /// Index of the node of the first entry in the level below.
+    first_child: usize,
+}
+
+impl ProllyNode {
+    /// The absolute offset at which the data covered by this node starts.
+    pub fn offset(&self) -> usize {
+        self.entries[0].offset
+    }
+
+    /// The length of the data covered by this node.
+    pub fn length(&self) -> usize {
+        self.entries.iter().map(|entry| entry.length).sum()
+    }
+}
+
+///
+/// The result of [ProllyTree::diff].
+///
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ProllyDiff {
+    /// Chunks of the changed subtrees of the old tree that do not occur in the
+    /// changed subtrees of the new tree.
+    pub removed: Vec<ProllyEntry>,
+
+    /// Chunks of the changed subtrees of the new tree that do not occur in the
+    /// changed subtrees of the old tree.
+    pub added: Vec<ProllyEntry>,
+}
+
+///
+/// A content-defined Merkle tree (probabilistic B-tree) over a sequence of
+/// chunk digests.\
+/// \
+/// The leaf nodes hold the chunk digests and the internal nodes hold the
+/// digests of their child nodes. Node boundaries are chosen by running the
+/// gear hash over the digest of each entry and comparing it against the mask
+/// for the desired fanout, so an edit of the data only changes the nodes on
+/// the path to the changed chunks. Use [ProllyTreeBuilder] to build a tree.
+///
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ProllyTree {
+    /// The nodes of each level, where index 0 holds the leaf nodes and the
+    /// last level holds only the root node.
+    levels: Vec<Vec<ProllyNode>>,
+}
+
+impl ProllyTree {
+    ///
+    /// The root node, or [None] if the tree is empty.
+    ///
+    pub fn root(&self) -> Option<&ProllyNode> {
+        self.levels.last().map(|level| &level[0])
+    }
+
+    ///
+    /// The number of levels of the tree.
+    ///
+    pub fn height(&self) -> usize {
+        self.levels.len()
+    }
+
+    ///
+    /// The nodes of the given level, where level 0 holds the leaf nodes.
+    ///
+    pub fn nodes(&self, level: usize) -> &[ProllyNode] {
+        self.levels.get(level).map_or(&[], |nodes| nodes.as_slice())
+    }
+
+    ///
+    /// Find the chunk entry that contains the given absolute offset.
+    ///
+    pub fn get(&self, offset: usize) -> Option<&ProllyEntry> {
+        let mut node = self.root()?;
+        for level in (0..self.levels.len()).rev() {
+            let index = node.entries.partition_point(|entry| entry.offset + entry.length <= offset);
+            let entry = node.entries.get(index)?;
+            if entry.offset > offset {
+                return None;
+            }
+
+            if level == 0 {
+                return Some(entry);
+            }
+            node = &self.levels[level - 1][node.first_child + index];
+        }
+
+        None
+    }
+
+    ///
+    /// Compare this (old) tree to another (new) tree.\
+    /// \
+    /// Subtrees with equal digests are skipped, so only the nodes on the paths
+    /// to changed chunks are visited. The returned entries are ordered by offset.\
+    /// Chunks are only compared against the changed subtrees of the other tree,
+    /// so a chunk that also occurs in an unchanged subtree of the other tree is
+    /// still reported.
+    ///
+    pub fn diff(&self, other: &ProllyTree) -> ProllyDiff {
+        let (old_root, new_root) = match (self.root(), other.root()) {
+            (Some(old_root), Some(new_root)) => (old_root, new_root),
+            _ => return ProllyDiff {
+                removed: self.nodes(0).iter().flat_map(|node| node.entries.clone()).collect(),
+                added: other.nodes(0).iter().flat_map(|node| node.entries.clone()).collect(),
+            },
+        };
+
+        if old_root.digest == new_root.digest {
+            return ProllyDiff::default();
+        }
+
+        let mut old_level = self.levels.len() - 1;
+        let mut new_level = other.levels.len() - 1;
+        let mut old_nodes = vec![old_root];
+        let mut new_nodes = vec![new_root];
+
+        // Bring both frontiers to the same level before comparing entries.
+        while old_level > new_level {
+            old_nodes = self.children(old_level, &old_nodes, |_| true);
+            old_level -= 1;
+        }
+        while new_level > old_level {
+            new_nodes = other.children(new_level, &new_nodes, |_| true);
+            new_level -= 1;
+        }
+
+        let mut level = old_level;
+        loop {
+            let old_digests = digests(&old_nodes);
+            let new_digests = digests(&new_nodes);
+
+            if level == 0 {
+                return ProllyDiff {
+                    removed: unmatched(&old_nodes, &new_digests),
+                    added: unmatched(&new_nodes, &old_digests),
+                };
+            }
+
+            old_nodes = self.children(level, &old_nodes, |entry| !new_digests.contains(entry.digest.as_slice()));
+            new_nodes = other.children(level, &new_nodes, |entry| !old_digests.contains(entry.digest.as_slice()));
+            level -= 1;
+        }
+    }
+
+    /// Collect the child nodes of the selected entries of the given nodes.
+    fn children<'a, P>(&'a self, level: usize, nodes: &[&ProllyNode], predicate: P) -> Vec<&'a ProllyNode>
+        where P: Fn(&ProllyEntry) -> bool
+    {
+        let below = &self.levels[level - 1];
+        nodes.iter()
+            .flat_map(|node| node.entries.iter()
+                .enumerate()
+                .filter(|(_, entry)| predicate(entry))
+                .map(move |(index, _)| &below[node.first_child + index]))
+            .collect()
+    }
+}
+
+fn digests<'a>(nodes: &[&'a ProllyNode]) -> HashSet<&'a [u8]> {
+    nodes.iter()
+        .flat_map(|node| node.entries.iter().map(|entry| entry.digest.as_slice()))
+        .collect()
+}
+
+fn unmatched(nodes: &[&ProllyNode], digests: &HashSet<&[u8]>) -> Vec<ProllyEntry> {
+    nodes.iter()
+        .flat_map(|node| node.entries.iter())
+        .filter(|entry| !digests.contains(entry.digest.as_slice()))
+        .cloned()
+        .collect()
+}
+
+///
+/// Builds a [ProllyTree] from a stream of chunks.\
+/// \
+/// The given hasher computes the digest of a node from the concatenated
+/// digests of its entries. Nodes hold between a quarter and four times the
+/// average fanout of entries, except for the last node of each level.
+///
+/// ```no_run
+/// # use std::fs;
+/// # use fastcdc_alt::{FastCDC, ProllyTreeBuilder};
+/// # fn digest(data: &[u8]) -> Vec<u8> { unimplemented!() }
+/// let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+/// let mut chunker = FastCDC::new(4096, 16384, 65535).unwrap();
+/// let mut builder = ProllyTreeBuilder::new(64, digest).unwrap();
+///
+/// for chunk in chunker.as_iterator(&contents) {
+///     builder.push(&chunk, digest(&contents[chunk.offset as usize..chunk.cutpoint]));
+/// }
+/// let tree = builder.finish();
+/// ```
+///
+pub struct ProllyTreeBuilder<F: Fn(&[u8]) -> Vec<u8>> {
+    hasher: F,
+    min_entries: usize,
+    max_entries: usize,
+    mask: u64,
+    levels: Vec<Vec<ProllyNode>>,
+    /// The entries of each level that are not part of a node yet.
+    pending: Vec<Vec<ProllyEntry>>,
+    /// Number of bytes covered by the chunks pushed so far.
+    processed: usize,
+}
+
+impl<F: Fn(&[u8]) -> Vec<u8>> ProllyTreeBuilder<F> {
+    ///
+    /// Create a new `ProllyTreeBuilder` with the given average number of entries per node.
+    ///
+    /// The average fanout is rounded to a power of two between 64 and 65536.
+    ///
+    pub fn new(avg_fanout: u32, hasher: F) -> Result<Self, Error> {
+        let bits = logarithm2(avg_fanout);
+        if !(6..=16).contains(&bits) {
+            return Err(Error::Other(format!("{} is out of range (avg_fanout must be between 64 and 65536)", avg_fanout)))
+        }
+
+        let avg_fanout = 1usize << bits;
+        Ok(Self {
+            hasher,
+            min_entries: avg_fanout / 4,
+            max_entries: avg_fanout * 4,
+            mask: MASKS[bits as usize],
+            levels: Vec::new(),
+            pending: Vec::new(),
+            processed: 0,
+        })
+    }
+
+    ///
+    /// Add the next chunk with its digest to the tree.\
+    /// \
+    /// Only the length of the chunk is taken into account for the offsets,
+    /// so chunks returned by [FastCDC::cut] can be pushed directly.
+    ///
+    pub fn push(&mut self, chunk: &Chunk, digest: Vec<u8>) {
+        let entry = ProllyEntry {
+            digest,
+            offset: self.processed,
+            length: chunk.get_length(),
+        };
+        self.processed += entry.length;
+
+        self.add_entry(0, entry);
+    }
+
+    ///
+    /// Complete the tree. Afterwards the builder can be used for a new tree.
+    ///
+    pub fn finish(&mut self) -> ProllyTree {
+        let mut level = 0;
+        while level < self.pending.len() {
+            if !self.pending[level].is_empty() {
+                self.close(level);
+            }
+
+            // A single node on this level covers all entries and is the root.
+            if self.levels[level].len() == 1 {
+                self.levels.truncate(level + 1);
+                break;
+            }
+            level += 1;
+        }
+
+        self.pending.clear();
+        self.processed = 0;
+
+        ProllyTree {
+            levels: std::mem::take(&mut self.levels),
+        }
+    }
+
+    fn add_entry(&mut self, level: usize, entry: ProllyEntry) {
+        if self.pending.len() <= level {
+            self.pending.resize(level + 1, Vec::new());
+            self.levels.resize(level + 1, Vec::new());
+        }
+
+        let boundary = self.is_boundary(&entry.digest);
+        self.pending[level].push(entry);
+
+        let count = self.pending[level].len();
+        if count >= self.max_entries || (count >= self.min_entries && boundary) {
+            self.close(level);
+        }
+    }
+
+    fn close(&mut self, level: usize) {
+        let entries = std::mem::take(&mut self.pending[level]);
+        let first_child = if level == 0 { 0 } else { self.levels[level - 1].len() - entries.len() };
+        let digest = (self.hasher)(&entries.iter().flat_map(|entry| entry.digest.iter().copied()).collect::<Vec<u8>>());
+        let node = ProllyNode {
+            digest,
+            entries,
+            first_child,
+        };
+
+        let entry = ProllyEntry {
+            digest: node.digest.clone(),
+            offset: node.offset(),
+            length: node.length(),
+        };
+        self.levels[level].push(node);
+
+        self.add_entry(level + 1, entry);
+    }
+
+    #[inline(always)]
+    fn is_boundary(&self, digest: &[u8]) -> bool {
+        let hash = digest.iter().fold(0u64, |hash, byte| (hash << 1).wrapping_add(GEAR[*byte as usize]));
+        hash & self.mask == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use md5::{Digest, Md5};
+
+    fn pseudo_random_bytes(seed: u64, length: usize) -> Vec<u8> {
+        let mut state = seed;
+        (0..length).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 32) as u8
+        }).collect()
+    }
+
+    fn md5(data: &[u8]) -> Vec<u8> {
+        Md5::digest(data).to_vec()
+    }
+
+    fn build(contents: &[u8]) -> ProllyTree {
+        let mut chunker = FastCDC::new(64, 256, 1024).unwrap();
+        let mut builder = ProllyTreeBuilder::new(64, md5).unwrap();
+        for chunk in chunker.as_iterator(contents) {
+            builder.push(&chunk, md5(&contents[chunk.offset as usize..chunk.cutpoint]));
+        }
+        builder.finish()
+    }
+
+    #[test]
+    fn test_fanout_invalid() {
+        assert!(ProllyTreeBuilder::new(32, md5).is_err());
+        assert!(ProllyTreeBuilder::new(131072, md5).is_err());
+    }
+
+    #[test]
+    fn test_empty_tree() {
+        let tree = build(&[]);
+        assert_eq!(tree.height(), 0);
+        assert!(tree.root().is_none());
+        assert!(tree.get(0).is_none());
+
+        let other = build(&pseudo_random_bytes(1, 1000));
+        let diff = tree.diff(&other);
+        assert!(diff.removed.is_empty());
+        assert_eq!(diff.added.iter().map(|entry| entry.length).sum::<usize>(), 1000);
+    }
+
+    #[test]
+    fn test_build_and_get() {
+        let contents = pseudo_random_bytes(0x2545f4914f6cdd1d, 1024 * 1024);
+        let tree = build(&contents);
+        assert!(tree.height() >= 2);
+
+        let root = tree.root().unwrap();
+        assert_eq!(root.offset(), 0);
+        assert_eq!(root.length(), contents.len());
+
+        let mut chunker = FastCDC::new(64, 256, 1024).unwrap();
+        for chunk in chunker.as_iterator(&contents) {
+            for offset in [chunk.offset as usize, chunk.cutpoint - 1] {
+                let entry = tree.get(offset).unwrap();
+                assert_eq!(entry.offset, chunk.offset as usize);
+                assert_eq!(entry.length, chunk.get_length());
+                assert_eq!(entry.digest, md5(&contents[chunk.offset as usize..chunk.cutpoint]));
+            }
+        }
+        assert!(tree.get(contents.len()).is_none());
+    }
+
+    #[test]
+    fn test_diff() {
+        let contents = pseudo_random_bytes(0x9e3779b97f4a7c15, 1024 * 1024);
+        let mut modified = contents.clone();
+        modified.splice(500_000..500_000, pseudo_random_bytes(3, 100));
+        modified[10_000] ^= 0xff;
+
+        let old = build(&contents);
+        let new = build(&modified);
+        assert!(old.diff(&old).added.is_empty());
+
+        let diff = old.diff(&new);
+        assert!(!diff.added.is_empty());
+        assert!(diff.added.len() < 10);
+        assert!(diff.removed.len() < 10);
+        assert!(diff.added.iter().any(|entry| entry.offset <= 10_000 && 10_000 < entry.offset + entry.length));
+        assert!(diff.added.iter().any(|entry| entry.offset <= 500_000 && 500_100 <= entry.offset + entry.length));
+
+        // Every chunk of the new tree is either added or contained in the old tree.
+        let old_chunks = digests(&old.nodes(0).iter().collect::<Vec<_>>());
+        for node in new.nodes(0) {
+            for entry in &node.entries {
+                assert!(old_chunks.contains(entry.digest.as_slice()) || diff.added.contains(entry));
+            }
+        }
+    }
+}
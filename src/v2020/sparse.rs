@@ -0,0 +1,78 @@
+//
+// Copyright (c) 2023 Florian Gäbler
+//
+
+use std::io;
+use std::os::unix::io::RawFd;
+
+///
+/// Tracks the data extents of a sparse file with `SEEK_DATA` and `SEEK_HOLE`,
+/// so that holes can be filled with zeros instead of being read.
+///
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(super) struct Holes {
+    fd: RawFd,
+    /// File offset of the next byte to read.
+    offset: u64,
+    /// End of the data extent containing the offset.
+    data_end: u64,
+}
+
+impl Holes {
+    /// Start tracking at the current position of the file, failing if the
+    /// file does not support seeking.
+    pub(super) fn new(fd: RawFd) -> io::Result<Self> {
+        let offset = seek(fd, 0, libc::SEEK_CUR)?;
+        Ok(Self {
+            fd,
+            offset,
+            data_end: offset,
+        })
+    }
+
+    ///
+    /// Determine the next extent of at most `space` bytes. Returns the number of
+    /// zero bytes of a hole, which are skipped in the file, or otherwise the
+    /// number of data bytes to read, which is zero at the end of the file.
+    ///
+    pub(super) fn next_extent(&mut self, space: usize) -> io::Result<(usize, usize)> {
+        if self.offset >= self.data_end {
+            let data = match seek(self.fd, self.offset, libc::SEEK_DATA) {
+                Ok(data) => data,
+                // No more data, so the rest of the file is a hole.
+                Err(error) if error.raw_os_error() == Some(libc::ENXIO) => seek(self.fd, 0, libc::SEEK_END)?,
+                Err(error) => return Err(error),
+            };
+
+            if data > self.offset {
+                let zeros = (data - self.offset).min(space as u64);
+                self.offset += zeros;
+                seek(self.fd, self.offset, libc::SEEK_SET)?;
+                return Ok((zeros as usize, 0));
+            }
+
+            self.data_end = match seek(self.fd, self.offset, libc::SEEK_HOLE) {
+                Ok(hole) => hole,
+                Err(error) if error.raw_os_error() == Some(libc::ENXIO) => self.offset,
+                Err(error) => return Err(error),
+            };
+            seek(self.fd, self.offset, libc::SEEK_SET)?;
+        }
+
+        Ok((0, (self.data_end - self.offset).min(space as u64) as usize))
+    }
+
+    /// Account for bytes read from the file.
+    pub(super) fn advance(&mut self, count: usize) {
+        self.offset += count as u64;
+    }
+}
+
+fn seek(fd: RawFd, offset: u64, whence: i32) -> io::Result<u64> {
+    let result = unsafe { libc::lseek64(fd, offset as libc::off64_t, whence) };
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result as u64)
+    }
+}
@@ -0,0 +1,602 @@
+//
+// Copyright (c) 2023 Florian Gäbler
+//
+
+use super::*;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+
+/// Size of a tar header and of the blocks the member data is padded to.
+const BLOCK_SIZE: usize = 512;
+
+/// Maximum size of the headers of a member, including pax and GNU extension headers.
+const MAX_HEADER_SIZE: usize = 1 << 20;
+
+///
+/// The part of a tar archive a [TarChunk] belongs to.
+///
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum TarChunkKind {
+    /// The header of a member, including preceding pax and GNU extension headers.\
+    /// Global pax headers directly before the end of the archive form a header on their own.
+    /// Headers larger than the maximum chunk size are split into several chunks.
+    Header,
+    /// A content-defined chunk of the data of a member.
+    Data,
+    /// The zero padding after the data of a member.
+    Padding,
+    /// The end-of-archive marker and anything following it.
+    Trailer,
+}
+
+///
+/// Represents a chunk identified by the [TarCDC] chunker.\
+/// See the individual fields for more documentation.
+///
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct TarChunk {
+    /// The chunk with offsets relative to the start of the archive.\
+    /// The hash is zero for chunks that are not content-defined.
+    pub chunk: Chunk,
+
+    /// The part of the archive this chunk belongs to.
+    pub kind: TarChunkKind,
+}
+
+/// A part of the archive, with offsets relative to the start of the archive.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct Segment {
+    start: usize,
+    end: usize,
+    kind: TarChunkKind,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum State {
+    Header,
+    Data { remaining: usize, padding: usize },
+    Padding(usize),
+    Trailer,
+    Done,
+}
+
+/// The source of a [TarCDC], which parses the headers of the archive while passing
+/// it on and records the parts of the archive.
+struct TarSource<R: Read> {
+    source: R,
+    /// Headers that have been parsed, but not passed on yet.
+    header: Vec<u8>,
+    /// Number of bytes of `header` passed on.
+    header_pos: usize,
+    /// Number of bytes passed on so far.
+    position: usize,
+    /// The part of the archive to read next.
+    state: State,
+    /// Parts of the archive that have not been chunked yet.
+    segments: VecDeque<Segment>,
+    /// Ends of the parts found since the last chunk, to be forced as cut points.
+    cuts: Vec<usize>,
+    /// Error in the archive, returned once the data before it has been chunked.
+    error: Option<Error>,
+}
+
+impl<R: Read> TarSource<R> {
+    fn new(source: R) -> Self {
+        Self {
+            source,
+            header: Vec::with_capacity(BLOCK_SIZE),
+            header_pos: 0,
+            position: 0,
+            state: State::Header,
+            segments: VecDeque::new(),
+            cuts: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Record a part of the archive, unless it is empty.
+    fn push_segment(&mut self, start: usize, end: usize, kind: TarChunkKind) {
+        if start < end {
+            self.segments.push_back(Segment { start, end, kind });
+            self.cuts.push(end);
+        }
+    }
+
+    /// Read the next header including any extension headers into `header`, and record
+    /// the parts of the archive up to the next header.
+    fn read_header(&mut self) -> Result<(), Error> {
+        let start = self.position;
+        let mut pax_size = None;
+        let mut global = true;
+        self.header.clear();
+        self.header_pos = 0;
+        loop {
+            let block_start = self.header.len();
+            let offset = start + block_start;
+            self.header.resize(block_start + BLOCK_SIZE, 0);
+            let filled = read_full(&mut self.source, &mut self.header[block_start..])?;
+            if filled == 0 && block_start == 0 {
+                self.header.clear();
+                self.state = State::Done;
+                return Ok(());
+            } else if filled < BLOCK_SIZE {
+                return Err(Error::Other(format!("truncated tar header at offset {}", offset)));
+            }
+
+            let block = &self.header[block_start..];
+            if block.iter().all(|byte| *byte == 0) {
+                if block_start > 0 && !global {
+                    return Err(Error::Other(format!("missing tar header at offset {}", offset)));
+                }
+                // Global pax headers without a following member form a header on their own.
+                self.push_segment(start, offset, TarChunkKind::Header);
+                self.segments.push_back(Segment { start: offset, end: usize::MAX, kind: TarChunkKind::Trailer });
+                self.state = State::Trailer;
+                return Ok(());
+            }
+
+            let stored = parse_number(&block[148..156]);
+            let computed = block.iter().enumerate()
+                .map(|(index, byte)| if (148..156).contains(&index) { b' ' as u64 } else { *byte as u64 })
+                .sum::<u64>();
+            if stored != Some(computed) {
+                return Err(Error::Other(format!("invalid tar header checksum at offset {}", offset)));
+            }
+
+            let size = parse_number(&block[124..136])
+                .and_then(|size| usize::try_from(size).ok())
+                .ok_or_else(|| Error::Other(format!("invalid tar size field at offset {}", offset)))?;
+            let typeflag = block[156];
+            global &= typeflag == b'g';
+
+            let size = match typeflag {
+                // pax extended headers and GNU long names precede the actual header.
+                b'x' | b'g' | b'L' | b'K' => {
+                    let data_start = self.header.len();
+                    let data_end = padded(size)
+                        .and_then(|padded| padded.checked_add(data_start))
+                        .filter(|end| *end <= MAX_HEADER_SIZE)
+                        .ok_or_else(|| Error::Other(format!("tar headers at offset {} exceed {} bytes", start, MAX_HEADER_SIZE)))?;
+                    self.header.resize(data_end, 0);
+                    self.source.read_exact(&mut self.header[data_start..])?;
+                    if typeflag == b'x' {
+                        pax_size = parse_pax_size(&self.header[data_start..data_start + size]).or(pax_size);
+                    }
+                    continue;
+                }
+                // Only regular files and unknown types have data.
+                b'1' | b'2' | b'3' | b'4' | b'5' | b'6' => 0,
+                _ => pax_size.unwrap_or(size),
+            };
+
+            let end = start + self.header.len();
+            let padding_end = padded(size)
+                .and_then(|padded| padded.checked_add(end))
+                .ok_or_else(|| Error::Other(format!("invalid tar member size at offset {}", offset)))?;
+            self.push_segment(start, end, TarChunkKind::Header);
+            self.push_segment(end, end + size, TarChunkKind::Data);
+            self.push_segment(end + size, padding_end, TarChunkKind::Padding);
+            self.state = State::Data { remaining: size, padding: padding_end - end - size };
+            return Ok(());
+        }
+    }
+}
+
+impl<R: Read> Read for TarSource<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            if self.header_pos < self.header.len() {
+                let length = buf.len().min(self.header.len() - self.header_pos);
+                buf[..length].copy_from_slice(&self.header[self.header_pos..self.header_pos + length]);
+                self.header_pos += length;
+                self.position += length;
+                return Ok(length);
+            }
+
+            let remaining = match self.state {
+                State::Header => {
+                    if let Err(error) = self.read_header() {
+                        // Hold the malformed headers back and end the source before them.
+                        self.header.clear();
+                        self.error = Some(error);
+                        self.state = State::Done;
+                    }
+                    continue;
+                }
+                State::Data { remaining: 0, padding } => {
+                    self.state = State::Padding(padding);
+                    continue;
+                }
+                State::Padding(0) => {
+                    self.state = State::Header;
+                    continue;
+                }
+                State::Data { remaining, .. } | State::Padding(remaining) => remaining,
+                State::Trailer => usize::MAX,
+                State::Done => return Ok(0),
+            };
+
+            let length = buf.len().min(remaining);
+            if length == 0 {
+                return Ok(0);
+            }
+            let bytes_read = self.source.read(&mut buf[..length])?;
+            if bytes_read == 0 {
+                if self.state != State::Trailer {
+                    self.error = Some(Error::Other(format!("truncated tar member data at offset {}", self.position)));
+                }
+                self.state = State::Done;
+            } else if let State::Data { remaining, .. } | State::Padding(remaining) = &mut self.state {
+                *remaining -= bytes_read;
+            }
+            self.position += bytes_read;
+            return Ok(bytes_read);
+        }
+    }
+}
+
+///
+/// A streaming chunker for tar archives that aligns the cut points with the archive members.\
+/// \
+/// The ustar, pax and GNU headers are parsed while reading the archive. The headers of each
+/// member and the padding after its data are returned as separate chunks, while the data of
+/// each member is chunked independently, exactly as [StreamCDC] would chunk the member data
+/// on its own. Changes to a header, such as a modified mtime, therefore only affect the
+/// header chunk.\
+/// \
+/// The archive is chunked by a [StreamCDC] with forced cut points at the member boundaries,
+/// so this struct also allocates a `Vec<u8>` of `max_size` bytes to act as a buffer.
+///
+/// ```no_run
+/// # use std::fs::File;
+/// # use fastcdc_alt::v2020::TarCDC;
+/// let source = File::open("backup.tar").unwrap();
+/// let chunker = TarCDC::new(source, 4096, 16384, 65535).unwrap();
+/// for result in chunker {
+///     let (_data, tar_chunk) = result.unwrap();
+///     println!("kind={:?} offset={} length={}", tar_chunk.kind, tar_chunk.chunk.offset, tar_chunk.chunk.get_length());
+/// }
+/// ```
+///
+pub struct TarCDC<R: Read> {
+    inner: StreamCDC<TarSource<R>>,
+    /// True after the end or an error has been returned.
+    done: bool,
+}
+
+impl<R: Read> TarCDC<R> {
+    ///
+    /// Construct a `TarCDC` that will process the tar archive from the given source.
+    ///
+    /// Uses chunk size normalization level 1 by default.
+    ///
+    pub fn new(source: R, min_size: u32, avg_size: u32, max_size: u32) -> Result<Self, Error> {
+        Self::new_advanced(source, min_size, avg_size, max_size, Normalization::Level1)
+    }
+
+    ///
+    /// Create a new `TarCDC` with the given normalization level.
+    ///
+    pub fn new_advanced(
+        source: R,
+        min_size: u32,
+        avg_size: u32,
+        max_size: u32,
+        level: Normalization,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            inner: StreamCDC::new_advanced(TarSource::new(source), min_size, avg_size, max_size, level)?,
+            done: false,
+        })
+    }
+
+    /// Find the next chunk in the archive. If the end of the source has been
+    /// reached, returns `Error::Empty` as the error.
+    fn read_chunk(&mut self) -> Result<(Vec<u8>, TarChunk), Error> {
+        self.inner.fill_buffer()?;
+        for offset in std::mem::take(&mut self.inner.source.cuts) {
+            self.inner.force_cut(offset);
+        }
+
+        let processed = self.inner.processed;
+        let source = &mut self.inner.source;
+        while source.segments.front().is_some_and(|segment| segment.end <= processed) {
+            source.segments.pop_front();
+        }
+        let segment = match source.segments.front() {
+            Some(segment) if self.inner.length > 0 => *segment,
+            _ => return Err(source.error.take().unwrap_or(Error::Empty)),
+        };
+
+        let (data, chunk) = match segment.kind {
+            // Headers and padding are not content-defined, so they are returned as a whole.
+            TarChunkKind::Header | TarChunkKind::Padding => {
+                let length = (segment.end - processed).min(self.inner.length);
+                let data = self.inner.drain_bytes(length)?;
+                let zero = zero_prefix(&data) == length;
+                self.inner.processed += length;
+                (data, Chunk { hash: 0, offset: processed as isize, cutpoint: processed + length, zero })
+            }
+            TarChunkKind::Data | TarChunkKind::Trailer => self.inner.read_chunk()?,
+        };
+
+        Ok((data, TarChunk { chunk, kind: segment.kind }))
+    }
+}
+
+impl<R: Read> Iterator for TarCDC<R> {
+    type Item = Result<(Vec<u8>, TarChunk), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let slice = self.read_chunk();
+        if let Err(error) = slice {
+            // Do not continue after the end or a malformed archive.
+            self.done = true;
+            if let Error::Empty = error {
+                None
+            } else {
+                Some(Err(error))
+            }
+        } else {
+            Some(slice)
+        }
+    }
+}
+
+/// Read from the source until the buffer is full or the end of the source, returning
+/// the number of bytes read.
+fn read_full<R: Read>(source: &mut R, buffer: &mut [u8]) -> Result<usize, Error> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match source.read(&mut buffer[filled..])? {
+            0 => break,
+            bytes_read => filled += bytes_read,
+        }
+    }
+    Ok(filled)
+}
+
+/// Round the size up to a multiple of the block size, unless that overflows.
+fn padded(size: usize) -> Option<usize> {
+    size.div_ceil(BLOCK_SIZE).checked_mul(BLOCK_SIZE)
+}
+
+/// Parse a numeric header field, which is either octal or base-256 encoded.
+fn parse_number(field: &[u8]) -> Option<u64> {
+    if field[0] & 0x80 != 0 {
+        return field[1..].iter().try_fold(u64::from(field[0] & 0x7f), |value, byte| {
+            value.checked_mul(256).map(|value| value + u64::from(*byte))
+        });
+    }
+
+    let digits = field.iter()
+        .skip_while(|byte| **byte == b' ')
+        .take_while(|byte| (b'0'..=b'7').contains(*byte));
+    let mut value = None;
+    for byte in digits {
+        value = Some(value.unwrap_or(0u64).checked_mul(8)? + u64::from(byte - b'0'));
+    }
+    value
+}
+
+/// Find the `size` record in the data of a pax extended header.
+fn parse_pax_size(mut data: &[u8]) -> Option<usize> {
+    let mut size = None;
+    while !data.is_empty() {
+        let space = data.iter().position(|byte| *byte == b' ')?;
+        let length = std::str::from_utf8(&data[..space]).ok()?.parse::<usize>().ok()?;
+        if length <= space || length > data.len() {
+            return None;
+        }
+
+        let record = &data[space + 1..length];
+        if let Some(value) = record.strip_prefix(b"size=") {
+            let value = value.strip_suffix(b"\n").unwrap_or(value);
+            size = std::str::from_utf8(value).ok()?.parse::<usize>().ok();
+        }
+        data = &data[length..];
+    }
+    size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(name: &str, size: usize, typeflag: u8, mtime: u64) -> Vec<u8> {
+        let mut block = vec![0u8; BLOCK_SIZE];
+        block[..name.len()].copy_from_slice(name.as_bytes());
+        block[100..107].copy_from_slice(b"0000644");
+        block[124..135].copy_from_slice(format!("{:011o}", size).as_bytes());
+        block[136..147].copy_from_slice(format!("{:011o}", mtime).as_bytes());
+        block[148..156].copy_from_slice(b"        ");
+        block[156] = typeflag;
+        block[257..263].copy_from_slice(b"ustar\0");
+        block[263..265].copy_from_slice(b"00");
+        let checksum = block.iter().map(|byte| *byte as u32).sum::<u32>();
+        block[148..155].copy_from_slice(format!("{:06o}\0", checksum).as_bytes());
+        block
+    }
+
+    fn member(archive: &mut Vec<u8>, name: &str, data: &[u8], mtime: u64) {
+        archive.extend(header(name, data.len(), b'0', mtime));
+        archive.extend_from_slice(data);
+        archive.resize(padded(archive.len()).unwrap(), 0);
+    }
+
+    fn finish(archive: &mut Vec<u8>) {
+        archive.resize(archive.len() + 2 * BLOCK_SIZE, 0);
+    }
+
+    fn build_archive(mtime: u64) -> (Vec<u8>, Vec<u8>) {
+        let contents = std::fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        let mut archive = Vec::new();
+        member(&mut archive, "small.txt", b"hello world", mtime);
+        archive.extend(header("dir/", 0, b'5', mtime));
+        member(&mut archive, "SekienAkashita.jpg", &contents, mtime);
+        finish(&mut archive);
+        (archive, contents)
+    }
+
+    fn chunks(archive: &[u8]) -> Vec<(Vec<u8>, TarChunk)> {
+        TarCDC::new(archive, 4096, 16384, 65535).unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_number(b"00000001750\0"), Some(1000));
+        assert_eq!(parse_number(b"   1750 \0"), Some(1000));
+        assert_eq!(parse_number(&[0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x03, 0xe8]), Some(1000));
+        assert_eq!(parse_number(b"\0\0\0"), None);
+        assert_eq!(parse_pax_size(b"30 mtime=1350244992.023960108\n11 size=42\n"), Some(42));
+        assert_eq!(parse_pax_size(b"13 path=test\n"), None);
+    }
+
+    #[test]
+    fn test_tar_members() {
+        let (archive, contents) = build_archive(0);
+        let chunks = chunks(&archive);
+
+        let kinds = chunks.iter().map(|(_, tar_chunk)| tar_chunk.kind).collect::<Vec<_>>();
+        assert_eq!(kinds[..5], [
+            TarChunkKind::Header,
+            TarChunkKind::Data,
+            TarChunkKind::Padding,
+            TarChunkKind::Header,
+            TarChunkKind::Header,
+        ]);
+        assert_eq!(*kinds.last().unwrap(), TarChunkKind::Trailer);
+
+        let mut cursor = 0;
+        for (data, tar_chunk) in &chunks {
+            assert_eq!(tar_chunk.chunk.offset, cursor as isize);
+            assert_eq!(data[..], archive[cursor..tar_chunk.chunk.cutpoint]);
+            cursor = tar_chunk.chunk.cutpoint;
+        }
+        assert_eq!(cursor, archive.len());
+
+        // The member data is chunked as if it were chunked on its own.
+        let expected = FastCDC::new(4096, 16384, 65535).unwrap()
+            .as_iterator(&contents)
+            .map(|chunk| (chunk.hash, chunk.get_length()))
+            .collect::<Vec<_>>();
+        let data = chunks[5..].iter()
+            .filter(|(_, tar_chunk)| tar_chunk.kind == TarChunkKind::Data)
+            .map(|(_, tar_chunk)| (tar_chunk.chunk.hash, tar_chunk.chunk.get_length()))
+            .collect::<Vec<_>>();
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn test_tar_header_change() {
+        let (archive, _) = build_archive(0);
+        let (modified, _) = build_archive(1_700_000_000);
+        let original = chunks(&archive);
+        let modified = chunks(&modified);
+
+        assert_eq!(original.len(), modified.len());
+        for (original, modified) in original.iter().zip(modified.iter()) {
+            assert_eq!(original.1.chunk.offset, modified.1.chunk.offset);
+            assert_eq!(original.0 == modified.0, original.1.kind != TarChunkKind::Header);
+        }
+    }
+
+    #[test]
+    fn test_pax_size() {
+        let data = vec![7u8; 1000];
+        let record = b"13 size=1000\n";
+        let mut archive = header("././@PaxHeader", record.len(), b'x', 0);
+        archive.extend_from_slice(record);
+        archive.resize(padded(archive.len()).unwrap(), 0);
+        // The size in the ustar header is overridden by the pax header.
+        archive.extend(header("large.bin", 0, b'0', 0));
+        archive.extend_from_slice(&data);
+        archive.resize(padded(archive.len()).unwrap(), 0);
+        finish(&mut archive);
+
+        let chunks = chunks(&archive);
+        assert_eq!(chunks[0].1.kind, TarChunkKind::Header);
+        assert_eq!(chunks[0].1.chunk.get_length(), 3 * BLOCK_SIZE);
+        assert_eq!(chunks[1].1.kind, TarChunkKind::Data);
+        assert_eq!(chunks[1].0, data);
+        assert_eq!(chunks[2].1.kind, TarChunkKind::Padding);
+        assert!(chunks[2].1.chunk.zero);
+        assert_eq!(chunks[3].1.kind, TarChunkKind::Trailer);
+    }
+
+    #[test]
+    fn test_trailing_global_header() {
+        let record = b"16 comment=test\n";
+        let mut archive = Vec::new();
+        member(&mut archive, "small.txt", b"hello world", 0);
+        archive.extend(header("pax_global_header", record.len(), b'g', 0));
+        archive.extend_from_slice(record);
+        archive.resize(padded(archive.len()).unwrap(), 0);
+        finish(&mut archive);
+
+        let chunks = chunks(&archive);
+        let kinds = chunks.iter().map(|(_, tar_chunk)| tar_chunk.kind).collect::<Vec<_>>();
+        assert_eq!(kinds, [
+            TarChunkKind::Header,
+            TarChunkKind::Data,
+            TarChunkKind::Padding,
+            TarChunkKind::Header,
+            TarChunkKind::Trailer,
+        ]);
+        assert_eq!(chunks[3].1.chunk.get_length(), 2 * BLOCK_SIZE);
+        assert_eq!(chunks[4].1.chunk.cutpoint, archive.len());
+
+        // Other extension headers still need a member header.
+        let mut archive = header("././@LongLink", 4, b'L', 0);
+        archive.extend_from_slice(b"name");
+        archive.resize(padded(archive.len()).unwrap(), 0);
+        finish(&mut archive);
+        let result = TarCDC::new(archive.as_slice(), 4096, 16384, 65535).unwrap().collect::<Vec<_>>();
+        assert!(result.last().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_malformed_sizes() {
+        // Extension headers are limited in size instead of being read into memory.
+        let mut archive = Vec::new();
+        member(&mut archive, "small.txt", b"hello world", 0);
+        archive.extend(header("././@PaxHeader", 2 * MAX_HEADER_SIZE, b'x', 0));
+        finish(&mut archive);
+        let result = TarCDC::new(archive.as_slice(), 4096, 16384, 65535).unwrap().collect::<Vec<_>>();
+        assert_eq!(result.len(), 4);
+        assert!(matches!(result[3], Err(Error::Other(_))));
+
+        // A pax size whose padding overflows is rejected.
+        let record = b"29 size=18446744073709551615\n";
+        let mut archive = header("././@PaxHeader", record.len(), b'x', 0);
+        archive.extend_from_slice(record);
+        archive.resize(padded(archive.len()).unwrap(), 0);
+        archive.extend(header("large.bin", 0, b'0', 0));
+        finish(&mut archive);
+        let result = TarCDC::new(archive.as_slice(), 4096, 16384, 65535).unwrap().collect::<Vec<_>>();
+        assert_eq!(result.len(), 1);
+        assert!(matches!(result[0], Err(Error::Other(_))));
+    }
+
+    #[test]
+    fn test_invalid_archives() {
+        let (mut archive, _) = build_archive(0);
+        archive[148] = b'7';
+        let result = TarCDC::new(archive.as_slice(), 4096, 16384, 65535).unwrap().collect::<Vec<_>>();
+        assert_eq!(result.len(), 1);
+        assert!(result[0].is_err());
+
+        let (archive, _) = build_archive(0);
+        let truncated = &archive[..20_000];
+        let result = TarCDC::new(truncated, 4096, 16384, 65535).unwrap().collect::<Vec<_>>();
+        assert!(result.last().unwrap().is_err());
+
+        assert_eq!(TarCDC::new(&[][..], 4096, 16384, 65535).unwrap().count(), 0);
+    }
+}
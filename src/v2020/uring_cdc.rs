@@ -0,0 +1,551 @@
+//
+// Copyright (c) 2023 Florian Gäbler
+//
+
+use super::*;
+use io_uring::{opcode, register::Probe, types, IoUring};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Seek};
+use std::os::unix::fs::{FileExt, FileTypeExt};
+use std::os::unix::io::AsRawFd;
+
+/// Number of reads kept in flight by [UringCDC].
+pub const URING_QUEUE_DEPTH: usize = 4;
+
+/// Size of each of the pooled buffers the reads of [UringCDC] complete into,
+/// unless the maximum chunk size is larger.
+pub const URING_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// State of the read into a slot.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum SlotState {
+    /// The slot is not in use.
+    Free,
+    /// A read into the slot has been submitted.
+    Pending,
+    /// The buffer is full, or a read returned no data.
+    Done,
+    /// A read failed with the given error number, which is returned until the slot is dropped.
+    Failed(i32),
+}
+
+/// A pooled buffer and the reads filling it.
+struct Slot {
+    buffer: Box<[u8]>,
+    /// File offset of the first byte of the buffer.
+    offset: u64,
+    /// Number of bytes read into the buffer so far.
+    filled: usize,
+    state: SlotState,
+}
+
+/// Reads a file sequentially into pooled buffers with several reads in flight,
+/// until a read returns no data.
+struct UringReader {
+    ring: IoUring,
+    file: File,
+    slots: Vec<Slot>,
+    /// Slots in use, in order of their offsets.
+    queue: VecDeque<usize>,
+    /// Slots not in use.
+    free: Vec<usize>,
+    /// Offset of the next slot to read.
+    next_offset: u64,
+    /// Start of the unconsumed data in the buffer of the first slot.
+    cursor: usize,
+    /// True once a read returned no data.
+    eof: bool,
+    /// True once io_uring rejected a read, after which plain reads are used.
+    fallback: bool,
+    in_flight: usize,
+}
+
+impl UringReader {
+    fn new(file: File, start: u64, buffer_size: usize) -> io::Result<Self> {
+        let ring = IoUring::new(URING_QUEUE_DEPTH as u32)?;
+
+        let mut probe = Probe::new();
+        ring.submitter().register_probe(&mut probe)?;
+        if !probe.is_supported(opcode::Read::CODE) {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "io_uring read not supported"));
+        }
+
+        let slots = (0..URING_QUEUE_DEPTH).map(|_| Slot {
+            buffer: vec![0; buffer_size].into_boxed_slice(),
+            offset: 0,
+            filled: 0,
+            state: SlotState::Free,
+        }).collect();
+
+        Ok(Self {
+            ring,
+            file,
+            slots,
+            queue: VecDeque::new(),
+            free: (0..URING_QUEUE_DEPTH).rev().collect(),
+            next_offset: start,
+            cursor: 0,
+            eof: false,
+            fallback: false,
+            in_flight: 0,
+        })
+    }
+
+    /// Read the rest of the buffer of the slot, through io_uring or with plain reads.
+    fn submit(&mut self, index: usize) -> io::Result<()> {
+        let slot = &mut self.slots[index];
+
+        if self.fallback {
+            while slot.filled < slot.buffer.len() {
+                match self.file.read_at(&mut slot.buffer[slot.filled..], slot.offset + slot.filled as u64) {
+                    Ok(0) => {
+                        self.eof = true;
+                        break;
+                    }
+                    Ok(length) => slot.filled += length,
+                    Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                    Err(error) => {
+                        slot.state = SlotState::Failed(error.raw_os_error().unwrap_or(libc::EIO));
+                        return Ok(());
+                    }
+                }
+            }
+            slot.state = SlotState::Done;
+            return Ok(());
+        }
+
+        let buffer = &mut slot.buffer[slot.filled..];
+        let entry = opcode::Read::new(types::Fd(self.file.as_raw_fd()), buffer.as_mut_ptr(), buffer.len() as u32)
+            .offset(slot.offset + slot.filled as u64)
+            .build()
+            .user_data(index as u64);
+
+        // The buffer is neither moved nor freed until the read completed.
+        unsafe { self.ring.submission().push(&entry) }
+            .map_err(|_| io::Error::other("io_uring submission queue is full"))?;
+        slot.state = SlotState::Pending;
+        self.in_flight += 1;
+
+        Ok(())
+    }
+
+    /// Handle the result of a read into the slot.
+    fn complete(&mut self, index: usize, result: i32) -> io::Result<()> {
+        let slot = &mut self.slots[index];
+        match result {
+            0 => {
+                slot.state = SlotState::Done;
+                self.eof = true;
+            }
+            length if length > 0 => {
+                slot.filled += length as usize;
+                if slot.filled < slot.buffer.len() {
+                    // Read the rest of the buffer after a short read.
+                    return self.submit(index);
+                }
+                slot.state = SlotState::Done;
+            }
+            error if error == -libc::EAGAIN || error == -libc::EINTR => return self.submit(index),
+            // io_uring does not support reads of this file, e.g. because of its file system.
+            error if error == -libc::EINVAL || error == -libc::EOPNOTSUPP => {
+                self.fallback = true;
+                return self.submit(index);
+            }
+            error => slot.state = SlotState::Failed(-error),
+        }
+
+        Ok(())
+    }
+
+    /// Wait until the reads into the slot are done.
+    fn wait(&mut self, index: usize) -> io::Result<()> {
+        while self.slots[index].state == SlotState::Pending {
+            self.ring.submit_and_wait(1)?;
+            loop {
+                let cqe = self.ring.completion().next();
+                match cqe {
+                    Some(cqe) => {
+                        self.in_flight -= 1;
+                        self.complete(cqe.user_data() as usize, cqe.result())?;
+                    }
+                    None => break,
+                }
+            }
+        }
+
+        match self.slots[index].state {
+            SlotState::Failed(error) => Err(io::Error::from_raw_os_error(error)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Release the first slot if all of its data has been consumed, and read into all
+    /// free slots.
+    fn fill(&mut self) -> io::Result<()> {
+        if let Some(&head) = self.queue.front() {
+            let slot = &mut self.slots[head];
+            if slot.state == SlotState::Done && self.cursor == slot.buffer.len() {
+                slot.state = SlotState::Free;
+                self.queue.pop_front();
+                self.free.push(head);
+                self.cursor = 0;
+            }
+        }
+
+        while !self.eof {
+            let index = match self.free.pop() {
+                Some(index) => index,
+                None => break,
+            };
+            let slot = &mut self.slots[index];
+            slot.offset = self.next_offset;
+            slot.filled = 0;
+            self.next_offset += slot.buffer.len() as u64;
+            self.queue.push_back(index);
+            self.submit(index)?;
+        }
+
+        Ok(())
+    }
+
+    /// Find the next chunk by cutting in the buffers of the first two slots. Returns the
+    /// data of the chunk, which is only copied into `spill` if the chunk spans both slots,
+    /// or [None] at the end of the file.
+    fn cut<'a>(&'a mut self, inner: &mut FastCDC, spill: &'a mut Vec<u8>) -> io::Result<Option<(&'a [u8], Chunk)>> {
+        self.fill()?;
+        let head = match self.queue.front() {
+            Some(&head) => head,
+            None => return Ok(None),
+        };
+        self.wait(head)?;
+
+        // Reads past the end return no data, even if the file grows afterwards, so the
+        // data ends at the first slot that is not full. The buffers hold at least
+        // `max_size` bytes, so a chunk spans at most two slots.
+        let start = self.cursor;
+        let mut available = self.slots[head].filled - start;
+        let next = match self.queue.get(1) {
+            Some(&next) if self.slots[head].filled == self.slots[head].buffer.len() && available < inner.max_size => {
+                self.wait(next)?;
+                available += self.slots[next].filled;
+                Some(next)
+            }
+            _ => None,
+        };
+        if available == 0 {
+            return Ok(None);
+        }
+
+        inner.set_content_length(available);
+        let slot = &self.slots[head];
+        if let Some(chunk) = inner.cut(&slot.buffer[start..slot.filled]) {
+            self.cursor += chunk.cutpoint;
+            return Ok(Some((&self.slots[head].buffer[start..self.cursor], chunk)));
+        }
+
+        let next = &self.slots[next.ok_or_else(|| io::Error::other("no cut point found in the buffers"))?];
+        let chunk = inner.cut(&next.buffer[..next.filled])
+            .ok_or_else(|| io::Error::other("no cut point found in the buffers"))?;
+        spill.clear();
+        spill.extend_from_slice(&slot.buffer[start..]);
+        spill.extend_from_slice(&next.buffer[..chunk.cutpoint]);
+
+        // The first slot has been consumed completely.
+        self.slots[head].state = SlotState::Free;
+        self.queue.pop_front();
+        self.free.push(head);
+        self.cursor = chunk.cutpoint;
+
+        Ok(Some((spill, chunk)))
+    }
+}
+
+impl Drop for UringReader {
+    fn drop(&mut self) {
+        // The kernel may still write into the buffers of pending reads.
+        while self.in_flight > 0 {
+            if self.ring.submit_and_wait(self.in_flight).is_err() {
+                break;
+            }
+            self.in_flight -= self.ring.completion().count();
+        }
+    }
+}
+
+/// The source of a [UringCDC], read through io_uring if possible.
+enum Source {
+    Uring(Box<UringReader>),
+    Stream(Box<StreamCDC<File>>),
+}
+
+///
+/// A file chunker for Linux which reads the file through io_uring, with several
+/// reads in flight into pooled buffers, and chunks the data like [StreamCDC].\
+/// \
+/// The file is read from its current position until a read returns no data, so
+/// files without a known length, such as procfs files and block devices, and files
+/// that grow while they are chunked are read completely. The chunks are identical to
+/// those of [StreamCDC] for the same file. If io_uring is not available, e.g. because
+/// of the kernel version or a seccomp policy, or the file cannot be read at an offset,
+/// e.g. a pipe, the chunker falls back to a [StreamCDC] transparently, and if io_uring
+/// rejects the reads of the file, it continues with plain reads, see
+/// [is_uring()](UringCDC::is_uring).\
+/// \
+/// The cut points are found in the pooled buffers directly. The chunks are returned by
+/// [next_chunk()](UringCDC::next_chunk) together with a slice borrowing the buffers, so
+/// the data is only copied if a chunk spans two buffers.\
+/// \
+/// Requires the `io_uring` feature.
+///
+/// ```no_run
+/// # use std::fs::File;
+/// # use fastcdc_alt::UringCDC;
+/// let source = File::open("test/fixtures/SekienAkashita.jpg").unwrap();
+/// let chunker = UringCDC::new(source, 4096, 16384, 65535).unwrap();
+/// for result in chunker {
+///     let (_data, chunk) = result.unwrap();
+///     println!("offset={} size={}", chunk.offset, chunk.get_length());
+/// }
+/// ```
+///
+pub struct UringCDC {
+    inner: FastCDC,
+    source: Source,
+    /// Data of the last chunk if it spans two buffers.
+    spill: Vec<u8>,
+    /// Offset of the next chunk relative to the start position of the file.
+    position: usize,
+}
+
+impl UringCDC {
+    ///
+    /// Construct a `UringCDC` that will process the given file.
+    ///
+    /// Uses chunk size normalization level 1 by default.
+    ///
+    pub fn new(file: File, min_size: u32, avg_size: u32, max_size: u32) -> Result<Self, Error> {
+        Self::new_advanced(file, min_size, avg_size, max_size, Normalization::Level1)
+    }
+
+    ///
+    /// Create a new `UringCDC` with the given normalization level.
+    ///
+    pub fn new_advanced(
+        mut file: File,
+        min_size: u32,
+        avg_size: u32,
+        max_size: u32,
+        level: Normalization,
+    ) -> Result<Self, Error> {
+        let inner = FastCDC::new_advanced(min_size, avg_size, max_size, level, None)?;
+
+        // Only regular files and block devices can be read at an offset.
+        let file_type = file.metadata()?.file_type();
+        let position = if file_type.is_file() || file_type.is_block_device() {
+            file.stream_position().ok()
+        } else {
+            None
+        };
+
+        let buffer_size = URING_BUFFER_SIZE.max(inner.max_size);
+        let reader = position.and_then(|start| file.try_clone().and_then(|clone| UringReader::new(clone, start, buffer_size)).ok());
+        let source = match reader {
+            Some(reader) => Source::Uring(Box::new(reader)),
+            None => Source::Stream(Box::new(StreamCDC::new_advanced(file, min_size, avg_size, max_size, level)?)),
+        };
+
+        Ok(Self { inner, source, spill: Vec::new(), position: 0 })
+    }
+
+    ///
+    /// Whether the file is read through io_uring, or otherwise with a [StreamCDC]
+    /// or plain reads.
+    ///
+    pub fn is_uring(&self) -> bool {
+        matches!(&self.source, Source::Uring(reader) if !reader.fallback)
+    }
+
+    ///
+    /// Find the next chunk in the file, returning the chunk data from the buffers
+    /// together with the chunk, whose offsets are relative to the start position of the file.\
+    /// Returns `None` once the end of the file has been reached.
+    ///
+    pub fn next_chunk(&mut self) -> Option<Result<(&[u8], Chunk), Error>> {
+        let reader = match &mut self.source {
+            Source::Uring(reader) => reader,
+            Source::Stream(stream) => return match stream.next()? {
+                Ok((data, chunk)) => {
+                    self.spill = data;
+                    Some(Ok((&self.spill, chunk)))
+                }
+                Err(error) => Some(Err(error)),
+            },
+        };
+
+        let (data, chunk) = match reader.cut(&mut self.inner, &mut self.spill) {
+            Ok(Some(result)) => result,
+            Ok(None) => return None,
+            Err(error) => return Some(Err(error.into())),
+        };
+
+        let offset = self.position;
+        self.position += data.len();
+
+        Some(Ok((data, Chunk {
+            hash: chunk.hash,
+            offset: offset as isize,
+            cutpoint: self.position,
+            zero: chunk.zero
+        })))
+    }
+}
+
+impl Iterator for UringCDC {
+    type Item = Result<(Vec<u8>, Chunk), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Source::Stream(stream) = &mut self.source {
+            return stream.next();
+        }
+
+        self.next_chunk().map(|result| result.map(|(data, chunk)| (data.to_vec(), chunk)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Write;
+
+    #[test]
+    fn test_uring_matches_stream() {
+        let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        let path = std::env::temp_dir().join(format!("fastcdc-uring-{}", std::process::id()));
+        let mut large = Vec::new();
+        for index in 0..40 {
+            large.extend_from_slice(&contents[index * 1000..]);
+        }
+        fs::write(&path, &large).unwrap();
+
+        let stream = StreamCDC::new(File::open(&path).unwrap(), 4096, 16384, 65535).unwrap();
+        let expected = stream.map(|result| result.unwrap()).collect::<Vec<_>>();
+
+        let chunker = UringCDC::new(File::open(&path).unwrap(), 4096, 16384, 65535).unwrap();
+        let chunks = chunker.map(|result| result.unwrap()).collect::<Vec<_>>();
+        fs::remove_file(&path).unwrap();
+
+        assert!(large.len() > 2 * URING_BUFFER_SIZE);
+        assert_eq!(chunks.len(), expected.len());
+        assert!(chunks == expected);
+    }
+
+    #[test]
+    fn test_uring_empty_file() {
+        let path = std::env::temp_dir().join(format!("fastcdc-uring-empty-{}", std::process::id()));
+        File::create(&path).unwrap();
+        let mut chunker = UringCDC::new(File::open(&path).unwrap(), 4096, 16384, 65535).unwrap();
+        assert!(chunker.next().is_none());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_uring_unknown_length() {
+        // procfs files report a length of zero.
+        let file = File::open("/proc/self/mountinfo").unwrap();
+        assert_eq!(file.metadata().unwrap().len(), 0);
+        let chunker = UringCDC::new(file, 64, 256, 1024).unwrap();
+        let data = chunker.map(|result| result.unwrap().0).collect::<Vec<_>>().concat();
+        assert!(!data.is_empty());
+        assert_eq!(data, fs::read("/proc/self/mountinfo").unwrap());
+    }
+
+    #[test]
+    fn test_uring_pipe() {
+        let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        let (reader, mut writer) = std::os::unix::net::UnixStream::pair().unwrap();
+        let data = contents.clone();
+        let thread = std::thread::spawn(move || writer.write_all(&data).unwrap());
+
+        let file = File::from(std::os::unix::io::OwnedFd::from(reader));
+        let chunker = UringCDC::new(file, 4096, 16384, 65535).unwrap();
+        assert!(!chunker.is_uring());
+        let chunks = chunker.map(|result| result.unwrap()).collect::<Vec<_>>();
+        thread.join().unwrap();
+
+        let stream = StreamCDC::new(contents.as_slice(), 4096, 16384, 65535).unwrap();
+        let expected = stream.map(|result| result.unwrap()).collect::<Vec<_>>();
+        assert!(chunks == expected);
+    }
+
+    #[test]
+    fn test_uring_next_chunk() {
+        let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        let stream = StreamCDC::new(contents.as_slice(), 4096, 16384, 65535).unwrap();
+        let expected = stream.map(|result| result.unwrap()).collect::<Vec<_>>();
+
+        let mut chunker = UringCDC::new(File::open("test/fixtures/SekienAkashita.jpg").unwrap(), 4096, 16384, 65535).unwrap();
+        let mut index = 0;
+        while let Some(result) = chunker.next_chunk() {
+            let (data, chunk) = result.unwrap();
+            assert_eq!(data, expected[index].0.as_slice());
+            assert_eq!(chunk, expected[index].1);
+            index += 1;
+        }
+        assert_eq!(index, expected.len());
+    }
+
+    #[test]
+    fn test_uring_read_errors() {
+        let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        let file = File::open("test/fixtures/SekienAkashita.jpg").unwrap();
+        let mut reader = match UringReader::new(file, 0, 4096) {
+            Ok(reader) => reader,
+            // io_uring is not available.
+            Err(_) => return,
+        };
+
+        // Retry a read which was interrupted after a short read.
+        reader.fill().unwrap();
+        let head = reader.queue[0];
+        reader.wait(head).unwrap();
+        reader.slots[head].filled = 100;
+        reader.complete(head, -libc::EAGAIN).unwrap();
+        reader.wait(head).unwrap();
+        assert_eq!(reader.slots[head].filled, 4096);
+        assert_eq!(&reader.slots[head].buffer[..], &contents[..4096]);
+
+        // A failed read is returned every time instead of waiting for it again.
+        reader.complete(head, -libc::EIO).unwrap();
+        assert_eq!(reader.wait(head).unwrap_err().raw_os_error(), Some(libc::EIO));
+        assert_eq!(reader.wait(head).unwrap_err().raw_os_error(), Some(libc::EIO));
+
+        // Continue with plain reads if io_uring rejects the reads of the file.
+        let file = File::open("test/fixtures/SekienAkashita.jpg").unwrap();
+        let mut reader = UringReader::new(file, 0, 4096).unwrap();
+        reader.fill().unwrap();
+        let head = reader.queue[0];
+        reader.wait(head).unwrap();
+        reader.slots[head].filled = 100;
+        reader.complete(head, -libc::EINVAL).unwrap();
+        reader.wait(head).unwrap();
+        assert!(reader.fallback);
+        assert_eq!(&reader.slots[head].buffer[..], &contents[..4096]);
+    }
+
+    #[test]
+    fn test_uring_fallback() {
+        let contents = fs::read("test/fixtures/SekienAkashita.jpg").unwrap();
+        let stream = StreamCDC::new(contents.as_slice(), 4096, 16384, 65535).unwrap();
+        let expected = stream.map(|result| result.unwrap()).collect::<Vec<_>>();
+
+        let mut chunker = UringCDC::new(File::open("test/fixtures/SekienAkashita.jpg").unwrap(), 4096, 16384, 65535).unwrap();
+        if let Source::Uring(reader) = &mut chunker.source {
+            reader.fallback = true;
+        }
+        assert!(!chunker.is_uring());
+        let chunks = chunker.map(|result| result.unwrap()).collect::<Vec<_>>();
+        assert!(chunks == expected);
+    }
+}
@@ -0,0 +1,131 @@
+/*
+ * Copyright (c) 2023 Florian Gäbler
+ *
+ * Chunks test/fixtures/SekienAkashita.jpg through the C API and compares the
+ * chunks with the vectors of the Rust tests. Build the static library with
+ * `cargo rustc --release --features capi --crate-type staticlib` and run from
+ * the repository root.
+ */
+
+#include <inttypes.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+#include "fastcdc_alt.h"
+
+struct expected {
+    uint32_t min_size, avg_size, max_size, level;
+    size_t count;
+    struct { uint64_t hash; size_t length; } chunks[6];
+};
+
+static const struct expected VECTORS[] = {
+    {4096, 16384, 65535, 1, 5, {
+        {17968276318003433923ULL, 21325},
+        {8197189939299398838ULL, 17140},
+        {13019990849178155730ULL, 28084},
+        {4509236223063678303ULL, 18217},
+        {2504464741100432583ULL, 24700},
+    }},
+    {8192, 32768, 131072, 1, 2, {
+        {15733367461443853673ULL, 66549},
+        {6321136627705800457ULL, 42917},
+    }},
+    {16384, 65536, 262144, 1, 1, {
+        {2504464741100432583ULL, 109466},
+    }},
+    {4096, 16384, 65535, 0, 5, {
+        {443122261039895162ULL, 6634},
+        {15733367461443853673ULL, 59915},
+        {10460176299449652894ULL, 25597},
+        {6197802202431009942ULL, 5237},
+        {6321136627705800457ULL, 12083},
+    }},
+    {8192, 16384, 32768, 3, 6, {
+        {10718006254707412376ULL, 17350},
+        {13104072099671895560ULL, 19911},
+        {12322483109039221194ULL, 17426},
+        {16009206469796846404ULL, 17519},
+        {2473608525189754172ULL, 19940},
+        {2504464741100432583ULL, 17320},
+    }},
+};
+
+/* Size of the pieces passed to fastcdc_cut(), to cover chunks across buffers. */
+#define PIECE_SIZE 10000
+
+static int check(const struct expected *vector, const uint8_t *contents, size_t length) {
+    FastCDC *chunker = NULL;
+    FastCDCChunk chunk;
+    size_t start = 0, cursor = 0, index = 0;
+    int failed = 0;
+
+    if (fastcdc_new(vector->min_size, vector->avg_size, vector->max_size, vector->level, &chunker) != FAST_CDC_STATUS_OK) {
+        fprintf(stderr, "invalid configuration %" PRIu32 "/%" PRIu32 "\n", vector->avg_size, vector->level);
+        return 1;
+    }
+    fastcdc_set_content_length(chunker, length);
+
+    while (cursor < length) {
+        size_t end = cursor + PIECE_SIZE < length ? cursor + PIECE_SIZE : length;
+        if (!fastcdc_cut(chunker, contents + cursor, end - cursor, &chunk)) {
+            cursor = end;
+            continue;
+        }
+
+        cursor += chunk.cutpoint;
+        if (index >= vector->count || chunk.hash != vector->chunks[index].hash || cursor - start != vector->chunks[index].length) {
+            fprintf(stderr, "unexpected chunk %zu (hash=%" PRIu64 " length=%zu) for %" PRIu32 "/%" PRIu32 "\n",
+                    index, chunk.hash, cursor - start, vector->avg_size, vector->level);
+            failed = 1;
+        }
+        start = cursor;
+        index++;
+    }
+
+    if (index != vector->count) {
+        fprintf(stderr, "found %zu chunks instead of %zu for %" PRIu32 "/%" PRIu32 "\n",
+                index, vector->count, vector->avg_size, vector->level);
+        failed = 1;
+    }
+
+    fastcdc_free(chunker);
+    return failed;
+}
+
+int main(void) {
+    FILE *file = fopen("test/fixtures/SekienAkashita.jpg", "rb");
+    uint8_t *contents;
+    size_t length, index;
+    int failed = 0;
+
+    if (file == NULL) {
+        perror("test/fixtures/SekienAkashita.jpg");
+        return 1;
+    }
+    fseek(file, 0, SEEK_END);
+    length = (size_t) ftell(file);
+    fseek(file, 0, SEEK_SET);
+    contents = malloc(length);
+    if (contents == NULL || fread(contents, 1, length, file) != length) {
+        fprintf(stderr, "failed to read the fixture\n");
+        return 1;
+    }
+    fclose(file);
+
+    if (fastcdc_validate(32, 16384, 65535, 1) != FAST_CDC_STATUS_MINIMUM_TOO_LOW
+            || fastcdc_validate(4096, 16384, 65535, 4) != FAST_CDC_STATUS_INVALID_LEVEL) {
+        fprintf(stderr, "invalid configurations were not rejected\n");
+        failed = 1;
+    }
+
+    for (index = 0; index < sizeof(VECTORS) / sizeof(VECTORS[0]); index++) {
+        failed |= check(&VECTORS[index], contents, length);
+    }
+
+    free(contents);
+    if (!failed) {
+        printf("ok\n");
+    }
+    return failed;
+}